@@ -0,0 +1,194 @@
+use crate::circuit::CircuitBreaker;
+use crate::crd::SasGenerator;
+use crate::metrics::{render_token_expiry, Metrics};
+use crate::watchdog::Watchdog;
+use kube::runtime::reflector::Store;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, instrument};
+
+/// Requests larger than this are rejected; the endpoints take no body
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Time a client gets to send its request line and headers
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Startup checks gating `/readyz`
+#[derive(Debug, Default)]
+pub struct Readiness {
+    /// The initial list of SasGenerators has been loaded into the controller cache
+    pub cache_synced: AtomicBool,
+    /// The operator-wide Azure credential could be constructed
+    pub credential_ready: AtomicBool,
+}
+
+impl Readiness {
+    /// Names of the checks that have not passed yet
+    fn pending(&self) -> Vec<&'static str> {
+        [
+            ("cache_synced", &self.cache_synced),
+            ("credential_ready", &self.credential_ready),
+        ]
+        .into_iter()
+        .filter(|(_, passed)| !passed.load(Ordering::Relaxed))
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// State served by the admin endpoints
+pub struct AdminState {
+    pub metrics: Arc<Metrics>,
+    /// Controller caches of the CRs, for metrics derived from their status
+    pub crs: Vec<Store<SasGenerator>>,
+    /// ClusterRole for the enabled features, as printed by `--print-rbac`
+    pub rbac: String,
+    pub readiness: Readiness,
+    pub watchdog: Arc<Watchdog>,
+    pub circuit: Arc<CircuitBreaker>,
+}
+
+impl AdminState {
+    fn crs(&self) -> Vec<Arc<SasGenerator>> {
+        self.crs.iter().flat_map(Store::state).collect()
+    }
+}
+
+/// One response of the admin or webhook server
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+}
+
+/// Serves `/metrics` (Prometheus), `/rbac` (required ClusterRole) and the `/healthz` and
+/// `/readyz` probes over plain HTTP/1.1.
+/// Each connection carries one request; the endpoints are scraped, not browsed.
+pub async fn serve(address: String, state: Arc<AdminState>) -> io::Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "Admin server listening");
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &state).await {
+                debug!(%peer, %e, "Admin request failed");
+            }
+        });
+    }
+}
+
+#[instrument(skip_all)]
+async fn handle(mut stream: TcpStream, state: &AdminState) -> io::Result<()> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request not received in time"))??;
+
+    let mut parts = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => route(path, state),
+        _ => Response::text("405 Method Not Allowed", "only GET is supported\n"),
+    };
+    debug!(status = response.status, "Admin request served");
+    write_response(&mut stream, response).await
+}
+
+/// Writes `response` and closes the connection
+pub async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    response: Response,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads up to the end of the request headers
+async fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let buf = read_until(stream, MAX_REQUEST_BYTES, |buf| {
+        buf.windows(4).any(|w| w == b"\r\n\r\n")
+    })
+    .await?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Reads until `done` accepts the bytes received so far or the client stops sending
+pub async fn read_until<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    limit: usize,
+    done: impl Fn(&[u8]) -> bool,
+) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !done(&buf) {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        if buf.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request too large",
+            ));
+        }
+    }
+    Ok(buf)
+}
+
+fn route(path: &str, state: &AdminState) -> Response {
+    // Query strings carry nothing the endpoints use
+    match path.split('?').next().unwrap_or_default() {
+        "/metrics" => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: state.metrics.render()
+                + &render_token_expiry(&state.crs())
+                + &state.circuit.render(),
+        },
+        "/rbac" => Response {
+            status: "200 OK",
+            content_type: "application/yaml",
+            body: state.rbac.clone(),
+        },
+        "/healthz" => match state.watchdog.stalled() {
+            None => Response::text("200 OK", "ok\n"),
+            Some(silence) => Response::text(
+                "503 Service Unavailable",
+                format!("controller stream silent for {}s\n", silence.as_secs()),
+            ),
+        },
+        "/readyz" => match state.readiness.pending().as_slice() {
+            [] => Response::text("200 OK", "ok\n"),
+            pending => Response::text(
+                "503 Service Unavailable",
+                format!("waiting for: {}\n", pending.join(", ")),
+            ),
+        },
+        _ => Response::text("404 Not Found", "not found\n"),
+    }
+}
@@ -0,0 +1,143 @@
+use crate::crd::SasGenerator;
+use crate::sas::{sas_permissions, SasTokenInfo, StorageAuth};
+use crate::signature::SasOptions;
+use crate::utils::{format_rfc3339, token_hash};
+use anyhow::{Context, Result};
+use kube::ResourceExt;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+
+/// Tracing target of audit records, so they can be filtered or routed apart from operational
+/// logs (e.g. `RUST_LOG=info,audit=info`)
+pub const AUDIT_TARGET: &str = "audit";
+
+static AUDIT_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// One issued SAS token; never carries the token itself, only fingerprints: `tokenHash` of
+/// this token, and `tokensHash` of all tokens issued with it, which matches `status.tokenHash`
+/// and the token checksum annotation of the Secrets carrying them
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuanceRecord {
+    pub timestamp: String,
+    pub namespace: String,
+    pub name: String,
+    pub account: String,
+    pub container: String,
+    pub permissions: String,
+    pub start: String,
+    pub expiry: String,
+    pub identity: String,
+    pub reason: String,
+    pub token_hash: String,
+    pub tokens_hash: String,
+}
+
+/// Opens `path` for appending audit records as JSON lines, in addition to the `audit` tracing
+/// target; call once at startup
+pub fn init_audit_log(path: &str) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log {path}"))?;
+    if AUDIT_FILE.set(Mutex::new(file)).is_err() {
+        error!("Audit log was already initialized; ignoring");
+    }
+    Ok(())
+}
+
+/// Describes the identity a token was signed with, without any secret material
+pub fn identity(sasgen: &SasGenerator, auth: &StorageAuth) -> String {
+    let spec = &sasgen.spec;
+    match auth {
+        StorageAuth::AccountKey(_) => match (
+            &spec.account_key_secret_ref,
+            &spec.connection_string_secret_ref,
+        ) {
+            (Some(key_ref), _) => format!("accountKey secret/{}", key_ref.name),
+            (None, Some(conn_ref)) => format!("connectionString secret/{}", conn_ref.name),
+            (None, None) => "accountKey".to_string(),
+        },
+        StorageAuth::Aad { provider, .. } => {
+            let kind = provider.kind();
+            if let Some(secret_ref) = &spec.credentials_secret_ref {
+                format!("{kind} secret/{}", secret_ref.name)
+            } else if let Some(client_id) = spec
+                .azure_identity
+                .as_ref()
+                .and_then(|i| i.client_id.as_deref())
+            {
+                format!("{kind} clientId={client_id}")
+            } else {
+                kind.to_string()
+            }
+        }
+    }
+}
+
+impl IssuanceRecord {
+    pub fn new(
+        sasgen: &SasGenerator,
+        auth: &StorageAuth,
+        container: &str,
+        options: &SasOptions,
+        token: &SasTokenInfo,
+        start_skew_seconds: i64,
+        reason: &str,
+    ) -> Self {
+        let start = token.generated - Duration::seconds(start_skew_seconds);
+        Self {
+            timestamp: format_rfc3339(OffsetDateTime::now_utc()),
+            namespace: sasgen.namespace().unwrap_or_default(),
+            name: sasgen.name_any(),
+            account: sasgen.spec.storage_account.clone(),
+            container: container.to_string(),
+            permissions: sas_permissions(options),
+            start: format_rfc3339(start),
+            expiry: format_rfc3339(token.expiry),
+            identity: identity(sasgen, auth),
+            reason: reason.to_string(),
+            token_hash: token_hash(&token.token),
+            tokens_hash: String::new(),
+        }
+    }
+}
+
+/// Emits the record on the `audit` target and, when configured, appends it to the audit log
+pub fn record_issuance(record: &IssuanceRecord) {
+    info!(
+        target: AUDIT_TARGET,
+        audit = true,
+        cr_namespace = %record.namespace,
+        cr_name = %record.name,
+        account = %record.account,
+        container = %record.container,
+        permissions = %record.permissions,
+        start = %record.start,
+        expiry = %record.expiry,
+        identity = %record.identity,
+        reason = %record.reason,
+        token_hash = %record.token_hash,
+        tokens_hash = %record.tokens_hash,
+        "SAS token issued"
+    );
+    let Some(file) = AUDIT_FILE.get() else {
+        return;
+    };
+    let line = match serde_json::to_string(record) {
+        Ok(line) => line,
+        Err(e) => {
+            error!(error = %e, "Failed to serialize audit record");
+            return;
+        }
+    };
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = writeln!(file, "{line}").and_then(|()| file.flush()) {
+        error!(error = %e, "Failed to write audit record");
+    }
+}
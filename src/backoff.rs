@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio_retry::strategy::jitter;
+
+/// Consecutive failed reconciles per CR, driving an exponential error backoff that resets
+/// after the next success or the deletion of the CR
+#[derive(Debug, Default)]
+pub struct ErrorBackoff {
+    streaks: Mutex<HashMap<String, u32>>,
+}
+
+impl ErrorBackoff {
+    /// Counts another failure of `key` and returns `base` doubled per earlier failure, capped
+    /// at `cap`. Half of the delay is jittered so CRs that failed together spread out.
+    pub fn next_delay(&self, key: &str, base: Duration, cap: Duration) -> Duration {
+        let mut streaks = self.streaks.lock().unwrap();
+        let streak = streaks.entry(key.to_string()).or_default();
+        let delay = base.saturating_mul(2u32.saturating_pow(*streak)).min(cap);
+        *streak = streak.saturating_add(1);
+        let half = delay / 2;
+        half + jitter(half)
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.streaks.lock().unwrap().remove(key);
+    }
+}
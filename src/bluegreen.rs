@@ -0,0 +1,75 @@
+use crate::crd::{ContextData, SasGenerator, SecretDistribution, SecretTarget};
+use crate::distribute::data_hash;
+use crate::reconcile::ReconcileError;
+use crate::secret::read_secret_data;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{Patch, PatchParams};
+use kube::{Api, ResourceExt};
+use serde_json::{Map, Value};
+use tracing::{info, instrument};
+
+/// Tokens currently published in the target Secret, which become the previous tokens of a
+/// blue/green rotation
+pub async fn current_tokens(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    target: &SecretTarget,
+) -> Result<Vec<(String, String)>, ReconcileError> {
+    let Some(data) = read_secret_data(ctx, &target.namespace, &target.name).await? else {
+        return Ok(Vec::new());
+    };
+    let keys = sasgen.secret_keys();
+    let single = target.containers.len() == 1;
+    Ok(target
+        .containers
+        .iter()
+        .filter_map(|container| {
+            data.get(&keys.sas_token_for(container, single))
+                .map(|token| {
+                    (
+                        container.clone(),
+                        String::from_utf8_lossy(token).into_owned(),
+                    )
+                })
+        })
+        .collect())
+}
+
+/// Removes the previous tokens once the overlap window has passed, and records the new data
+/// of each Secret in `distribution` so the removal is not taken for drift
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn drop_previous_tokens(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    targets: &[SecretTarget],
+    distribution: &mut [SecretDistribution],
+) -> Result<(), ReconcileError> {
+    let keys = sasgen.secret_keys();
+    for target in targets {
+        let single = target.containers.len() == 1;
+        // A null value in a merge patch deletes the key and is a no-op when it is absent
+        let removed: Vec<String> = target
+            .containers
+            .iter()
+            .map(|c| keys.previous_sas_token_for(c, single))
+            .collect();
+        let patch = serde_json::json!({
+            "data": removed.iter().map(|k| (k.clone(), Value::Null)).collect::<Map<_, _>>()
+        });
+
+        let api: Api<Secret> = Api::namespaced(ctx.client.clone(), &target.namespace);
+        let patched = api
+            .patch(&target.name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+        ctx.written.remove_keys(target, &removed);
+        if let Some(state) = distribution
+            .iter_mut()
+            .find(|d| d.namespace == target.namespace && d.secret == target.name)
+        {
+            state.resource_version = patched.resource_version();
+            state.data_hash = Some(data_hash(&patched));
+        }
+        info!(secret = %target.name, "Overlap window ended; removed previous tokens");
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const CIRCUIT_OPEN: &str = "sas_operator_azure_circuit_open";
+
+/// Consecutive failures of one storage account and, once they reach the threshold, until when
+/// calls to it are refused
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Per-account circuit breaker: after `threshold` consecutive Azure failures, CRs of that
+/// account stop calling Azure for `cooldown` while other accounts are served normally. After
+/// the cool-down one call is let through as a probe while the others keep waiting; a single
+/// further failure reopens the circuit, a success closes it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    accounts: Mutex<BTreeMap<String, Breaker>>,
+}
+
+impl CircuitBreaker {
+    /// `threshold` 0 disables the breaker
+    pub fn new(threshold: u32, cooldown_seconds: u64) -> Self {
+        Self {
+            threshold,
+            cooldown: Duration::from_secs(cooldown_seconds),
+            accounts: Mutex::default(),
+        }
+    }
+
+    /// Time left before calls to `account` are allowed again, if its circuit is open. The
+    /// first caller after the cool-down gets `None` and becomes the probe; the circuit stays
+    /// open for the others until its result is recorded, or for another cool-down if it never is.
+    pub fn open_for(&self, account: &str) -> Option<Duration> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let breaker = accounts.get_mut(account)?;
+        let now = Instant::now();
+        if let Some(left) = breaker
+            .open_until?
+            .checked_duration_since(now)
+            .filter(|left| !left.is_zero())
+        {
+            return Some(left);
+        }
+        info!(%account, "Cool-down over; letting one probe call through");
+        breaker.open_until = Some(now + self.cooldown);
+        None
+    }
+
+    pub fn record_success(&self, account: &str) {
+        if let Some(breaker) = self.accounts.lock().unwrap().remove(account) {
+            if breaker.open_until.is_some() {
+                info!(%account, "Azure calls succeed again; closing the circuit");
+            }
+        }
+    }
+
+    pub fn record_failure(&self, account: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut accounts = self.accounts.lock().unwrap();
+        let breaker = accounts.entry(account.to_string()).or_default();
+        breaker.failures = breaker.failures.saturating_add(1);
+        if breaker.failures >= self.threshold {
+            warn!(
+                %account,
+                failures = breaker.failures,
+                cooldown = ?self.cooldown,
+                "Repeated Azure failures; opening the circuit"
+            );
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Drops the state of `account` once no CR targets it any more
+    pub fn forget(&self, account: &str) {
+        self.accounts.lock().unwrap().remove(account);
+    }
+
+    /// Renders whether each failing account's circuit is open, in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = format!(
+            "# HELP {CIRCUIT_OPEN} Whether Azure calls for the storage account are suspended\n\
+             # TYPE {CIRCUIT_OPEN} gauge\n"
+        );
+        let now = Instant::now();
+        for (account, breaker) in self.accounts.lock().unwrap().iter() {
+            let open = breaker.open_until.is_some_and(|until| until > now);
+            let _ = writeln!(
+                out,
+                "{CIRCUIT_OPEN}{{account=\"{}\"}} {}",
+                crate::metrics::escape(account),
+                u8::from(open)
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lets the cool-down of `account` run out
+    fn cool_down(circuit: &CircuitBreaker, account: &str) {
+        let mut accounts = circuit.accounts.lock().unwrap();
+        accounts.get_mut(account).unwrap().open_until = Some(Instant::now());
+    }
+
+    #[test]
+    fn opens_after_threshold_failures() {
+        let circuit = CircuitBreaker::new(2, 60);
+        circuit.record_failure("backupacct");
+        assert_eq!(circuit.open_for("backupacct"), None);
+        circuit.record_failure("backupacct");
+        assert!(circuit.open_for("backupacct").is_some());
+        assert_eq!(circuit.open_for("otheracct"), None);
+    }
+
+    #[test]
+    fn lets_a_single_probe_through_after_the_cool_down() {
+        let circuit = CircuitBreaker::new(1, 60);
+        circuit.record_failure("backupacct");
+        cool_down(&circuit, "backupacct");
+        assert_eq!(circuit.open_for("backupacct"), None);
+        assert!(circuit.open_for("backupacct").is_some());
+
+        circuit.record_failure("backupacct");
+        assert!(circuit.open_for("backupacct").is_some());
+        cool_down(&circuit, "backupacct");
+        assert_eq!(circuit.open_for("backupacct"), None);
+        circuit.record_success("backupacct");
+        assert_eq!(circuit.open_for("backupacct"), None);
+        assert_eq!(circuit.open_for("backupacct"), None);
+    }
+
+    #[test]
+    fn forgets_accounts() {
+        let circuit = CircuitBreaker::new(1, 60);
+        circuit.record_failure("backupacct");
+        circuit.forget("backupacct");
+        assert_eq!(circuit.open_for("backupacct"), None);
+        assert!(!circuit.render().contains("backupacct"));
+    }
+}
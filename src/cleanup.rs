@@ -0,0 +1,232 @@
+use crate::crd::{ContextData, DeletionPolicy, SasGenerator, SecretDistribution, SecretTarget};
+use crate::reconcile::ReconcileError;
+use crate::remote::delete_remote;
+use crate::versioned::VERSIONED_FROM_LABEL;
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams};
+use kube::{Api, Resource, ResourceExt};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tracing::{info, instrument, warn};
+
+/// Finalizer holding the CR until Secrets outside its namespace or cluster are deleted
+pub const CLEANUP_FINALIZER: &str = "sas.azure.com/cleanup";
+
+/// Marks Secrets without an owner reference (other namespaces, or `ownerReference: false`)
+/// with their CR
+pub const OWNER_ANNOTATION: &str = "sas.azure.com/owner";
+
+/// `namespace/name` of the CR, as stored in `OWNER_ANNOTATION`
+pub fn owner_key(sasgen: &SasGenerator) -> String {
+    format!(
+        "{}/{}",
+        sasgen.namespace().unwrap_or_default(),
+        sasgen.name_any()
+    )
+}
+
+/// Whether the Secret was written for this CR: it carries the CR's controller owner reference
+/// or its `OWNER_ANNOTATION`. Other Secrets are never overwritten or deleted.
+pub fn owned_by(sasgen: &SasGenerator, secret: &Secret) -> bool {
+    let controlled = secret.owner_references().iter().any(|owner| {
+        owner.controller == Some(true)
+            && owner.kind == SasGenerator::kind(&())
+            && owner.name == sasgen.name_any()
+            && sasgen.uid().is_none_or(|uid| owner.uid == uid)
+    });
+    controlled || secret.annotations().get(OWNER_ANNOTATION) == Some(&owner_key(sasgen))
+}
+
+/// Owner references for a Secret in `namespace`. Owner references cannot cross namespaces and
+/// may be disabled, so such Secrets are marked with `OWNER_ANNOTATION` instead and cleaned up
+/// explicitly.
+pub fn secret_owner(
+    sasgen: &SasGenerator,
+    namespace: &str,
+    annotations: &mut BTreeMap<String, String>,
+) -> Option<Vec<OwnerReference>> {
+    if needs_cleanup(sasgen, namespace) {
+        annotations.insert(OWNER_ANNOTATION.into(), owner_key(sasgen));
+        None
+    } else {
+        Some(vec![sasgen.controller_owner_ref(&()).unwrap()])
+    }
+}
+
+/// Secrets outside the CR namespace, or written without an owner reference, are not garbage
+/// collected
+pub fn needs_cleanup(sasgen: &SasGenerator, namespace: &str) -> bool {
+    !sasgen.owner_reference() || sasgen.namespace().as_deref() != Some(namespace)
+}
+
+async fn patch_finalizers(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    finalizers: Vec<String>,
+) -> Result<(), ReconcileError> {
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let api: Api<SasGenerator> = Api::namespaced(ctx.client.clone(), &ns);
+    let patch = json!({
+        "metadata": {
+            "finalizers": finalizers,
+            "resourceVersion": sasgen.resource_version(),
+        }
+    });
+    api.patch(
+        &sasgen.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Adds the cleanup finalizer before the first Secret the garbage collector will not delete
+/// is written, unless such Secrets are retained
+pub async fn ensure_finalizer(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    targets: &[SecretTarget],
+) -> Result<(), ReconcileError> {
+    let remote = sasgen
+        .spec
+        .cluster_refs
+        .as_ref()
+        .is_some_and(|c| !c.is_empty());
+    let needed = sasgen.deletion_policy() == DeletionPolicy::Delete
+        && (remote || targets.iter().any(|t| needs_cleanup(sasgen, &t.namespace)));
+    if !needed || sasgen.finalizers().iter().any(|f| f == CLEANUP_FINALIZER) {
+        return Ok(());
+    }
+    let mut finalizers = sasgen.finalizers().to_vec();
+    finalizers.push(CLEANUP_FINALIZER.into());
+    patch_finalizers(sasgen, ctx, finalizers).await?;
+    info!("Added cleanup finalizer for Secrets without an owner reference");
+    Ok(())
+}
+
+/// Deletes a Secret written for this CR outside its namespace, with its immutable versions.
+/// Secrets not written by it (no matching owner annotation) are left alone.
+async fn delete_foreign_secret(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    namespace: &str,
+    name: &str,
+) -> Result<(), ReconcileError> {
+    let api: Api<Secret> = Api::namespaced(ctx.client.clone(), namespace);
+    let versions = api
+        .list(&ListParams::default().labels(&format!("{VERSIONED_FROM_LABEL}={name}")))
+        .await?
+        .items;
+    let secrets = api.get_opt(name).await?.into_iter().chain(versions);
+
+    for secret in secrets {
+        let secret_name = secret.name_any();
+        if secret.annotations().get(OWNER_ANNOTATION) != Some(&owner_key(sasgen)) {
+            warn!(%namespace, %secret_name, "Secret is not owned by this CR; not deleting it");
+            continue;
+        }
+        api.delete(&secret_name, &DeleteParams::default()).await?;
+        info!(%namespace, %secret_name, "Deleted Secret without an owner reference");
+    }
+    Ok(())
+}
+
+/// Deletes Secrets of the previous rollout that are no longer targets, e.g. after
+/// the target namespace changed. Secrets with an owner reference are left to the garbage collector.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn delete_stale_secrets(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    previous: &[SecretDistribution],
+    targets: &[SecretTarget],
+) -> Result<(), ReconcileError> {
+    for old in previous {
+        let still_target = targets
+            .iter()
+            .any(|t| t.namespace == old.namespace && t.name == old.secret);
+        if !still_target && needs_cleanup(sasgen, &old.namespace) {
+            delete_foreign_secret(sasgen, ctx, &old.namespace, &old.secret).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the CR's Secrets the garbage collector will not delete, including the copies in
+/// `clusterRefs`, unless they are retained, and releases the finalizer
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn finalize(sasgen: &SasGenerator, ctx: &ContextData) -> Result<(), ReconcileError> {
+    if !sasgen.finalizers().iter().any(|f| f == CLEANUP_FINALIZER) {
+        return Ok(());
+    }
+    let distribution = sasgen
+        .status
+        .as_ref()
+        .map(|s| s.distribution.as_slice())
+        .unwrap_or_default();
+    if sasgen.deletion_policy() == DeletionPolicy::Retain {
+        info!("Deletion policy is Retain; leaving Secrets in place");
+    }
+    for written in distribution {
+        if sasgen.deletion_policy() == DeletionPolicy::Delete
+            && needs_cleanup(sasgen, &written.namespace)
+        {
+            delete_foreign_secret(sasgen, ctx, &written.namespace, &written.secret).await?;
+        }
+    }
+    if sasgen.deletion_policy() == DeletionPolicy::Delete {
+        delete_remote(sasgen, ctx, distribution).await;
+    }
+
+    let finalizers = sasgen
+        .finalizers()
+        .iter()
+        .filter(|f| *f != CLEANUP_FINALIZER)
+        .cloned()
+        .collect();
+    patch_finalizers(sasgen, ctx, finalizers).await?;
+    info!("Cleanup finished; released finalizer");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sasgen() -> SasGenerator {
+        serde_json::from_value(json!({
+            "apiVersion": "sas.azure.com/v1alpha1",
+            "kind": "SasGenerator",
+            "metadata": { "name": "backup", "namespace": "apps", "uid": "1234" },
+            "spec": { "storageAccount": "backupacct", "containerName": "data" },
+        }))
+        .unwrap()
+    }
+
+    fn secret(owner: Option<OwnerReference>, annotation: Option<&str>) -> Secret {
+        let mut secret = Secret::default();
+        secret.metadata.owner_references = owner.map(|o| vec![o]);
+        secret.metadata.annotations =
+            annotation.map(|a| BTreeMap::from([(OWNER_ANNOTATION.into(), a.into())]));
+        secret
+    }
+
+    #[test]
+    fn secrets_written_for_the_cr_are_owned() {
+        let sasgen = sasgen();
+        let owner = sasgen.controller_owner_ref(&()).unwrap();
+        assert!(owned_by(&sasgen, &secret(Some(owner), None)));
+        assert!(owned_by(&sasgen, &secret(None, Some("apps/backup"))));
+    }
+
+    #[test]
+    fn foreign_secrets_are_not_owned() {
+        let sasgen = sasgen();
+        assert!(!owned_by(&sasgen, &secret(None, None)));
+        assert!(!owned_by(&sasgen, &secret(None, Some("other/backup"))));
+        let mut recreated = sasgen.controller_owner_ref(&()).unwrap();
+        recreated.uid = "5678".into();
+        assert!(!owned_by(&sasgen, &secret(Some(recreated), None)));
+    }
+}
@@ -0,0 +1,633 @@
+use crate::config::Config;
+use crate::conversion::{convert, V1ALPHA1};
+use crate::crd::{crd, crds, render_crds, served_versions, SasGenerator};
+use crate::crdinstall::install_crds;
+use crate::credentials::provider_from_env;
+use crate::http::{init_proxy, new_http_client};
+use crate::manifests::{render, ManifestOptions};
+use crate::rbac::{
+    cluster_role, cluster_role_binding, CLUSTER_ROLE_NAME, DEFAULT_NAMESPACE, SERVICE_ACCOUNT_NAME,
+};
+use crate::reconcile::{azure_status, ROTATE_ANNOTATION};
+use crate::sas::{
+    account_key_from_connection_string, blob_endpoint, check_container_read, check_storage_token,
+    check_user_delegation_key, generate_container_sas, parse_permissions, StorageAuth,
+};
+use crate::schema;
+use crate::status::CONDITION_READY;
+use crate::utils::{format_rfc3339, format_time};
+use crate::validate::validate_spec;
+use azure_core::auth::Secret;
+use azure_identity::TokenCredentialOptions;
+use k8s_openapi::api::core::v1::{Namespace, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::api::{Api, ObjectMeta, Patch, PatchParams};
+use kube::{Client, ResourceExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Subcommands that run the operator's logic from a workstation or CI job, e.g.
+/// `sas generate --account X --container Y --ttl 24h`
+pub const COMMANDS: &[&str] = &[
+    "generate",
+    "validate",
+    "doctor",
+    "rotate",
+    "status",
+    "crd",
+    "install",
+    "manifests",
+];
+
+/// Flags of a subcommand, given as `--name value`, `--name=value`, `-n value` or a bare
+/// `--name`; a lone `-` is a value (stdin). Other arguments are positional. Repeated flags
+/// keep every value.
+pub struct Flags {
+    values: BTreeMap<String, Vec<String>>,
+    positional: Vec<String>,
+}
+
+impl Flags {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut flags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut positional = Vec::new();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')) else {
+                positional.push(arg);
+                continue;
+            };
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => match args.next_if(|next| next == "-" || !next.starts_with('-')) {
+                    Some(value) => (name.to_string(), value),
+                    None => (name.to_string(), "true".to_string()),
+                },
+            };
+            flags.entry(name).or_default().push(value);
+        }
+        Ok(Self {
+            values: flags,
+            positional,
+        })
+    }
+
+    /// Last value of the flag
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name)?.last().map(String::as_str)
+    }
+
+    /// Every value of a repeatable flag, in order
+    pub fn all(&self, name: &str) -> &[String] {
+        self.values.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The single positional argument, e.g. the CR of `rotate <namespace>/<name>`
+    pub fn argument(&self, what: &str) -> Result<&str, String> {
+        match self.positional.as_slice() {
+            [argument] => Ok(argument),
+            [] => Err(format!("{what} is required")),
+            [_, extra, ..] => Err(format!("unexpected argument '{extra}'")),
+        }
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        self.get(name)
+            .ok_or_else(|| format!("--{name} is required"))
+    }
+}
+
+/// Runs `command` with the flags that follow it on the command line
+pub async fn run(command: &str, args: Vec<String>, config: Config) -> CliResult {
+    let flags = Flags::parse(args)?;
+    match command {
+        "generate" => generate(&flags, &config).await,
+        "validate" => validate(&flags, &config),
+        "doctor" => doctor(&flags, &config).await,
+        "rotate" => rotate(&flags).await,
+        "status" => status(&flags).await,
+        "crd" => crd_command(&flags, &config),
+        "install" => install(&flags, &config).await,
+        "manifests" => manifests(&flags),
+        _ => Err(format!(
+            "unknown command '{command}'; expected one of {}",
+            COMMANDS.join(", ")
+        )
+        .into()),
+    }
+}
+
+/// Parses a Go-style duration such as `24h` into whole hours, as SAS lifetimes are set in hours
+fn parse_hours(flag: &str, value: &str) -> Result<i64, String> {
+    let duration: kube::core::Duration = value
+        .parse()
+        .map_err(|e| format!("--{flag} '{value}' is not a duration: {e}"))?;
+    let seconds = std::time::Duration::from(duration).as_secs();
+    if seconds == 0 || seconds % 3600 != 0 {
+        return Err(format!(
+            "--{flag} must be a positive number of hours, e.g. 24h"
+        ));
+    }
+    Ok((seconds / 3600) as i64)
+}
+
+/// CR built from the `--account`, `--container`, `--cloud`, `--endpoint-suffix` and
+/// `--blob-endpoint` flags, so commands apply the same rules as the controller, and the
+/// account key from `AZURE_STORAGE_KEY` or `AZURE_STORAGE_CONNECTION_STRING` if set
+fn target(flags: &Flags) -> Result<(SasGenerator, Option<Secret>), Box<dyn std::error::Error>> {
+    let account = flags.required("account")?;
+    let mut spec = json!({
+        "storageAccount": account,
+        "containerName": flags.required("container")?,
+    });
+    for (flag, field) in [
+        ("cloud", "cloud"),
+        ("endpoint-suffix", "endpointSuffix"),
+        ("blob-endpoint", "blobEndpoint"),
+    ] {
+        if let Some(value) = flags.get(flag) {
+            spec[field] = json!(value);
+        }
+    }
+    let account_key = match (
+        std::env::var("AZURE_STORAGE_KEY"),
+        std::env::var("AZURE_STORAGE_CONNECTION_STRING"),
+    ) {
+        (Ok(key), _) => Some(Secret::new(key)),
+        (_, Ok(value)) => Some(account_key_from_connection_string(&value, account)?),
+        _ => None,
+    };
+    if account_key.is_some() {
+        // Stands in for the key Secret, so the longer TTLs of service SAS validate
+        spec["accountKeySecretRef"] = json!({ "name": "AZURE_STORAGE_KEY" });
+    }
+    let sasgen = serde_json::from_value(json!({
+        "apiVersion": "sas.azure.com/v1alpha1",
+        "kind": "SasGenerator",
+        "metadata": { "name": "cli" },
+        "spec": spec,
+    }))?;
+    Ok((sasgen, account_key))
+}
+
+/// Signs with the account key if one was given, else with the operator's Azure AD
+/// credential chain (`AZURE_*` variables, Azure CLI, ...)
+fn storage_auth(
+    sasgen: &SasGenerator,
+    account_key: Option<Secret>,
+) -> Result<StorageAuth, Box<dyn std::error::Error>> {
+    if let Some(key) = account_key {
+        return Ok(StorageAuth::AccountKey(key));
+    }
+    let mut options = TokenCredentialOptions::from(new_http_client());
+    if let Some(cloud) = sasgen.spec.cloud {
+        options.set_authority_host(cloud.authority_host().to_string());
+    }
+    Ok(StorageAuth::Aad {
+        provider: provider_from_env()?,
+        options,
+    })
+}
+
+/// `generate`: signs one container token like the controller would and prints it
+async fn generate(flags: &Flags, config: &Config) -> CliResult {
+    let container = flags.required("container")?;
+    let ttl_hours = match flags.get("ttl") {
+        Some(ttl) => parse_hours("ttl", ttl)?,
+        None => config.default_ttl_hours(),
+    };
+    let (sasgen, account_key) = target(flags)?;
+    // One-shot tokens are never renewed
+    validate_spec(&sasgen, ttl_hours, 0)?;
+
+    let mut options = sasgen.sas_options();
+    if let Some(permissions) = flags.get("permissions") {
+        let permissions = parse_permissions(permissions)
+            .map_err(|c| format!("--permissions has unknown letter '{c}'"))?;
+        options.permissions = Some(permissions.to_string());
+    }
+    let location = sasgen.cloud_location();
+    init_proxy(config.proxy.clone());
+    let auth = storage_auth(&sasgen, account_key)?;
+
+    let info = generate_container_sas(
+        &auth,
+        &location,
+        container,
+        ttl_hours,
+        OffsetDateTime::now_utc(),
+        config.start_skew_seconds,
+        &options,
+    )
+    .await?;
+    let url = format!("{}/{container}?{}", blob_endpoint(&location), info.token);
+    match flags.get("output").unwrap_or("token") {
+        "token" => println!("{}", info.token),
+        "url" => println!("{url}"),
+        "json" => println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "token": info.token,
+                "url": url,
+                "expiry": format_rfc3339(info.expiry),
+            }))?
+        ),
+        other => return Err(format!("--output '{other}' must be token, url or json").into()),
+    }
+    Ok(())
+}
+
+/// `validate -f <file>`: checks every SasGenerator of a manifest (`-` reads stdin) against
+/// the CRD schema and the operator's spec rules, so CI catches what the API server or the
+/// controller would reject
+fn validate(flags: &Flags, config: &Config) -> CliResult {
+    let path = flags
+        .get("f")
+        .or(flags.get("filename"))
+        .ok_or("-f <file> is required")?;
+    let manifest = match path {
+        "-" => std::io::read_to_string(std::io::stdin())?,
+        path => std::fs::read_to_string(path)?,
+    };
+    let crd = crd()?;
+    let (mut checked, mut failed) = (0, 0);
+    for document in serde_yaml::Deserializer::from_str(&manifest) {
+        let value = Value::deserialize(document)?;
+        if value["kind"] != "SasGenerator" {
+            continue;
+        }
+        checked += 1;
+        let name = format!(
+            "{}/{}",
+            value["metadata"]["namespace"].as_str().unwrap_or("default"),
+            value["metadata"]["name"].as_str().unwrap_or("<unnamed>")
+        );
+        let errors = validate_document(&value, &crd, config);
+        if errors.is_empty() {
+            println!("{name}: valid");
+        } else {
+            failed += 1;
+            for error in errors {
+                println!("{name}: {error}");
+            }
+        }
+    }
+    match (checked, failed) {
+        (0, _) => Err(format!("no SasGenerator found in {path}").into()),
+        (_, 0) => Ok(()),
+        _ => Err(format!("{failed} of {checked} SasGenerators are invalid").into()),
+    }
+}
+
+fn validate_document(
+    value: &Value,
+    crd: &CustomResourceDefinition,
+    config: &Config,
+) -> Vec<String> {
+    let api_version = value["apiVersion"].as_str().unwrap_or_default();
+    let Some(version) = crd
+        .spec
+        .versions
+        .iter()
+        .find(|v| format!("{}/{}", crd.spec.group, v.name) == api_version)
+    else {
+        return vec![format!("apiVersion: unknown version '{api_version}'")];
+    };
+    let Some(spec) = value.get("spec") else {
+        return vec!["spec: required".to_string()];
+    };
+    let spec_schema = version
+        .schema
+        .as_ref()
+        .and_then(|s| s.open_api_v3_schema.as_ref())
+        .and_then(|s| s.properties.as_ref())
+        .and_then(|properties| properties.get("spec"));
+    if let Some(spec_schema) = spec_schema {
+        let errors = schema::check(spec, spec_schema, "spec");
+        if !errors.is_empty() {
+            return errors;
+        }
+    }
+
+    // The spec rules are written against the storage version
+    let sasgen: SasGenerator = match convert(value.clone(), V1ALPHA1)
+        .and_then(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+    {
+        Ok(sasgen) => sasgen,
+        Err(e) => return vec![e],
+    };
+    let ttl_hours = sasgen
+        .spec
+        .sas_ttl_hours
+        .unwrap_or(config.default_ttl_hours());
+    let renewal_hours = sasgen
+        .spec
+        .sas_renewal_hours
+        .unwrap_or(config.default_renewal_hours());
+    match validate_spec(&sasgen, ttl_hours, renewal_hours) {
+        Ok(()) => vec![],
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// `doctor --account X --container Y [--list]`: checks that the configured credential can
+/// do what the controller needs on the account, printing the role assignment to add for
+/// each failed check
+async fn doctor(flags: &Flags, config: &Config) -> CliResult {
+    let (sasgen, account_key) = target(flags)?;
+    let account = sasgen.spec.storage_account.clone();
+    let container = sasgen.container_names().concat();
+    let location = sasgen.cloud_location();
+    init_proxy(config.proxy.clone());
+    let signs_with_key = account_key.is_some();
+    let auth = storage_auth(&sasgen, account_key);
+    let mut failed = 0;
+
+    let auth = match auth {
+        Ok(auth) => auth,
+        Err(e) => {
+            report("credential", Err(format!("{e}")), CREDENTIAL_HINT);
+            return Err("1 check failed".into());
+        }
+    };
+    match &auth {
+        StorageAuth::AccountKey(_) => {
+            println!("ok    credential: signing with the account key; no Azure AD role is needed");
+        }
+        StorageAuth::Aad { provider, options } => {
+            let result = check_storage_token(provider.as_ref(), options).await;
+            failed += report(
+                &format!("credential ({})", provider.kind()),
+                result.map_err(|e| format!("{e:#}")),
+                CREDENTIAL_HINT,
+            );
+            let result = check_user_delegation_key(&auth, &location).await;
+            let hint = match result.as_ref().err().and_then(azure_status) {
+                Some(403) => format!(
+                    "assign the 'Storage Blob Delegator' role (or a 'Storage Blob Data' role) on \
+                     storage account '{account}' to this identity"
+                ),
+                Some(404) | None => format!(
+                    "check that storage account '{account}' exists and is reachable at {}",
+                    blob_endpoint(&location)
+                ),
+                Some(_) => String::new(),
+            };
+            failed += report(
+                "user delegation key",
+                result.map_err(|e| format!("{e:#}")),
+                &hint,
+            );
+        }
+    }
+
+    if flags.get("list").is_some() {
+        let result = check_container_read(&auth, &location, &container).await;
+        let hint = match result.as_ref().err().and_then(azure_status) {
+            Some(403) if signs_with_key => {
+                "the account key is wrong or shared key access is disabled on the account".into()
+            }
+            Some(403) => format!(
+                "assign the 'Storage Blob Data Reader' role on container '{container}' (or the \
+                 account) to this identity"
+            ),
+            Some(404) => format!("container '{container}' does not exist in '{account}'"),
+            _ => String::new(),
+        };
+        failed += report(
+            &format!("list container {container}"),
+            result.map_err(|e| format!("{e:#}")),
+            &hint,
+        );
+    }
+
+    match failed {
+        0 => Ok(()),
+        1 => Err("1 check failed".into()),
+        n => Err(format!("{n} checks failed").into()),
+    }
+}
+
+const CREDENTIAL_HINT: &str = "set AZURE_TENANT_ID and AZURE_CLIENT_ID with a client secret, \
+    certificate or federated token, or log in with `az login`";
+
+/// Prints the outcome of one `doctor` check; returns 1 if it failed
+fn report(check: &str, result: Result<(), String>, hint: &str) -> usize {
+    match result {
+        Ok(()) => {
+            println!("ok    {check}");
+            0
+        }
+        Err(e) => {
+            println!("FAIL  {check}: {e}");
+            if !hint.is_empty() {
+                println!("      hint: {hint}");
+            }
+            1
+        }
+    }
+}
+
+/// `rotate <namespace>/<name>`: asks the controller to issue new tokens for the CR right
+/// away, e.g. after a suspected leak, by stamping `ROTATE_ANNOTATION` with the current time
+async fn rotate(flags: &Flags) -> CliResult {
+    let target = flags.argument("<namespace>/<name>")?;
+    let (namespace, name) = target
+        .split_once('/')
+        .ok_or_else(|| format!("'{target}' must be <namespace>/<name>"))?;
+    let requested = format_rfc3339(OffsetDateTime::now_utc());
+    let client = Client::try_default().await?;
+    Api::<SasGenerator>::namespaced(client, namespace)
+        .patch(
+            name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({
+                "metadata": { "annotations": { ROTATE_ANNOTATION: requested } }
+            })),
+        )
+        .await?;
+    println!("Rotation of {namespace}/{name} requested at {requested}");
+    Ok(())
+}
+
+/// `status [-n <namespace>]`: one line per SasGenerator with where its tokens go, when they
+/// expire and renew, and whether the CR is Ready
+async fn status(flags: &Flags) -> CliResult {
+    let client = Client::try_default().await?;
+    let api = match flags.get("n").or(flags.get("namespace")) {
+        Some(namespace) => Api::<SasGenerator>::namespaced(client, namespace),
+        None => Api::<SasGenerator>::all(client),
+    };
+    let mut rows = vec![[
+        "NAMESPACE",
+        "NAME",
+        "ACCOUNT",
+        "CONTAINER",
+        "TARGET SECRET",
+        "EXPIRY",
+        "NEXT RENEWAL",
+        "READY",
+    ]
+    .map(String::from)];
+    for sasgen in api.list(&Default::default()).await? {
+        let status = sasgen.status.clone().unwrap_or_default();
+        let containers = match sasgen.container_names() {
+            names if names.is_empty() => "<selector>".to_string(),
+            names => names.join(","),
+        };
+        let target = status
+            .target_secret
+            .clone()
+            .unwrap_or_else(|| status.target_secrets.join(","));
+        let ready = status
+            .conditions
+            .iter()
+            .find(|c| c.type_ == CONDITION_READY)
+            .map(|c| match &c.reason {
+                Some(reason) if c.status != "True" => format!("{} ({reason})", c.status),
+                _ => c.status.clone(),
+            });
+        rows.push([
+            sasgen.namespace().unwrap_or_default(),
+            sasgen.name_any(),
+            sasgen.spec.storage_account.clone(),
+            containers,
+            target,
+            status.expiry.as_ref().map(format_time).unwrap_or_default(),
+            status.next_renewal_time.unwrap_or_default(),
+            ready.unwrap_or_else(|| "Unknown".into()),
+        ]);
+    }
+    if rows.len() == 1 {
+        println!("No SasGenerators found");
+        return Ok(());
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", line.join("   ").trim_end());
+    }
+    Ok(())
+}
+
+/// `crd [-o <file>] [--format yaml|json] [--versions v1alpha1,v1beta1]`: prints the CRDs to
+/// stdout, or writes them to a file, for pipelines that apply or template them. Serves
+/// v1beta1 only with `WEBHOOK_ENABLED` unless `--versions` says otherwise.
+fn crd_command(flags: &Flags, config: &Config) -> CliResult {
+    let versions: Vec<String> = match flags.get("versions") {
+        Some(versions) => versions
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(String::from)
+            .collect(),
+        None => served_versions(config),
+    };
+    let json = match flags.get("format").unwrap_or("yaml") {
+        "yaml" => false,
+        "json" => true,
+        other => return Err(format!("--format '{other}' must be yaml or json").into()),
+    };
+    let rendered = render_crds(&crds(&versions)?, json)?;
+    match flags.get("o").or(flags.get("output")) {
+        Some("-") | None => print!("{rendered}"),
+        Some(path) => {
+            std::fs::write(path, rendered)?;
+            eprintln!("Wrote the CRDs to {path}");
+        }
+    }
+    Ok(())
+}
+
+/// `install [--namespace <ns>] [--service-account <name>]`: applies the CRDs, the
+/// operator's namespace and ServiceAccount, and the ClusterRole for the features enabled in
+/// the environment, bound to that ServiceAccount. Meant for bootstrapping dev clusters.
+async fn install(flags: &Flags, config: &Config) -> CliResult {
+    let namespace = flags.get("namespace").unwrap_or(DEFAULT_NAMESPACE);
+    let service_account = flags.get("service-account").unwrap_or(SERVICE_ACCOUNT_NAME);
+    let client = Client::try_default().await?;
+    install_crds(&client, config).await?;
+
+    let params = PatchParams::apply("sas-operator").force();
+    let object = Namespace {
+        metadata: ObjectMeta {
+            name: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    Api::<Namespace>::all(client.clone())
+        .patch(namespace, &params, &Patch::Apply(&object))
+        .await?;
+    let object = ServiceAccount {
+        metadata: ObjectMeta {
+            name: Some(service_account.to_string()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    Api::<ServiceAccount>::namespaced(client.clone(), namespace)
+        .patch(service_account, &params, &Patch::Apply(&object))
+        .await?;
+    Api::<ClusterRole>::all(client.clone())
+        .patch(
+            CLUSTER_ROLE_NAME,
+            &params,
+            &Patch::Apply(cluster_role(config)),
+        )
+        .await?;
+    Api::<ClusterRoleBinding>::all(client)
+        .patch(
+            CLUSTER_ROLE_NAME,
+            &params,
+            &Patch::Apply(cluster_role_binding(namespace, service_account)),
+        )
+        .await?;
+    println!(
+        "Installed the CRDs and ClusterRole {CLUSTER_ROLE_NAME} for ServiceAccount \
+         {namespace}/{service_account}"
+    );
+    Ok(())
+}
+
+/// `manifests [--namespace <ns>] [--image <image>] [--env KEY=VALUE ...]`: prints the
+/// operator's ServiceAccount, RBAC and Deployment, with the ClusterRole matching the
+/// features the `--env` settings enable, and the webhook with `--env WEBHOOK_ENABLED=true`
+fn manifests(flags: &Flags) -> CliResult {
+    let env = flags
+        .all("env")
+        .iter()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| format!("--env '{pair}' must be KEY=VALUE"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // Applied over this shell's environment, so the ClusterRole sees the Deployment's settings
+    for (key, value) in &env {
+        std::env::set_var(key, value);
+    }
+    let config = Config::from_env();
+    let image = format!("sas-operator:{}", env!("CARGO_PKG_VERSION"));
+    let options = ManifestOptions {
+        namespace: flags.get("namespace").unwrap_or(DEFAULT_NAMESPACE),
+        service_account: flags.get("service-account").unwrap_or(SERVICE_ACCOUNT_NAME),
+        image: flags.get("image").unwrap_or(&image),
+        env: &env,
+    };
+    print!("{}", render(&config, &options)?);
+    Ok(())
+}
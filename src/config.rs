@@ -0,0 +1,178 @@
+use crate::crd::AirGapSettings;
+use crate::http::ProxySettings;
+use crate::sentry::SentrySettings;
+use crate::shard::Shard;
+use crate::validate::{MAX_START_SKEW_SECONDS, MAX_USER_DELEGATION_TTL_HOURS};
+use azure_storage::CloudLocation;
+
+pub fn env_var_or_default<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Comma-separated list from the environment, without blanks
+pub fn env_list(key: &str) -> Vec<String> {
+    std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Local Azurite emulator that replaces Azure for every CR (development only)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzuriteSettings {
+    pub address: String,
+    pub port: u16,
+}
+
+impl AzuriteSettings {
+    /// Azurite serves the well-known `devstoreaccount1` account under a path, not a subdomain
+    pub fn cloud_location(&self) -> CloudLocation {
+        CloudLocation::Emulator {
+            address: self.address.clone(),
+            port: self.port,
+        }
+    }
+}
+
+/// Operator-wide settings read from the environment at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub sas_renewal_hours: i64,
+    pub sas_ttl_hours: i64,
+    pub air_gap: Option<AirGapSettings>,
+    pub start_skew_seconds: i64,
+    pub reconcile_interval_seconds: u64,
+    /// Longest sleep of an idle CR before it is re-checked, even if renewal is further away;
+    /// see `requeue_ceiling_seconds`
+    pub max_requeue_seconds: u64,
+    pub azurite: Option<AzuriteSettings>,
+    pub proxy: ProxySettings,
+    /// Listen address of the admin server (`/metrics`, `/rbac`)
+    pub admin_address: String,
+    /// Seconds without controller events after which `/healthz` fails (0 disables)
+    pub watchdog_silence_seconds: u64,
+    /// CRs reconciled at the same time, bounding parallel Azure calls (0 = unbounded)
+    pub max_concurrent_reconciles: u16,
+    /// Namespaces whose CRs are reconciled (`WATCH_NAMESPACE`, comma-separated); empty means
+    /// all namespaces
+    pub watch_namespaces: Vec<String>,
+    /// Namespaces whose CRs this instance ignores (`EXCLUDE_NAMESPACES`, comma-separated), e.g.
+    /// ephemeral CI namespaces served by another operator version
+    pub exclude_namespaces: Vec<String>,
+    /// Label selector restricting the watched CRs (`WATCH_LABEL_SELECTOR`, e.g.
+    /// `sas.azure.com/instance=prod`), so deployments can split the CRs between them
+    pub watch_label_selector: Option<String>,
+    /// Share of the CRs reconciled by this replica (`SHARD_COUNT`/`SHARD_INDEX`)
+    pub shard: Option<Shard>,
+    /// Consecutive Azure failures of a storage account that open its circuit (0 disables)
+    pub circuit_breaker_threshold: u32,
+    /// How long an open circuit keeps the CRs of its account from calling Azure
+    pub circuit_breaker_cooldown_seconds: u64,
+    /// User delegation key requests per minute across all CRs (0 = unlimited)
+    pub azure_requests_per_minute: u32,
+    /// Listen address of the admission webhook server (`--webhook`)
+    pub webhook_address: String,
+    /// Directory holding the webhook serving certificate as `tls.crt` and `tls.key`
+    pub webhook_cert_dir: String,
+    /// Issue and rotate a self-signed webhook certificate instead of reading one provided by
+    /// e.g. cert-manager (`WEBHOOK_SELF_SIGNED_CERT`)
+    pub webhook_self_signed_cert: bool,
+    /// The webhook server is deployed (`WEBHOOK_ENABLED`), so the CRD may serve v1beta1
+    /// through its conversion webhook and the manifests include the webhook
+    pub webhook_enabled: bool,
+    /// Validating and mutating webhook configurations whose CA bundle the webhook server
+    /// keeps up to date with its self-signed certificate
+    pub webhook_configuration_name: String,
+    /// Apply the CRDs of this release at startup (`INSTALL_CRDS`)
+    pub install_crds: bool,
+    /// File to append one JSON audit record per issued token to (`AUDIT_LOG_FILE`); records
+    /// are always logged on the `audit` target
+    pub audit_log_file: Option<String>,
+    /// Where reconcile failures are reported besides the logs (`SENTRY_DSN`, `SENTRY_ENVIRONMENT`)
+    pub sentry: SentrySettings,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let air_gap = env_var_or_default("AIR_GAPPED", false).then(|| AirGapSettings {
+            ttl_hours: env_var_or_default("AIR_GAPPED_TTL_HOURS", MAX_USER_DELEGATION_TTL_HOURS),
+            renewal_hours: env_var_or_default("AIR_GAPPED_RENEWAL_HOURS", 96),
+            stale_after_hours: env_var_or_default("AIR_GAPPED_STALE_AFTER_HOURS", 24),
+        });
+        let azurite = std::env::var("AZURITE_HOST")
+            .ok()
+            .map(|address| AzuriteSettings {
+                address,
+                port: env_var_or_default("AZURITE_BLOB_PORT", 10000),
+            });
+
+        Self {
+            sas_renewal_hours: env_var_or_default("SAS_RENEWAL_HOURS", 24),
+            sas_ttl_hours: env_var_or_default("SAS_TTL_HOURS", 48),
+            air_gap,
+            start_skew_seconds: env_var_or_default("SAS_START_SKEW_SECONDS", 5)
+                .clamp(0, MAX_START_SKEW_SECONDS),
+            reconcile_interval_seconds: env_var_or_default("RECONCILE_INTERVAL_SECONDS", 15).max(1),
+            max_requeue_seconds: env_var_or_default("MAX_REQUEUE_SECONDS", 3600).max(1),
+            azurite,
+            proxy: ProxySettings::from_env(),
+            admin_address: env_var_or_default("ADMIN_ADDRESS", "0.0.0.0:8080".to_string()),
+            watchdog_silence_seconds: env_var_or_default("WATCHDOG_SILENCE_SECONDS", 900),
+            max_concurrent_reconciles: env_var_or_default("MAX_CONCURRENT_RECONCILES", 0),
+            watch_namespaces: env_list("WATCH_NAMESPACE"),
+            exclude_namespaces: env_list("EXCLUDE_NAMESPACES"),
+            watch_label_selector: std::env::var("WATCH_LABEL_SELECTOR")
+                .ok()
+                .filter(|s| !s.trim().is_empty()),
+            shard: Shard::from_env(),
+            circuit_breaker_threshold: env_var_or_default("CIRCUIT_BREAKER_THRESHOLD", 5),
+            circuit_breaker_cooldown_seconds: env_var_or_default(
+                "CIRCUIT_BREAKER_COOLDOWN_SECONDS",
+                300,
+            ),
+            azure_requests_per_minute: env_var_or_default("AZURE_REQUESTS_PER_MINUTE", 0),
+            webhook_address: env_var_or_default("WEBHOOK_ADDRESS", "0.0.0.0:8443".to_string()),
+            webhook_cert_dir: env_var_or_default(
+                "WEBHOOK_CERT_DIR",
+                "/tmp/k8s-webhook-server/serving-certs".to_string(),
+            ),
+            webhook_self_signed_cert: env_var_or_default("WEBHOOK_SELF_SIGNED_CERT", false),
+            webhook_enabled: env_var_or_default("WEBHOOK_ENABLED", false),
+            webhook_configuration_name: env_var_or_default(
+                "WEBHOOK_CONFIGURATION_NAME",
+                "sas-operator".to_string(),
+            ),
+            install_crds: env_var_or_default("INSTALL_CRDS", false),
+            audit_log_file: std::env::var("AUDIT_LOG_FILE")
+                .ok()
+                .filter(|s| !s.trim().is_empty()),
+            sentry: SentrySettings::from_env(),
+        }
+    }
+
+    /// Longest sleep of an idle CR: `MAX_REQUEUE_SECONDS`, capped at half the watchdog window
+    /// so requeued CRs keep the controller stream from looking stalled
+    pub fn requeue_ceiling_seconds(&self) -> u64 {
+        match self.watchdog_silence_seconds {
+            0 => self.max_requeue_seconds,
+            window => self.max_requeue_seconds.min(window / 2).max(1),
+        }
+    }
+
+    /// Operator-wide default TTL, lengthened in air-gapped mode
+    pub fn default_ttl_hours(&self) -> i64 {
+        self.air_gap.map_or(self.sas_ttl_hours, |a| a.ttl_hours)
+    }
+
+    /// Operator-wide default renewal window, widened in air-gapped mode
+    pub fn default_renewal_hours(&self) -> i64 {
+        self.air_gap
+            .map_or(self.sas_renewal_hours, |a| a.renewal_hours)
+    }
+}
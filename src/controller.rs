@@ -0,0 +1,247 @@
+use crate::backoff::ErrorBackoff;
+use crate::circuit::CircuitBreaker;
+use crate::cleanup::{owner_key, OWNER_ANNOTATION};
+use crate::config::Config;
+use crate::crd::{ContextData, SasGenerator, ACCOUNT_LABEL};
+use crate::policy::SasAccountPolicy;
+use crate::reconcile::{error_policy, reconcile};
+use crate::watchdog::Watchdog;
+use futures::stream::{self, StreamExt};
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube::core::SelectorExt;
+use kube::runtime::controller::{self, Action, Config as ControllerConfig, Controller};
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::watcher::{self, watcher, Config as WatcherConfig};
+use kube::runtime::{predicates, Predicate, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Controllers for every watched scope: the whole cluster, or each namespace of
+/// `WATCH_NAMESPACE`, so the operator can run with namespaced RBAC
+pub struct Controllers {
+    controllers: Vec<Controller<SasGenerator>>,
+    /// Caches of the watched CRs, one per controller
+    pub stores: Vec<Store<SasGenerator>>,
+    /// Caches of the Secrets written by the operator, one per controller
+    pub secrets: Vec<Store<Secret>>,
+}
+
+impl Controllers {
+    /// `circuit` and `backoff` forget the state of deleted CRs and of accounts no CR targets
+    pub fn new(
+        client: &Client,
+        config: &Config,
+        watchdog: &Arc<Watchdog>,
+        circuit: &Arc<CircuitBreaker>,
+        backoff: &Arc<ErrorBackoff>,
+    ) -> Self {
+        let tracked = Tracked {
+            watchdog: watchdog.clone(),
+            circuit: circuit.clone(),
+            backoff: backoff.clone(),
+        };
+        let (controllers, secrets): (Vec<_>, Vec<_>) = match config.watch_namespaces.as_slice() {
+            [] => vec![cluster_controller(client, config, &tracked)]
+                .into_iter()
+                .unzip(),
+            namespaces => namespaces
+                .iter()
+                .map(|ns| {
+                    new_controller(
+                        Api::namespaced(client.clone(), ns),
+                        Api::namespaced(client.clone(), ns),
+                        config,
+                        &tracked,
+                    )
+                })
+                .unzip(),
+        };
+        info!(
+            scope = ?config.watch_namespaces,
+            selector = ?config.watch_label_selector,
+            "Watching SasGenerators (empty scope means all namespaces)"
+        );
+        let stores = controllers.iter().map(Controller::store).collect();
+        Self {
+            controllers,
+            stores,
+            secrets,
+        }
+    }
+
+    /// Runs all controllers until their streams end, reporting every result to `watchdog`
+    pub async fn run(self, context: Arc<ContextData>, watchdog: Arc<Watchdog>) {
+        let streams = self.controllers.into_iter().map(|controller| {
+            controller
+                .run(reconcile, error_policy, context.clone())
+                .boxed()
+        });
+        stream::select_all(streams)
+            .for_each(|res| {
+                match &res {
+                    Ok((obj_ref, action)) => {
+                        watchdog.reconciled(key(obj_ref), *action != Action::await_change())
+                    }
+                    // Failing CRs back off for longer than the watchdog window
+                    Err(
+                        controller::Error::ReconcilerFailed(_, obj_ref)
+                        | controller::Error::ObjectNotFound(obj_ref),
+                    ) => watchdog.reconciled(key(obj_ref), false),
+                    Err(_) => watchdog.beat(),
+                }
+                async move {
+                    match res {
+                        Ok((_obj_ref, action)) => info!(?action, "Reconciliation complete"),
+                        Err(err) => error!(?err, "Controller error"),
+                    }
+                }
+            })
+            .await;
+    }
+}
+
+/// State outliving single reconciles, kept per CR or per storage account
+#[derive(Clone)]
+struct Tracked {
+    watchdog: Arc<Watchdog>,
+    circuit: Arc<CircuitBreaker>,
+    backoff: Arc<ErrorBackoff>,
+}
+
+impl Tracked {
+    /// Drops the state of a deleted CR, and of its storage account unless another CR in
+    /// `crs` still targets it
+    fn forget(&self, cr: &SasGenerator, crs: &Store<SasGenerator>) {
+        let cr_key = key(&ObjectRef::from_obj(cr));
+        self.watchdog.reconciled(cr_key.clone(), false);
+        self.backoff.reset(&owner_key(cr));
+        let account = &cr.spec.storage_account;
+        // The store still holds the deleted CR until the event is reflected
+        let in_use = crs.state().iter().any(|other| {
+            other.spec.storage_account == *account && key(&ObjectRef::from_obj(&**other)) != cr_key
+        });
+        if !in_use {
+            self.circuit.forget(account);
+        }
+    }
+}
+
+/// Controller for `api`, also triggered by changes to the Secrets in `secrets` it wrote, so
+/// deleted or edited Secrets are repaired right away. Returns the cache of those Secrets
+/// too, which drift detection reads instead of the API server.
+fn new_controller(
+    api: Api<SasGenerator>,
+    secrets: Api<Secret>,
+    config: &Config,
+    tracked: &Tracked,
+) -> (Controller<SasGenerator>, Store<Secret>) {
+    let mut watcher_config = WatcherConfig::default();
+    if let Some(selector) = &config.watch_label_selector {
+        watcher_config = watcher_config.labels(selector);
+    }
+    // Status patches written by the operator don't change any of these, so they no longer
+    // trigger another reconcile of the same CR
+    let changed = predicates::generation
+        .combine(predicates::labels)
+        .combine(predicates::annotations)
+        .combine(predicates::finalizers);
+    let (reader, writer) = reflector::store();
+    let tracked = tracked.clone();
+    let known = reader.clone();
+    let crs = watcher(api, watcher_config)
+        .default_backoff()
+        .inspect(move |event| match event {
+            Ok(watcher::Event::Delete(cr)) => tracked.forget(cr, &known),
+            _ => tracked.watchdog.beat(),
+        })
+        .reflect(writer)
+        .applied_objects()
+        .predicate_filter(changed);
+    // Only the operator's Secrets are cached; one losing the label is seen as deleted
+    let (secret_reader, secret_writer) = reflector::store();
+    let secrets = watcher(secrets, WatcherConfig::default().labels(ACCOUNT_LABEL))
+        .default_backoff()
+        .reflect(secret_writer)
+        .touched_objects();
+    let controller = Controller::for_stream(crs, reader)
+        .with_config(ControllerConfig::default().concurrency(config.max_concurrent_reconciles))
+        .watches_stream(secrets, secret_owners);
+    (controller, secret_reader)
+}
+
+/// Watchdog key of a CR
+fn key<K: Resource>(obj_ref: &ObjectRef<K>) -> String {
+    format!(
+        "{}/{}",
+        obj_ref.namespace.as_deref().unwrap_or_default(),
+        obj_ref.name
+    )
+}
+
+/// CRs that wrote the Secret: its controller owner reference, or `OWNER_ANNOTATION` for
+/// Secrets in other namespaces or written without an owner reference
+fn secret_owners(secret: Secret) -> Vec<ObjectRef<SasGenerator>> {
+    let namespace = secret.namespace().unwrap_or_default();
+    let owned = secret
+        .owner_references()
+        .iter()
+        .filter(|owner| {
+            owner.kind == SasGenerator::kind(&())
+                && owner.api_version == SasGenerator::api_version(&())
+        })
+        .map(|owner| ObjectRef::new(&owner.name).within(&namespace));
+    let annotated = secret
+        .annotations()
+        .get(OWNER_ANNOTATION)
+        .and_then(|key| key.split_once('/'))
+        .map(|(ns, name)| ObjectRef::new(name).within(ns));
+    owned.chain(annotated).collect()
+}
+
+/// Cluster-wide controller; only here may the operator watch Namespaces, so that new or
+/// relabelled namespaces receive the Secrets of CRs selecting them. A changed
+/// SasAccountPolicy re-checks every CR, as it may now allow or refuse any of them.
+fn cluster_controller(
+    client: &Client,
+    config: &Config,
+    tracked: &Tracked,
+) -> (Controller<SasGenerator>, Store<Secret>) {
+    let (controller, secrets) = new_controller(
+        Api::all(client.clone()),
+        Api::all(client.clone()),
+        config,
+        tracked,
+    );
+    let store = controller.store();
+    let policy_store = store.clone();
+    let controller = controller
+        .watches(
+            Api::<SasAccountPolicy>::all(client.clone()),
+            WatcherConfig::default(),
+            move |_| {
+                policy_store
+                    .state()
+                    .into_iter()
+                    .map(|cr| ObjectRef::from_obj(&*cr))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .watches(
+            Api::<Namespace>::all(client.clone()),
+            WatcherConfig::default(),
+            move |namespace| {
+                store
+                    .state()
+                    .into_iter()
+                    .filter(|cr| {
+                        cr.namespace_selector()
+                            .and_then(Result::ok)
+                            .is_some_and(|selector| selector.matches(namespace.labels()))
+                    })
+                    .map(|cr| ObjectRef::from_obj(&*cr))
+                    .collect::<Vec<_>>()
+            },
+        );
+    (controller, secrets)
+}
@@ -0,0 +1,474 @@
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+    CustomResourceConversion, CustomResourceDefinitionVersion, ServiceReference,
+    WebhookClientConfig, WebhookConversion,
+};
+use serde_json::{json, Map, Value};
+
+// v1beta1 is served next to the stored v1alpha1 and converted by the webhook's `/convert`,
+// so the CRDs only serve it with `WEBHOOK_ENABLED`.
+// It differs only in these fields:
+// - `ttl`, `renewBefore`, `startSkew` and `reconcileInterval` are durations such as `48h`,
+//   replacing `sasTtlHours`, `sasRenewalHours`, `startSkewSeconds` and
+//   `reconcileIntervalSeconds`
+// - `outputs[].permissions` lists permission names instead of SAS letters
+// - `status.conditions[]` always carry an `observedGeneration`, taken from the status when the
+//   condition has none
+// Conversions are lossless both ways: durations spelled differently from their canonical
+// form (`2m` for `120s`) are kept in `DURATIONS_ANNOTATION` while stored as v1alpha1.
+
+pub const V1ALPHA1: &str = "sas.azure.com/v1alpha1";
+pub const V1BETA1: &str = "sas.azure.com/v1beta1";
+
+/// v1beta1 duration fields whose spelling the v1alpha1 integers cannot carry, as a JSON object
+pub const DURATIONS_ANNOTATION: &str = "sas.azure.com/v1beta1-durations";
+
+/// Service in front of the `--webhook` server, which the API server calls for conversions;
+/// `manifests` renders it with `WEBHOOK_ENABLED`
+pub const WEBHOOK_SERVICE_NAME: &str = "sas-operator-webhook";
+pub const WEBHOOK_SERVICE_NAMESPACE: &str = "sas-operator";
+
+/// v1alpha1 field, v1beta1 field, seconds per v1alpha1 unit, v1beta1 description
+const DURATIONS: &[(&str, &str, u64, &str)] = &[
+    (
+        "sasTtlHours",
+        "ttl",
+        3600,
+        "Validity of each token in whole hours, e.g. `48h`",
+    ),
+    (
+        "sasRenewalHours",
+        "renewBefore",
+        3600,
+        "How long before expiry the token is renewed, in whole hours, e.g. `24h`",
+    ),
+    (
+        "startSkewSeconds",
+        "startSkew",
+        1,
+        "How far the token start time is backdated to absorb clock drift, e.g. `30s`",
+    ),
+    (
+        "reconcileIntervalSeconds",
+        "reconcileInterval",
+        1,
+        "Shortest time between re-checks of the CR, e.g. `5m`",
+    ),
+];
+
+/// SAS permission letters and their v1beta1 names
+const PERMISSIONS: &[(char, &str)] = &[
+    ('r', "read"),
+    ('a', "add"),
+    ('c', "create"),
+    ('w', "write"),
+    ('d', "delete"),
+    ('x', "deleteVersion"),
+    ('y', "permanentDelete"),
+    ('l', "list"),
+    ('t', "tags"),
+    ('m', "move"),
+    ('e', "execute"),
+    ('o', "ownership"),
+    ('p', "permissions"),
+];
+
+/// The v1beta1 version of the CRD, derived from the v1alpha1 one so both stay in sync
+pub fn v1beta1_version(
+    v1alpha1: &CustomResourceDefinitionVersion,
+) -> Result<CustomResourceDefinitionVersion, serde_json::Error> {
+    let mut version = serde_json::to_value(v1alpha1)?;
+    version["name"] = "v1beta1".into();
+    version["storage"] = false.into();
+
+    let schema = &mut version["schema"]["openAPIV3Schema"]["properties"];
+    if let Some(spec) = schema["spec"]["properties"].as_object_mut() {
+        for (old, new, _, description) in DURATIONS {
+            spec.remove(*old);
+            spec.insert(
+                new.to_string(),
+                json!({ "type": "string", "description": description }),
+            );
+        }
+    }
+    let names: Vec<&str> = PERMISSIONS.iter().map(|(_, name)| *name).collect();
+    let output = &mut schema["spec"]["properties"]["outputs"]["items"]["properties"];
+    if output.is_object() {
+        output["permissions"] = json!({
+            "type": "array",
+            "description": "SAS permissions of the output (default: every permission)",
+            "items": { "type": "string", "enum": names },
+        });
+    }
+    serde_json::from_value(version)
+}
+
+/// Conversion through the operator's webhook; the CA bundle is filled in at deploy time
+pub fn webhook_conversion() -> CustomResourceConversion {
+    CustomResourceConversion {
+        strategy: "Webhook".into(),
+        webhook: Some(WebhookConversion {
+            conversion_review_versions: vec!["v1".into()],
+            client_config: Some(WebhookClientConfig {
+                service: Some(ServiceReference {
+                    name: WEBHOOK_SERVICE_NAME.into(),
+                    namespace: WEBHOOK_SERVICE_NAMESPACE.into(),
+                    path: Some("/convert".into()),
+                    port: Some(443),
+                }),
+                ..Default::default()
+            }),
+        }),
+    }
+}
+
+/// Converts a SasGenerator to `desired` (`sas.azure.com/v1alpha1` or `/v1beta1`)
+pub fn convert(mut object: Value, desired: &str) -> Result<Value, String> {
+    let current = object["apiVersion"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    match (current.as_str(), desired) {
+        (from, to) if from == to => return Ok(object),
+        (V1ALPHA1, V1BETA1) => to_v1beta1(&mut object)?,
+        (V1BETA1, V1ALPHA1) => to_v1alpha1(&mut object)?,
+        (from, to) => return Err(format!("cannot convert from {from} to {to}")),
+    }
+    object["apiVersion"] = desired.into();
+    Ok(object)
+}
+
+fn to_v1beta1(object: &mut Value) -> Result<(), String> {
+    let spellings = take_spellings(object);
+    if let Some(spec) = object.get_mut("spec").and_then(Value::as_object_mut) {
+        for (old, new, unit, _) in DURATIONS {
+            if let Some(value) = spec.remove(*old).filter(|v| !v.is_null()) {
+                let amount = value
+                    .as_i64()
+                    .ok_or_else(|| format!("{old} must be an integer"))?;
+                // The spelling is only kept while it still means the stored value
+                let duration = spellings
+                    .get(*new)
+                    .and_then(Value::as_str)
+                    .filter(|spelled| parse_duration(new, spelled, *unit) == Ok(amount))
+                    .map_or_else(
+                        || format!("{amount}{}", unit_names(*unit).0),
+                        str::to_string,
+                    );
+                spec.insert(new.to_string(), duration.into());
+            }
+        }
+        for output in outputs(spec) {
+            if let Some(letters) = output.get("permissions").and_then(Value::as_str) {
+                let names = letters
+                    .chars()
+                    .map(|letter| {
+                        PERMISSIONS
+                            .iter()
+                            .find(|(l, _)| *l == letter)
+                            .map(|(_, name)| Value::from(*name))
+                            .ok_or_else(|| format!("unknown SAS permission '{letter}'"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                output.insert("permissions".into(), names.into());
+            }
+        }
+    }
+    let generation = object["status"]["observedGeneration"].clone();
+    for condition in conditions(object) {
+        let missing = condition
+            .get("observedGeneration")
+            .is_none_or(Value::is_null);
+        if missing && !generation.is_null() {
+            condition.insert("observedGeneration".into(), generation.clone());
+        }
+    }
+    Ok(())
+}
+
+fn to_v1alpha1(object: &mut Value) -> Result<(), String> {
+    take_spellings(object);
+    let mut spellings = Map::new();
+    if let Some(spec) = object.get_mut("spec").and_then(Value::as_object_mut) {
+        for (old, new, unit, _) in DURATIONS {
+            if let Some(value) = spec.remove(*new).filter(|v| !v.is_null()) {
+                let spelled = value.as_str().unwrap_or_default();
+                let amount = parse_duration(new, spelled, *unit)?;
+                if spelled != format!("{amount}{}", unit_names(*unit).0) {
+                    spellings.insert(new.to_string(), spelled.into());
+                }
+                spec.insert(old.to_string(), amount.into());
+            }
+        }
+        for output in outputs(spec) {
+            if let Some(names) = output.get("permissions").and_then(Value::as_array) {
+                let letters = names
+                    .iter()
+                    .map(|name| {
+                        PERMISSIONS
+                            .iter()
+                            .find(|(_, n)| name.as_str() == Some(*n))
+                            .map(|(letter, _)| *letter)
+                            .ok_or_else(|| format!("unknown SAS permission {name}"))
+                    })
+                    .collect::<Result<String, _>>()?;
+                output.insert("permissions".into(), letters.into());
+            }
+        }
+    }
+    if !spellings.is_empty() {
+        let annotations = &mut object["metadata"]["annotations"];
+        if !annotations.is_object() {
+            *annotations = json!({});
+        }
+        annotations[DURATIONS_ANNOTATION] = Value::Object(spellings).to_string().into();
+    }
+    Ok(())
+}
+
+/// `spelled` in whole `unit` seconds, keeping the sign
+fn parse_duration(field: &str, spelled: &str, unit: u64) -> Result<i64, String> {
+    let duration: kube::core::Duration = spelled
+        .parse()
+        .map_err(|e| format!("{field} is not a duration: {e}"))?;
+    let seconds = std::time::Duration::from(duration).as_secs();
+    if seconds % unit != 0 {
+        return Err(format!(
+            "{field} must be a whole number of {}",
+            unit_names(unit).1
+        ));
+    }
+    let amount = i64::try_from(seconds / unit).map_err(|_| format!("{field} is too long"))?;
+    Ok(if duration.is_negative() {
+        -amount
+    } else {
+        amount
+    })
+}
+
+/// Removes `DURATIONS_ANNOTATION`, returning the spellings it held
+fn take_spellings(object: &mut Value) -> Map<String, Value> {
+    let Some(metadata) = object.get_mut("metadata").and_then(Value::as_object_mut) else {
+        return Map::new();
+    };
+    let Some(annotations) = metadata
+        .get_mut("annotations")
+        .and_then(Value::as_object_mut)
+    else {
+        return Map::new();
+    };
+    let spellings = annotations
+        .remove(DURATIONS_ANNOTATION)
+        .and_then(|raw| serde_json::from_str(raw.as_str()?).ok())
+        .unwrap_or_default();
+    if annotations.is_empty() {
+        metadata.remove("annotations");
+    }
+    spellings
+}
+
+/// Duration suffix and name of a v1alpha1 unit
+fn unit_names(unit: u64) -> (&'static str, &'static str) {
+    if unit == 3600 {
+        ("h", "hours")
+    } else {
+        ("s", "seconds")
+    }
+}
+
+fn outputs(spec: &mut Map<String, Value>) -> impl Iterator<Item = &mut Map<String, Value>> {
+    spec.get_mut("outputs")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn conditions(object: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    object
+        .get_mut("status")
+        .and_then(|status| status.get_mut("conditions"))
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::CustomResourceExt;
+
+    fn v1alpha1() -> Value {
+        json!({
+            "apiVersion": V1ALPHA1,
+            "kind": "SasGenerator",
+            "metadata": {
+                "name": "backup",
+                "namespace": "apps",
+                "generation": 7,
+                "annotations": { "team": "storage" },
+            },
+            "spec": {
+                "storageAccount": "backupacct",
+                "containers": ["data", "logs"],
+                "secretName": "backup-sas",
+                "sasTtlHours": 48,
+                "sasRenewalHours": 24,
+                "startSkewSeconds": -30,
+                "reconcileIntervalSeconds": 300,
+                "outputs": [
+                    { "name": "reader", "secretName": "backup-ro", "permissions": "rl" },
+                    { "name": "writer", "secretName": "backup-rw", "permissions": "racwdxyltmeop" },
+                    { "name": "default", "secretName": "backup-all" },
+                ],
+                "rotationStrategy": { "type": "BlueGreen", "overlapHours": 2 },
+            },
+            "status": {
+                "observedGeneration": 7,
+                "tokenHash": "0123456789abcdef",
+                "expiry": "2030-01-03T00:00:00Z",
+                "conditions": [
+                    {
+                        "type": "Ready",
+                        "status": "True",
+                        "reason": "TokenIssued",
+                        "message": "Secrets hold a token",
+                        "lastTransitionTime": "2030-01-01T00:00:00Z",
+                    },
+                    {
+                        "type": "Renewing",
+                        "status": "False",
+                        "reason": "TokenIssued",
+                        "observedGeneration": 6,
+                    },
+                ],
+            },
+        })
+    }
+
+    #[test]
+    fn v1alpha1_round_trips_through_v1beta1() {
+        let original = v1alpha1();
+        let beta = convert(original.clone(), V1BETA1).unwrap();
+        assert_eq!(beta["apiVersion"], V1BETA1);
+        assert_eq!(beta["spec"]["ttl"], "48h");
+        assert_eq!(beta["spec"]["startSkew"], "-30s");
+        assert_eq!(
+            beta["spec"]["outputs"][0]["permissions"],
+            json!(["read", "list"])
+        );
+        assert_eq!(beta["status"]["conditions"][0]["observedGeneration"], 7);
+        assert_eq!(beta["status"]["conditions"][1]["observedGeneration"], 6);
+
+        let mut back = convert(beta, V1ALPHA1).unwrap();
+        // Conditions without a generation of their own get the status one in v1beta1
+        back["status"]["conditions"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("observedGeneration");
+        assert_eq!(back, original);
+    }
+
+    #[test]
+    fn negative_durations_round_trip() {
+        let mut original = v1alpha1();
+        original["spec"]["sasTtlHours"] = json!(-1);
+        original["spec"]["sasRenewalHours"] = json!(0);
+        let beta = convert(original.clone(), V1BETA1).unwrap();
+        assert_eq!(beta["spec"]["ttl"], "-1h");
+        assert_eq!(beta["spec"]["renewBefore"], "0h");
+        let mut back = convert(beta, V1ALPHA1).unwrap();
+        back["status"]["conditions"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("observedGeneration");
+        assert_eq!(back, original);
+    }
+
+    #[test]
+    fn v1beta1_spellings_round_trip() {
+        let mut beta = convert(v1alpha1(), V1BETA1).unwrap();
+        beta["spec"]["ttl"] = json!("2880m");
+        beta["spec"]["startSkew"] = json!("1m30s");
+        beta["spec"]["reconcileInterval"] = json!("300s");
+        beta["spec"]["outputs"][0]["permissions"] = json!(["list", "read", "read"]);
+
+        let alpha = convert(beta.clone(), V1ALPHA1).unwrap();
+        assert_eq!(alpha["spec"]["sasTtlHours"], 48);
+        assert_eq!(alpha["spec"]["startSkewSeconds"], 90);
+        assert_eq!(alpha["spec"]["reconcileIntervalSeconds"], 300);
+        assert_eq!(alpha["spec"]["outputs"][0]["permissions"], "lrr");
+        assert_eq!(
+            alpha["metadata"]["annotations"][DURATIONS_ANNOTATION],
+            r#"{"startSkew":"1m30s","ttl":"2880m"}"#
+        );
+
+        assert_eq!(convert(alpha, V1BETA1).unwrap(), beta);
+    }
+
+    #[test]
+    fn stale_spelling_yields_to_changed_value() {
+        let mut beta = convert(v1alpha1(), V1BETA1).unwrap();
+        beta["spec"]["ttl"] = json!("2880m");
+        let mut alpha = convert(beta, V1ALPHA1).unwrap();
+        alpha["spec"]["sasTtlHours"] = json!(72);
+
+        let beta = convert(alpha, V1BETA1).unwrap();
+        assert_eq!(beta["spec"]["ttl"], "72h");
+        assert_eq!(
+            beta["metadata"]["annotations"],
+            json!({ "team": "storage" })
+        );
+    }
+
+    #[test]
+    fn rejects_unrepresentable_v1beta1_values() {
+        let mut beta = convert(v1alpha1(), V1BETA1).unwrap();
+        beta["spec"]["ttl"] = json!("90m");
+        assert_eq!(
+            convert(beta.clone(), V1ALPHA1).unwrap_err(),
+            "ttl must be a whole number of hours"
+        );
+        beta["spec"]["ttl"] = json!("48h");
+        beta["spec"]["outputs"][0]["permissions"] = json!(["admin"]);
+        assert_eq!(
+            convert(beta, V1ALPHA1).unwrap_err(),
+            r#"unknown SAS permission "admin""#
+        );
+    }
+
+    #[test]
+    fn v1beta1_schema_replaces_duration_and_permission_fields() {
+        let alpha = crate::crd::SasGenerator::crd().spec.versions[0].clone();
+        let beta = serde_json::to_value(v1beta1_version(&alpha).unwrap()).unwrap();
+        let spec = &beta["schema"]["openAPIV3Schema"]["properties"]["spec"]["properties"];
+        assert!(spec.get("sasTtlHours").is_none());
+        assert_eq!(spec["ttl"]["type"], "string");
+        assert_eq!(
+            spec["outputs"]["items"]["properties"]["permissions"]["items"]["enum"][0],
+            "read"
+        );
+        assert_eq!(beta["storage"], false);
+    }
+
+    #[test]
+    fn conversion_webhook_only_with_v1beta1() {
+        let served = |versions: &[&str]| {
+            let versions: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+            let sasgen = crate::crd::crds(&versions).unwrap().remove(0);
+            let names: Vec<String> = sasgen
+                .spec
+                .versions
+                .iter()
+                .map(|v| v.name.clone())
+                .collect();
+            (names, sasgen.spec.conversion.is_some())
+        };
+        assert_eq!(served(&[]), (vec!["v1alpha1".to_string()], false));
+        assert_eq!(
+            served(&["v1alpha1", "v1beta1"]),
+            (vec!["v1alpha1".to_string(), "v1beta1".to_string()], true)
+        );
+        assert!(crate::crd::crds(&["v1beta1".to_string()]).is_err());
+    }
+}
@@ -1,7 +1,32 @@
+use crate::backoff::ErrorBackoff;
+use crate::circuit::CircuitBreaker;
+use crate::config::{AzuriteSettings, Config};
+use crate::conversion;
+use crate::credentials::CredentialProvider;
+use crate::distribute::WrittenSecrets;
+use crate::metrics::Metrics;
+use crate::policy::SasAccountPolicy;
+use crate::sas::parse_permissions;
+use crate::shard::Shard;
+use crate::signature::SasOptions;
+use crate::utils::format_time;
+use crate::validate::{ACCOUNT_NAME_PATTERN, CONTAINER_NAME_PATTERN};
+use azure_storage::CloudLocation;
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, Time};
+use kube::core::{ParseExpressionError, Selector};
+use kube::runtime::events::{Recorder, Reporter};
+use kube::runtime::reflector::Store;
 use kube::{CustomResource, CustomResourceExt, ResourceExt};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, instrument};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{debug, info, instrument, warn};
+
+/// Label carried by every Secret the operator writes; the Secret watch selects on it
+pub const ACCOUNT_LABEL: &str = "sas.azure.com/account";
 
 #[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[kube(
@@ -9,93 +34,904 @@ use tracing::{debug, info, instrument};
     version = "v1alpha1",
     kind = "SasGenerator",
     namespaced,
-    status = "SasGeneratorStatus"
+    status = "SasGeneratorStatus",
+    shortname = "sasgen",
+    shortname = "sasgens",
+    category = "all",
+    category = "azure",
+    printcolumn = r#"{"name":"Account","type":"string","jsonPath":".spec.storageAccount"}"#,
+    printcolumn = r#"{"name":"Container","type":"string","jsonPath":".spec.containerName"}"#,
+    printcolumn = r#"{"name":"Target Secret","type":"string","jsonPath":".status.targetSecret"}"#,
+    printcolumn = r#"{"name":"Expiry","type":"string","jsonPath":".status.expiry"}"#,
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct SasGeneratorSpec {
+    /// Storage account name: 3-24 lowercase letters and digits
+    #[schemars(regex(pattern = ACCOUNT_NAME_PATTERN), length(min = 3, max = 24))]
     pub storage_account: String,
-    pub container_name: String,
+    /// Azure cloud of the account, selecting both the AAD authority and the blob endpoint
+    pub cloud: Option<AzureCloud>,
+    /// Storage DNS suffix replacing the cloud's default, e.g. for custom domains
+    pub endpoint_suffix: Option<String>,
+    /// Full blob endpoint (e.g. a private endpoint); overrides `cloud` and `endpointSuffix`
+    pub blob_endpoint: Option<String>,
+    /// Identity used for this CR instead of the operator-wide credential
+    pub azure_identity: Option<AzureIdentity>,
+    /// Secret in the CR namespace with `tenantId` and `clientId` of a service principal, plus either
+    /// `clientSecret` or a PEM certificate and key in `tls.crt`/`tls.key`
+    pub credentials_secret_ref: Option<SecretRef>,
+    /// AAD tenant of the storage account when it differs from the identity's home tenant
+    /// (service principal and workload identity only; not managed identity or the Azure CLI)
+    pub tenant_id: Option<String>,
+    /// Sign service SAS tokens with the storage account key from this Secret (key defaults to
+    /// `accountKey`) instead of a user delegation key; allows TTLs beyond 7 days
+    pub account_key_secret_ref: Option<SecretKeyRef>,
+    /// Like `accountKeySecretRef`, but the key is taken from a connection string
+    /// (key defaults to `connectionString`)
+    pub connection_string_secret_ref: Option<SecretKeyRef>,
+    /// Single container to issue a SAS for (mutually exclusive with `containers`)
+    #[schemars(regex(pattern = CONTAINER_NAME_PATTERN), length(min = 3, max = 63))]
+    pub container_name: Option<String>,
+    /// Several containers sharing one CR (mutually exclusive with `containerName`)
+    #[schemars(inner(regex(pattern = CONTAINER_NAME_PATTERN), length(min = 3, max = 63)))]
+    pub containers: Option<Vec<String>>,
+    /// Discover containers in the account by prefix/regex instead of listing them
+    pub container_selector: Option<ContainerSelector>,
+    /// Adopt an externally issued SAS token until it nears expiry (key defaults to `sas_token`)
+    pub import_secret_ref: Option<SecretKeyRef>,
+    /// Response headers forced on blobs served with the token (e.g. for CDN/browser downloads)
+    pub response_headers: Option<ResponseHeaders>,
+    /// Encryption scope (ses) that writes made with the token are pinned to
+    pub encryption_scope: Option<String>,
+    /// AAD object ID the key owner pre-authorizes to use the token (saoid)
+    pub authorized_object_id: Option<String>,
+    /// AAD object ID that must additionally pass ACL checks to use the token (suoid)
+    pub unauthorized_object_id: Option<String>,
+    /// GUID signed into the token (scid) so storage analytics logs can be traced back to this CR
+    pub correlation_id: Option<String>,
+    /// Scope the token to one immutable blob snapshot or version instead of the container
+    /// (the URL outputs then address the blob; only the `default` and `azcopy` formats apply)
+    pub blob_scope: Option<BlobScope>,
+    /// Order and failure handling when writing several Secrets
+    pub rollout: Option<RolloutPolicy>,
+    /// Write one Secret per container instead of one Secret with per-container keys
+    pub secret_per_container: Option<bool>,
     pub secret_name: Option<String>,
+    /// Namespace the Secrets are written to (defaults to the CR namespace). Secrets elsewhere
+    /// carry no owner reference and are deleted through a finalizer instead. Other namespaces
+    /// must list the CR's namespace, or `*`, in their `sas.azure.com/allowed-source-namespaces`
+    /// annotation; this applies to `targetNamespaces` and `targetNamespaceSelector` too.
+    pub target_namespace: Option<String>,
+    /// Several namespaces each receiving a copy of the Secrets (mutually exclusive with
+    /// `targetNamespace`)
+    pub target_namespaces: Option<Vec<String>>,
+    /// Namespaces selected by label, including ones created later (mutually exclusive with
+    /// `targetNamespace` and `targetNamespaces`)
+    pub target_namespace_selector: Option<LabelSelector>,
+    /// Other clusters (e.g. DR or edge) receiving a copy of every Secret on each rotation,
+    /// written to the same namespace and name. Remote copies are deleted with the CR unless
+    /// `deletionPolicy` is `Retain`. Kubeconfigs must carry inline credentials: `exec`,
+    /// `auth-provider` and file references are refused.
+    pub cluster_refs: Option<Vec<ClusterRef>>,
+    /// Copy the CR's own labels/annotations (e.g. cost-allocation or ownership) onto its Secrets
+    pub propagate_metadata: Option<MetadataPropagation>,
+    /// Further Secrets issued from this CR, each with its own name, layout and permissions
+    /// (e.g. an rclone read-write Secret plus a read-only monitoring Secret)
+    pub outputs: Option<Vec<SecretOutput>>,
+    /// Extra labels on the generated Secrets; they win over propagated labels
+    pub secret_labels: Option<BTreeMap<String, String>>,
+    /// Extra annotations on the generated Secrets, e.g. `reloader.stakater.com/match`;
+    /// they win over propagated annotations
+    pub secret_annotations: Option<BTreeMap<String, String>>,
+    /// Set `false` to write Secrets without an owner reference, e.g. for GitOps setups where
+    /// they must outlive the CR; requires `deletionPolicy` (default `true`)
+    pub owner_reference: Option<bool>,
+    /// What happens to Secrets without an owner reference when the CR is deleted
+    /// (default `Delete`)
+    pub deletion_policy: Option<DeletionPolicy>,
+    /// How the previous token is retired when a new one is issued
+    pub rotation_strategy: Option<RotationStrategy>,
+    /// Write each rotation to a new immutable `<secretName>-<hash>` Secret; the Secret under
+    /// `secretName` then only holds a `secretName` key pointing at the current version
+    pub immutable_secrets: Option<bool>,
+    /// Type of the generated Secrets, e.g. `sas.azure.com/token` (default `Opaque`).
+    /// Changing it recreates the Secrets, since the type of a Secret is immutable.
+    pub secret_type: Option<String>,
+    /// Tool-specific Secret layout replacing the default keys (default `default`)
+    pub output_format: Option<OutputFormat>,
+    /// Data key names written to the Secret, for consumers expecting e.g. `AZURE_STORAGE_SAS_TOKEN`
+    pub secret_keys: Option<SecretKeys>,
+    /// Static entries added to the Secret, e.g. a bucket path or region
+    pub additional_data: Option<BTreeMap<String, String>>,
+    /// Secrets and ConfigMaps in the CR namespace whose entries are added to the Secret;
+    /// later sources win, `additionalData` and the generated keys win over all of them
+    pub additional_data_from: Option<Vec<DataSource>>,
+    /// Extra Secret entries rendered from templates, e.g. env files or config snippets
+    pub template: Option<SecretTemplate>,
     pub sas_ttl_hours: Option<i64>,
     pub sas_renewal_hours: Option<i64>,
+    /// Seconds the token start time is backdated to absorb clock drift (defaults to the operator setting)
+    pub start_skew_seconds: Option<i64>,
+    /// Shortest time between re-checks of the CR; idle CRs sleep until their renewal time.
+    /// Both are capped by the operator's requeue ceiling (defaults to the operator setting)
+    pub reconcile_interval_seconds: Option<u64>,
+    /// Record rotations in the container metadata (requires write access to container properties)
+    pub stamp_container_metadata: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+pub enum AzureCloud {
+    #[default]
+    #[serde(rename = "AzurePublic")]
+    Public,
+    #[serde(rename = "AzureUSGovernment")]
+    UsGovernment,
+    #[serde(rename = "AzureChina")]
+    China,
+    #[serde(rename = "AzureGermany")]
+    Germany,
+}
+
+impl AzureCloud {
+    /// Environment name used by Go-based tools (`AZURE_CLOUD_NAME`)
+    pub fn environment_name(self) -> &'static str {
+        match self {
+            AzureCloud::Public => "AzurePublicCloud",
+            AzureCloud::UsGovernment => "AzureUSGovernmentCloud",
+            AzureCloud::China => "AzureChinaCloud",
+            AzureCloud::Germany => "AzureGermanCloud",
+        }
+    }
+
+    pub fn authority_host(self) -> &'static str {
+        match self {
+            AzureCloud::Public => "https://login.microsoftonline.com",
+            AzureCloud::UsGovernment => "https://login.microsoftonline.us",
+            AzureCloud::China => "https://login.chinacloudapi.cn",
+            AzureCloud::Germany => "https://login.microsoftonline.de",
+        }
+    }
+
+    pub fn storage_endpoint_suffix(self) -> &'static str {
+        match self {
+            AzureCloud::Public => "core.windows.net",
+            AzureCloud::UsGovernment => "core.usgovcloudapi.net",
+            AzureCloud::China => "core.chinacloudapi.cn",
+            AzureCloud::Germany => "core.cloudapi.de",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureIdentity {
+    /// Client ID of a user-assigned managed identity attached to the operator's nodes, or of the
+    /// app federated with `serviceAccountName`. A node identity must be listed in
+    /// `managedIdentities` of a SasAccountPolicy that applies to the CR and its account.
+    pub client_id: Option<String>,
+    /// ServiceAccount in the CR namespace whose tokens are exchanged for Azure AD tokens
+    /// (workload identity federation); requires `clientId`. The ServiceAccount must list
+    /// this CR's name, or `*`, in its `sas.azure.com/allowed-sasgenerators` annotation.
+    pub service_account_name: Option<String>,
+}
+
+/// Signed response header overrides (rscc, rscd, rsce, rscl, rsct)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseHeaders {
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// A single blob snapshot (sr=bs) or version (sr=bv); set exactly one of `snapshot` and `versionId`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobScope {
+    pub blob_name: String,
+    /// Snapshot timestamp, e.g. `2024-03-09T01:42:34.9360000Z`
+    pub snapshot: Option<String>,
+    /// Version ID, e.g. `2024-03-09T01:42:34.9360000Z`
+    pub version_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutPolicy {
+    /// Namespaces updated first, in this order; unlisted namespaces follow
+    pub namespace_order: Option<Vec<String>>,
+    /// Stop at the first failed Secret and leave the rest pending (default true)
+    pub abort_on_error: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+pub enum RotationType {
+    /// The new token replaces the previous one right away
+    #[default]
+    Replace,
+    /// The previous token stays published next to the new one for `overlapHours`
+    BlueGreen,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+pub enum DeletionPolicy {
+    /// Secrets are deleted through the cleanup finalizer
+    #[default]
+    Delete,
+    /// Secrets are left in place
+    Retain,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationStrategy {
+    #[serde(rename = "type", default)]
+    pub type_: RotationType,
+    /// Hours the previous token stays published as `<sasToken>_previous` after a rotation;
+    /// defaults to the renewal window, after which the previous token expires anyway
+    pub overlap_hours: Option<i64>,
+}
+
+/// A further Secret of the CR, written to every target namespace with a token per container.
+/// `template`, `additionalData` and the metadata settings of the CR apply to it as well.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretOutput {
+    /// Unique name of the output, used in logs
+    pub name: String,
+    pub secret_name: String,
+    /// SAS permission letters in any order, e.g. `rl` for read and list
+    /// (default: every permission)
+    pub permissions: Option<String>,
+    /// Tool-specific layout of this Secret (default `default`)
+    pub output_format: Option<OutputFormat>,
+    /// Data key names of this Secret
+    pub secret_keys: Option<SecretKeys>,
+}
+
+/// Secret layouts for common consumers; `additionalData` and `template` still apply on top
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// `sas_token`, `account`, `container`, `url` and `connection_string` (see `secretKeys`)
+    #[default]
+    Default,
+    /// `AZCOPY_SAS_URL`, or `AZCOPY_SAS_URL_<CONTAINER>` per container
+    Azcopy,
+    /// `rclone.conf` with one `azureblob` remote per container, named after it
+    Rclone,
+    /// `cloud` credentials file for Velero's Azure plugin; single container only
+    Velero,
+    /// restic environment (`AZURE_ACCOUNT_NAME`, `AZURE_ACCOUNT_SAS`, `RESTIC_REPOSITORY`) as
+    /// referenced by VolSync ReplicationSources; single container only
+    Restic,
+    /// Kopia Azure backend environment (`KOPIA_REPOSITORY`, `AZURE_STORAGE_ACCOUNT`,
+    /// `AZURE_STORAGE_SAS_TOKEN`); single container only
+    Kopia,
+}
+
+impl OutputFormat {
+    /// Formats whose consumer lists or writes a whole container, which a `blobScope` token
+    /// cannot do
+    pub fn container_access(self) -> bool {
+        !matches!(self, OutputFormat::Default | OutputFormat::Azcopy)
+    }
+
+    /// Formats whose consumer addresses exactly one container per Secret
+    pub fn single_container(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Velero | OutputFormat::Restic | OutputFormat::Kopia
+        )
+    }
+}
+
+/// Data key names of the generated Secret; unset keys keep their defaults
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeys {
+    /// Token key (default `sas_token`); with several containers it is suffixed with `_<container>`
+    pub sas_token: Option<String>,
+    /// Storage account key (default `account`)
+    pub account: Option<String>,
+    /// Container key of single-container Secrets (default `container`)
+    pub container: Option<String>,
+    /// Comma-separated container list of multi-container Secrets (default `containers`)
+    pub containers: Option<String>,
+    /// Full container URL including the token (default `url`); suffixed like `sasToken`
+    pub url: Option<String>,
+    /// `BlobEndpoint=...;SharedAccessSignature=...` connection string (default
+    /// `connection_string`); suffixed like `sasToken`
+    pub connection_string: Option<String>,
+}
+
+impl SecretKeys {
+    pub fn sas_token(&self) -> &str {
+        self.sas_token.as_deref().unwrap_or("sas_token")
+    }
+
+    pub fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or("account")
+    }
+
+    pub fn container(&self) -> &str {
+        self.container.as_deref().unwrap_or("container")
+    }
+
+    pub fn containers(&self) -> &str {
+        self.containers.as_deref().unwrap_or("containers")
+    }
+
+    /// Token key of `container`, which is suffixed only when the Secret carries several containers
+    pub fn sas_token_for(&self, container: &str, single: bool) -> String {
+        if single {
+            self.sas_token().to_string()
+        } else {
+            format!("{}_{container}", self.sas_token())
+        }
+    }
+
+    /// Key publishing the token replaced by the last blue/green rotation
+    pub fn previous_sas_token_for(&self, container: &str, single: bool) -> String {
+        format!("{}_previous", self.sas_token_for(container, single))
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_deref().unwrap_or("url")
+    }
+
+    pub fn connection_string(&self) -> &str {
+        self.connection_string
+            .as_deref()
+            .unwrap_or("connection_string")
+    }
+}
+
+/// Secret entries rendered from `{{ .name }}` placeholders. Variables: `account`, `endpoint`,
+/// `expiry`, `containers`, and `token_<container>`, `url_<container>` and
+/// `connection_string_<container>`, plus the unsuffixed ones and `container` when the Secret
+/// carries a single container.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretTemplate {
+    /// Data key to template; rendered entries replace generated keys of the same name
+    #[serde(default)]
+    pub data: BTreeMap<String, String>,
+}
+
+/// Secret or ConfigMap in the CR namespace to copy all entries from; set exactly one
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DataSource {
+    pub secret_ref: Option<SecretRef>,
+    pub config_map_ref: Option<SecretRef>,
+}
+
+/// CR metadata copied onto the generated Secrets. Keys under `sas.azure.com/` and
+/// `kubectl.kubernetes.io/` are never copied, and generated entries win over copied ones.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataPropagation {
+    /// Label key prefixes to copy, e.g. `cost-center` or `app.kubernetes.io/`; empty copies all
+    pub labels: Option<Vec<String>>,
+    /// Annotation key prefixes to copy; empty copies all
+    pub annotations: Option<Vec<String>>,
+}
+
+/// Key prefixes owned by the operator or kubectl that must not travel to the Secrets
+const RESERVED_METADATA_PREFIXES: &[&str] = &["sas.azure.com/", "kubectl.kubernetes.io/"];
+
+/// Entries of `source` whose key starts with one of `prefixes` (all when empty)
+fn propagated(
+    source: &std::collections::BTreeMap<String, String>,
+    prefixes: Option<&Vec<String>>,
+) -> std::collections::BTreeMap<String, String> {
+    let Some(prefixes) = prefixes else {
+        return Default::default();
+    };
+    source
+        .iter()
+        .filter(|(key, _)| {
+            !RESERVED_METADATA_PREFIXES
+                .iter()
+                .any(|reserved| key.starts_with(reserved))
+        })
+        .filter(|(key, _)| prefixes.is_empty() || prefixes.iter().any(|p| key.starts_with(p)))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Remote cluster reached through a kubeconfig stored in the CR namespace
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterRef {
+    /// Name used in logs and rollout status
+    pub name: String,
+    /// Secret holding the kubeconfig (key defaults to `kubeconfig`); its current context is used
+    pub kubeconfig_secret_ref: SecretKeyRef,
+}
+
+/// Reference to a Secret in the CR namespace
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretRef {
+    pub name: String,
+}
+
+/// Reference to a key of a Secret in the CR namespace
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeyRef {
+    pub name: String,
+    /// Default depends on the referencing field
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerSelector {
+    /// Only containers whose name starts with this prefix (filtered by Azure)
+    pub prefix: Option<String>,
+    /// Only containers whose full name matches this regular expression
+    pub regex: Option<String>,
+}
+
+impl ContainerSelector {
+    /// Applies the regex part of the selector; the prefix is already applied when listing
+    pub fn matches(&self, container: &str) -> bool {
+        match &self.regex {
+            Some(pattern) => regex::Regex::new(pattern).is_ok_and(|re| re.is_match(container)),
+            None => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct SasGeneratorStatus {
-    pub token: Option<String>,
+    /// `metadata.generation` the status was written for
+    pub observed_generation: Option<i64>,
+    /// Fingerprint of the current tokens; the tokens themselves only live in the Secrets
+    pub token_hash: Option<String>,
     pub target_secret: Option<String>,
-    pub generated: Option<String>,
-    pub expiry: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub target_secrets: Vec<String>,
+    /// When the current tokens were issued
+    pub generated: Option<Time>,
+    /// When the current tokens expire
+    pub expiry: Option<Time>,
+    /// Correlation ID (scid) signed into the current token
+    pub correlation_id: Option<String>,
+    /// Secret the current token was imported from; cleared on the first rotation
+    pub imported_from: Option<String>,
+    /// Last time the operator successfully talked to Azure for this CR
+    pub last_azure_contact: Option<String>,
+    /// When the operator plans to rotate: expiry minus the renewal window
+    pub next_renewal_time: Option<String>,
+    /// Until when the previous token stays published after a blue/green rotation
+    pub overlap_until: Option<String>,
+    /// `sas.azure.com/rotate` annotation value the last rotation was issued for
+    pub rotation_request: Option<String>,
+    /// Message of the most recent failed reconcile; kept after recovery for diagnosis
+    pub last_error: Option<String>,
+    /// When the most recent failed reconcile happened
+    pub last_failure_time: Option<String>,
+    /// Failed reconciles since the last successful one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub consecutive_failures: u32,
+    /// Most recent rotations, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<RotationRecord>,
+    /// Per-Secret state of the last rollout
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub distribution: Vec<SecretDistribution>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<SasGeneratorCondition>,
+}
+
+/// One rotation, kept in `status.history`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationRecord {
+    pub generated: Time,
+    pub expiry: Time,
+    /// Why the rotation happened: Initial, Renewal, Requested, TargetsChanged,
+    /// RolloutIncomplete or SecretDrift
+    pub reason: String,
+    pub token_hash: Option<String>,
+    /// `namespace/name=resourceVersion` of every Secret written by the rotation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secret_revisions: Vec<String>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum DistributionState {
+    Pending,
+    Applied,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretDistribution {
+    pub namespace: String,
+    pub secret: String,
+    pub state: DistributionState,
+    /// resourceVersion observed after the write was confirmed
+    pub resource_version: Option<String>,
+    /// Hash of the Secret data observed after the write; only a different hash is drift,
+    /// label or annotation edits are not
+    pub data_hash: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SasGeneratorCondition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub status: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub last_transition_time: Option<String>,
+    /// `metadata.generation` the condition was computed for; kept for v1beta1 clients
+    pub observed_generation: Option<i64>,
+}
+
+/// Settings for clusters with intermittent Azure connectivity
+#[derive(Debug, Clone, Copy)]
+pub struct AirGapSettings {
+    /// Default TTL used instead of `sas_ttl_hours` so tokens outlive connectivity gaps
+    pub ttl_hours: i64,
+    /// Default renewal window; renewing early leaves days of retries before expiry
+    pub renewal_hours: i64,
+    /// Time without a successful Azure call after which status reports a stale connection
+    pub stale_after_hours: i64,
 }
 
 #[derive(Clone)]
 pub struct ContextData {
     pub client: kube::Client,
-    pub sas_renewal_hours: i64,
-    pub sas_ttl_hours: i64,
+    /// `Config::default_renewal_hours`, resolved once
+    pub default_renewal_hours: i64,
+    /// `Config::default_ttl_hours`, resolved once
+    pub default_ttl_hours: i64,
+    pub air_gap: Option<AirGapSettings>,
+    pub start_skew_seconds: i64,
+    pub reconcile_interval_seconds: u64,
+    pub max_requeue_seconds: u64,
+    pub exclude_namespaces: Vec<String>,
+    pub shard: Option<Shard>,
+    pub azurite: Option<AzuriteSettings>,
+    pub credentials: Arc<dyn CredentialProvider>,
+    pub metrics: Arc<Metrics>,
+    pub circuit: Arc<CircuitBreaker>,
+    pub backoff: Arc<ErrorBackoff>,
+    pub recorder: Recorder,
+    /// Caches of the Secrets written by the operator, one per controller
+    pub secrets: Vec<Store<Secret>>,
+    /// Namespaces those caches cover; empty for the whole cluster
+    pub watch_namespaces: Vec<String>,
+    pub written: Arc<WrittenSecrets>,
 }
 
 impl ContextData {
-    pub fn new(client: kube::Client, sas_renewal_hours: i64, sas_ttl_hours: i64) -> Self {
+    pub fn new(
+        client: kube::Client,
+        config: &Config,
+        credentials: Arc<dyn CredentialProvider>,
+        secrets: Vec<Store<Secret>>,
+        circuit: Arc<CircuitBreaker>,
+        backoff: Arc<ErrorBackoff>,
+    ) -> Self {
         info!(
-            renewal_hours = sas_renewal_hours,
-            ttl_hours = sas_ttl_hours,
+            ?config,
+            credentials = credentials.kind(),
             "Initialized ContextData"
         );
+        if let Some(azurite) = &config.azurite {
+            warn!(
+                ?azurite,
+                "Azurite mode: all CRs target the emulator, not Azure"
+            );
+        }
+        let recorder = Recorder::new(
+            client.clone(),
+            Reporter {
+                controller: "sas-operator".into(),
+                instance: std::env::var("POD_NAME").ok(),
+            },
+        );
         Self {
             client,
-            sas_renewal_hours,
-            sas_ttl_hours,
+            default_renewal_hours: config.default_renewal_hours(),
+            default_ttl_hours: config.default_ttl_hours(),
+            air_gap: config.air_gap,
+            start_skew_seconds: config.start_skew_seconds,
+            reconcile_interval_seconds: config.reconcile_interval_seconds,
+            max_requeue_seconds: config.requeue_ceiling_seconds(),
+            exclude_namespaces: config.exclude_namespaces.clone(),
+            shard: config.shard,
+            azurite: config.azurite.clone(),
+            credentials,
+            metrics: Arc::new(Metrics::default()),
+            circuit,
+            backoff,
+            recorder,
+            secrets,
+            watch_namespaces: config.watch_namespaces.clone(),
+            written: Arc::default(),
         }
     }
+
+    /// Whether the CR is left to another operator instance: it lives in an excluded
+    /// namespace, or hashes into another replica's shard
+    pub fn is_excluded(&self, sasgen: &SasGenerator) -> bool {
+        let namespace = sasgen.metadata.namespace.as_deref().unwrap_or_default();
+        self.exclude_namespaces.iter().any(|ns| ns == namespace)
+            || self
+                .shard
+                .is_some_and(|shard| !shard.owns(namespace, &sasgen.name_any()))
+    }
+}
+
+/// A Secret written by the operator and the containers whose tokens it carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretTarget {
+    pub name: String,
+    pub namespace: String,
+    pub containers: Vec<String>,
 }
 
 impl SasGenerator {
-    /// Resolves the secret name to use: CR-provided override or default computed from storage/container
+    /// Blob endpoint of the storage account: the explicit endpoint or suffix, else its cloud's default
+    pub fn cloud_location(&self) -> CloudLocation {
+        let account = self.spec.storage_account.clone();
+        if let Some(uri) = &self.spec.blob_endpoint {
+            return CloudLocation::Custom {
+                account,
+                uri: uri.trim_end_matches('/').to_string(),
+            };
+        }
+        if let Some(suffix) = &self.spec.endpoint_suffix {
+            return CloudLocation::Custom {
+                uri: format!("https://{account}.blob.{suffix}"),
+                account,
+            };
+        }
+        match self.spec.cloud.unwrap_or_default() {
+            AzureCloud::Public => CloudLocation::Public { account },
+            AzureCloud::China => CloudLocation::China { account },
+            cloud => CloudLocation::Custom {
+                uri: format!("https://{account}.blob.{}", cloud.storage_endpoint_suffix()),
+                account,
+            },
+        }
+    }
+
+    /// Tokens are service SAS signed with a shared key rather than user delegation SAS
+    pub fn signs_with_account_key(&self) -> bool {
+        self.spec.account_key_secret_ref.is_some()
+            || self.spec.connection_string_secret_ref.is_some()
+    }
+
+    /// Returns the containers listed explicitly in the spec, in spec order
+    pub fn container_names(&self) -> Vec<String> {
+        self.spec
+            .container_name
+            .iter()
+            .chain(self.spec.containers.iter().flatten())
+            .cloned()
+            .collect()
+    }
+
+    /// Parsed `targetNamespaceSelector`, if set
+    pub fn namespace_selector(&self) -> Option<Result<Selector, ParseExpressionError>> {
+        self.spec
+            .target_namespace_selector
+            .clone()
+            .map(Selector::try_from)
+    }
+
+    /// Namespaces the Secrets are written to when they are not selected by label: the explicit
+    /// list, the single target namespace, or the CR namespace
+    pub fn target_namespaces(&self) -> Vec<String> {
+        if let Some(namespaces) = &self.spec.target_namespaces {
+            return namespaces.clone();
+        }
+        let namespace = self
+            .spec
+            .target_namespace
+            .clone()
+            .or_else(|| self.namespace())
+            .unwrap_or_else(|| "default".into());
+        vec![namespace]
+    }
+
+    /// Resolves the Secrets to write: one per container, or a single Secret holding all containers.
+    /// Names come from the CR override or default to `volsync-{account}[-{container}]`.
     #[instrument(skip(self))]
-    pub fn target_secret_name(&self) -> String {
-        match &self.spec.secret_name {
-            Some(name) => {
-                debug!(%name, "Using secret_name provided in CR spec");
-                name.clone()
-            }
-            None => {
-                let default_name = format!(
-                    "volsync-{}-{}",
-                    self.spec.storage_account, self.spec.container_name
-                );
-                debug!(target_secret = %default_name, "Computed default target Secret name");
-                default_name
+    pub fn secret_targets(
+        &self,
+        containers: &[String],
+        namespaces: &[String],
+    ) -> Vec<SecretTarget> {
+        let account = &self.spec.storage_account;
+        let mut targets = Vec::new();
+
+        for namespace in namespaces.iter().cloned() {
+            if self.spec.secret_per_container.unwrap_or(false) {
+                targets.extend(containers.iter().map(|container| SecretTarget {
+                    name: match &self.spec.secret_name {
+                        Some(name) => format!("{name}-{container}"),
+                        None => format!("volsync-{account}-{container}"),
+                    },
+                    namespace: namespace.clone(),
+                    containers: vec![container.clone()],
+                }));
+            } else {
+                let name = match (&self.spec.secret_name, containers) {
+                    (Some(name), _) => name.clone(),
+                    (None, [container]) => format!("volsync-{account}-{container}"),
+                    (None, _) => format!("volsync-{account}"),
+                };
+                targets.push(SecretTarget {
+                    name,
+                    namespace,
+                    containers: containers.to_vec(),
+                });
             }
         }
+
+        debug!(?targets, "Resolved target Secrets");
+        targets
+    }
+
+    /// `secretName` that names the Secrets exactly as leaving it unset does, if the spec
+    /// tells: a single Secret for selected containers is named after how many match
+    pub fn default_secret_name(&self) -> Option<String> {
+        let account = &self.spec.storage_account;
+        if self.spec.secret_per_container.unwrap_or(false) {
+            return Some(format!("volsync-{account}"));
+        }
+        match self.container_names().as_slice() {
+            [] => None,
+            [container] => Some(format!("volsync-{account}-{container}")),
+            _ => Some(format!("volsync-{account}")),
+        }
+    }
+
+    /// Type of the generated Secrets
+    pub fn secret_type(&self) -> &str {
+        self.spec.secret_type.as_deref().unwrap_or("Opaque")
+    }
+
+    /// Previous tokens are published during an overlap window after each rotation
+    pub fn blue_green(&self) -> bool {
+        self.spec
+            .rotation_strategy
+            .as_ref()
+            .is_some_and(|r| r.type_ == RotationType::BlueGreen)
+    }
+
+    /// Secrets in the CR namespace carry an owner reference unless disabled
+    pub fn owner_reference(&self) -> bool {
+        self.spec.owner_reference.unwrap_or(true)
+    }
+
+    /// Secrets the garbage collector does not handle are deleted with the CR unless retained
+    pub fn deletion_policy(&self) -> DeletionPolicy {
+        self.spec.deletion_policy.unwrap_or_default()
+    }
+
+    /// Secret data key names, with defaults for the ones not overridden
+    pub fn secret_keys(&self) -> SecretKeys {
+        self.spec.secret_keys.clone().unwrap_or_default()
+    }
+
+    /// Collects the optional SAS parameters from the spec
+    pub fn sas_options(&self) -> SasOptions {
+        SasOptions {
+            response_headers: self.spec.response_headers.clone().unwrap_or_default(),
+            encryption_scope: self.spec.encryption_scope.clone(),
+            correlation_id: self.spec.correlation_id.clone(),
+            authorized_object_id: self.spec.authorized_object_id.clone(),
+            unauthorized_object_id: self.spec.unauthorized_object_id.clone(),
+            blob_scope: self.spec.blob_scope.clone(),
+            permissions: None,
+        }
+    }
+
+    /// SAS options of an output: those of the CR with the output's permissions
+    pub fn output_sas_options(&self, output: &SecretOutput) -> SasOptions {
+        SasOptions {
+            permissions: output
+                .permissions
+                .as_deref()
+                .and_then(|p| parse_permissions(p).ok())
+                .map(|p| p.to_string()),
+            ..self.sas_options()
+        }
+    }
+
+    /// The CR as seen by one of its `outputs`: the output's Secret name and layout, with one
+    /// Secret per namespace holding every container
+    pub fn for_output(&self, output: &SecretOutput) -> SasGenerator {
+        let mut view = self.clone();
+        view.spec.secret_name = Some(output.secret_name.clone());
+        view.spec.secret_per_container = Some(false);
+        view.spec.output_format = output.output_format;
+        view.spec.secret_keys = output.secret_keys.clone();
+        view.spec.outputs = None;
+        view
     }
 
     /// Returns labels for the secret based on the spec
-    pub fn secret_labels(&self) -> std::collections::BTreeMap<String, String> {
-        std::collections::BTreeMap::from([
-            (
-                "sas.azure.com/account".into(),
-                self.spec.storage_account.clone(),
-            ),
-            (
-                "sas.azure.com/container".into(),
-                self.spec.container_name.clone(),
-            ),
-        ])
+    pub fn secret_labels(
+        &self,
+        target: &SecretTarget,
+    ) -> std::collections::BTreeMap<String, String> {
+        let propagation = self.spec.propagate_metadata.as_ref();
+        let mut labels = propagated(self.labels(), propagation.and_then(|p| p.labels.as_ref()));
+        labels.extend(self.spec.secret_labels.clone().unwrap_or_default());
+        labels.insert(ACCOUNT_LABEL.into(), self.spec.storage_account.clone());
+        if let [container] = target.containers.as_slice() {
+            labels.insert("sas.azure.com/container".into(), container.clone());
+        }
+        labels
     }
 
-    /// Returns annotations for the secret based on status
-    pub fn secret_annotations(&self) -> std::collections::BTreeMap<String, String> {
-        let status = self.status.clone().unwrap_or_default();
+    /// Returns annotations for the secret based on spec and status
+    pub fn secret_annotations(
+        &self,
+        status: &SasGeneratorStatus,
+    ) -> std::collections::BTreeMap<String, String> {
+        let propagation = self.spec.propagate_metadata.as_ref();
+        let mut annotations = propagated(
+            self.annotations(),
+            propagation.and_then(|p| p.annotations.as_ref()),
+        );
+        annotations.extend(self.spec.secret_annotations.clone().unwrap_or_default());
+        annotations.insert(
+            "sas.azure.com/generated".into(),
+            status
+                .generated
+                .as_ref()
+                .map(format_time)
+                .unwrap_or_default(),
+        );
+        annotations.insert(
+            "sas.azure.com/expires".into(),
+            status.expiry.as_ref().map(format_time).unwrap_or_default(),
+        );
+        if let Some(scope) = &self.spec.encryption_scope {
+            annotations.insert("sas.azure.com/encryption-scope".into(), scope.clone());
+        }
+        annotations
+    }
+
+    /// Returns the container metadata entries stamped on rotation
+    pub fn container_metadata(
+        &self,
+        rotated_at: &str,
+    ) -> std::collections::BTreeMap<String, String> {
         std::collections::BTreeMap::from([
+            ("last_sas_rotation".into(), rotated_at.to_string()),
             (
-                "sas.azure.com/generated".into(),
-                status.generated.unwrap_or_default(),
-            ),
-            (
-                "sas.azure.com/expires".into(),
-                status.expiry.unwrap_or_default(),
+                "sas_issuer".into(),
+                format!(
+                    "{}/{}",
+                    self.namespace().unwrap_or_default(),
+                    self.name_any()
+                ),
             ),
         ])
     }
@@ -103,21 +939,32 @@ impl SasGenerator {
     /// Logs the CR spec and resolved secret
     pub fn log_spec(&self) {
         let cr_name = self.name_any();
-        let target_secret = self.target_secret_name();
+        let target_secrets: Vec<String> = self
+            .secret_targets(&self.container_names(), &self.target_namespaces())
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
         let token_present = self
             .status
             .as_ref()
-            .and_then(|s| s.token.as_ref())
+            .and_then(|s| s.token_hash.as_ref())
             .is_some();
-        let expiry = self.status.as_ref().and_then(|s| s.expiry.as_ref());
+        let expiry = self
+            .status
+            .as_ref()
+            .and_then(|s| s.expiry.as_ref())
+            .map(format_time);
 
         info!(
             crd = %cr_name,
             account = %self.spec.storage_account,
-            container = %self.spec.container_name,
+            containers = ?self.container_names(),
+            container_selector = ?self.spec.container_selector,
             ttl = ?self.spec.sas_ttl_hours,
             renewal = ?self.spec.sas_renewal_hours,
-            target_secret = %target_secret,
+            start_skew = ?self.spec.start_skew_seconds,
+            reconcile_interval = ?self.spec.reconcile_interval_seconds,
+            target_secrets = ?target_secrets,
             token_present = %token_present,
             expiry = ?expiry,
             "Loaded SasGenerator spec and status"
@@ -125,11 +972,71 @@ impl SasGenerator {
     }
 }
 
+/// The CRD serving v1alpha1 (stored) and v1beta1, converted by the webhook
+pub fn crd() -> Result<CustomResourceDefinition, serde_json::Error> {
+    let mut crd = SasGenerator::crd();
+    let v1beta1 = conversion::v1beta1_version(&crd.spec.versions[0])?;
+    crd.spec.versions.push(v1beta1);
+    crd.spec.conversion = Some(conversion::webhook_conversion());
+    Ok(crd)
+}
+
+/// SasGenerator versions the CRD serves by default: v1beta1 only when the webhook that
+/// converts it is deployed
+pub fn served_versions(config: &Config) -> Vec<String> {
+    if config.webhook_enabled {
+        vec!["v1alpha1".into(), "v1beta1".into()]
+    } else {
+        vec!["v1alpha1".into()]
+    }
+}
+
+/// Every CRD of the operator, serving only `versions` of SasGenerator (the storage version
+/// if empty). The storage version cannot be left out, and a single version needs no
+/// conversion webhook.
+pub fn crds(versions: &[String]) -> Result<Vec<CustomResourceDefinition>, String> {
+    let mut sasgen = crd().map_err(|e| e.to_string())?;
+    if versions.is_empty() {
+        sasgen.spec.versions.retain(|v| v.storage);
+        sasgen.spec.conversion = None;
+    } else {
+        if let Some(unknown) = versions
+            .iter()
+            .find(|v| !sasgen.spec.versions.iter().any(|known| known.name == **v))
+        {
+            return Err(format!("unknown API version '{unknown}'"));
+        }
+        sasgen.spec.versions.retain(|v| versions.contains(&v.name));
+        if !sasgen.spec.versions.iter().any(|v| v.storage) {
+            return Err("the storage version v1alpha1 must be included".into());
+        }
+        if sasgen.spec.versions.len() == 1 {
+            sasgen.spec.conversion = None;
+        }
+    }
+    Ok(vec![sasgen, SasAccountPolicy::crd()])
+}
+
+/// Multi-document YAML, or a JSON `List` that `kubectl apply -f` accepts as well
+pub fn render_crds(
+    crds: &[CustomResourceDefinition],
+    json: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if json {
+        let list = serde_json::json!({ "apiVersion": "v1", "kind": "List", "items": crds });
+        return Ok(serde_json::to_string_pretty(&list)? + "\n");
+    }
+    let documents = crds
+        .iter()
+        .map(serde_yaml::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(documents.join("---\n"))
+}
+
+/// Writes every CRD to `crd.yaml` in the working directory (`--crd`)
 #[instrument]
 pub fn generate_crd() -> Result<(), Box<dyn std::error::Error>> {
-    let crd = SasGenerator::crd();
-    let yaml = serde_yaml::to_string(&crd)?;
-    std::fs::write("crd.yaml", yaml)?;
+    std::fs::write("crd.yaml", render_crds(&crds(&[])?, false)?)?;
     info!("CRD YAML generated successfully at crd.yaml");
     Ok(())
 }
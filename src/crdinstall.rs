@@ -0,0 +1,97 @@
+use crate::config::Config;
+use crate::crd::{crds, served_versions};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::api::{Patch, PatchParams};
+use kube::runtime::wait::{await_condition, conditions};
+use kube::{Api, Client, ResourceExt};
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+/// Operator release that last applied a CRD, so an older replica never downgrades it
+pub const OPERATOR_VERSION_ANNOTATION: &str = "sas.azure.com/operator-version";
+
+const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Applies the CRDs of this release (`INSTALL_CRDS=true`), replacing the manual
+/// `--crd` + `kubectl apply` step of an upgrade. v1beta1 is served only with
+/// `WEBHOOK_ENABLED`. CRDs last applied by a newer release are left alone; a CRD whose
+/// stored versions this release no longer serves is an error.
+#[instrument(skip_all)]
+pub async fn install_crds(
+    client: &Client,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for crd in crds(&served_versions(config))? {
+        apply(client, crd).await?;
+    }
+    Ok(())
+}
+
+async fn apply(
+    client: &Client,
+    mut crd: CustomResourceDefinition,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let api = Api::<CustomResourceDefinition>::all(client.clone());
+    let name = crd.name_any();
+    let ours = env!("CARGO_PKG_VERSION");
+
+    if let Some(existing) = api.get_opt(&name).await? {
+        let theirs = existing.annotations().get(OPERATOR_VERSION_ANNOTATION);
+        if let Some(theirs) = theirs.filter(|v| newer(v, ours)) {
+            warn!(
+                crd = %name,
+                installed = %theirs,
+                running = %ours,
+                "CRD was applied by a newer operator; not downgrading it"
+            );
+            return Ok(());
+        }
+        let served: Vec<&str> = crd.spec.versions.iter().map(|v| v.name.as_str()).collect();
+        let dropped: Vec<&String> = existing
+            .status
+            .as_ref()
+            .and_then(|s| s.stored_versions.as_ref())
+            .into_iter()
+            .flatten()
+            .filter(|v| !served.contains(&v.as_str()))
+            .collect();
+        if !dropped.is_empty() {
+            return Err(format!(
+                "CRD {name} has objects stored as {dropped:?}, which this release no longer serves; \
+                 migrate them before upgrading"
+            )
+            .into());
+        }
+    }
+
+    crd.annotations_mut()
+        .insert(OPERATOR_VERSION_ANNOTATION.to_string(), ours.to_string());
+    api.patch(
+        &name,
+        &PatchParams::apply("sas-operator").force(),
+        &Patch::Apply(&crd),
+    )
+    .await?;
+    tokio::time::timeout(
+        ESTABLISH_TIMEOUT,
+        await_condition(api, &name, conditions::is_crd_established()),
+    )
+    .await
+    .map_err(|_| format!("CRD {name} was not established within {ESTABLISH_TIMEOUT:?}"))??;
+    info!(crd = %name, version = %ours, "Applied CRD");
+    Ok(())
+}
+
+/// Whether release `a` is newer than `b`, comparing dot-separated numbers; pre-release
+/// suffixes are ignored
+fn newer(a: &str, b: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(a) > parse(b)
+}
@@ -0,0 +1,603 @@
+use anyhow::{bail, Context, Result};
+use azure_core::auth::{AccessToken, Secret, TokenCredential};
+use azure_core::{HttpClient, Method, Request, Url};
+use azure_identity::{
+    AzureCliCredential, ClientCertificateCredential, ClientCertificateCredentialOptions,
+    ClientSecretCredential, DefaultAzureCredential, EnvironmentCredential, TokenCredentialOptions,
+    WorkloadIdentityCredential,
+};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use serde::Deserialize;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tracing::{debug, info, instrument, warn};
+
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+
+/// Builds the Azure AD token credential used for storage calls.
+/// Implementations hold everything they need up front so building a credential is cheap.
+pub trait CredentialProvider: Send + Sync + std::fmt::Debug {
+    /// Short name used in logs
+    fn kind(&self) -> &'static str;
+
+    /// `options` carry the authority host of the target cloud and the HTTP client to use
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>>;
+
+    /// The same identity authenticating in another tenant (multi-tenant app registrations);
+    /// `None` when the credential is bound to its home tenant. Service principals and workload
+    /// identity can be retargeted (also when `environment` or `default` picks them up from the
+    /// `AZURE_*` variables); managed identity and the Azure CLI cannot.
+    fn for_tenant(&self, _tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        None
+    }
+}
+
+/// Auto-detecting chain (environment, workload identity, managed identity, Azure CLI)
+#[derive(Debug, Default)]
+pub struct DefaultProvider;
+
+impl CredentialProvider for DefaultProvider {
+    fn kind(&self) -> &'static str {
+        "default"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        let credential = DefaultAzureCredential::create(options)
+            .context("Failed to initialize DefaultAzureCredential")?;
+        Ok(Arc::new(credential))
+    }
+
+    /// The chain tries the environment first; the managed identity and CLI fallbacks are
+    /// bound to their home tenant
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        environment_for_tenant(tenant_id, |key| std::env::var(key).ok())
+    }
+}
+
+/// Service principal or workload identity from the standard `AZURE_*` variables
+#[derive(Debug, Default)]
+pub struct EnvironmentProvider;
+
+impl CredentialProvider for EnvironmentProvider {
+    fn kind(&self) -> &'static str {
+        "environment"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        let credential = EnvironmentCredential::create(options)
+            .context("Failed to initialize EnvironmentCredential")?;
+        Ok(Arc::new(credential))
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        environment_for_tenant(tenant_id, |key| std::env::var(key).ok())
+    }
+}
+
+/// The identity `EnvironmentCredential` would pick from the `AZURE_*` variables, in its order
+/// (workload identity, client secret, client certificate), authenticating in `tenant_id`
+fn environment_for_tenant(
+    tenant_id: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<Arc<dyn CredentialProvider>> {
+    let tenant_id = tenant_id.to_string();
+    let client_id = env("AZURE_CLIENT_ID")?;
+    if let Some(token) = env("AZURE_FEDERATED_TOKEN") {
+        return Some(Arc::new(WorkloadIdentityProvider {
+            tenant_id,
+            client_id,
+            token: FederatedToken::Inline(Secret::new(token)),
+        }));
+    }
+    if let Some(path) = env("AZURE_FEDERATED_TOKEN_FILE") {
+        return Some(Arc::new(WorkloadIdentityProvider {
+            tenant_id,
+            client_id,
+            token: FederatedToken::File(path),
+        }));
+    }
+    if let Some(client_secret) = env("AZURE_CLIENT_SECRET") {
+        return Some(Arc::new(ClientSecretProvider {
+            tenant_id,
+            client_id,
+            client_secret: Secret::new(client_secret),
+        }));
+    }
+    let path = env("AZURE_CLIENT_CERTIFICATE_PATH")?;
+    let der = std::fs::read(&path)
+        .map_err(|e| warn!(%path, error = %e, "Failed to read client certificate"))
+        .ok()?;
+    Some(Arc::new(ClientCertificateProvider {
+        tenant_id,
+        client_id,
+        certificate: Secret::new(azure_core::base64::encode(der)),
+        password: Secret::new(env("AZURE_CLIENT_CERTIFICATE_PASSWORD").unwrap_or_default()),
+    }))
+}
+
+/// The account signed in to the Azure CLI, for running the operator outside a cluster
+#[derive(Debug, Default)]
+pub struct AzureCliProvider;
+
+impl CredentialProvider for AzureCliProvider {
+    fn kind(&self) -> &'static str {
+        "azure-cli"
+    }
+
+    fn credential(&self, _options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        // The CLI does its own HTTP and authority handling
+        Ok(Arc::new(AzureCliCredential::new()))
+    }
+}
+
+/// Explicitly ordered sources, tried one after another until one issues a token.
+/// Unlike `DefaultProvider` the order is fixed by the operator, not by auto-detection.
+#[derive(Debug)]
+pub struct ChainProvider {
+    pub sources: Vec<Arc<dyn CredentialProvider>>,
+}
+
+impl CredentialProvider for ChainProvider {
+    fn kind(&self) -> &'static str {
+        "chain"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        let mut sources = Vec::with_capacity(self.sources.len());
+        for source in &self.sources {
+            match source.credential(options.clone()) {
+                Ok(credential) => sources.push((source.kind(), credential)),
+                Err(e) => debug!(source = source.kind(), error = %e, "Skipping credential source"),
+            }
+        }
+        if sources.is_empty() {
+            bail!("No source of the credential chain could be initialized");
+        }
+        Ok(Arc::new(ChainedCredential { sources }))
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        let sources: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|source| source.for_tenant(tenant_id))
+            .collect();
+        (!sources.is_empty()).then(|| Arc::new(Self { sources }) as Arc<dyn CredentialProvider>)
+    }
+}
+
+#[derive(Debug)]
+struct ChainedCredential {
+    sources: Vec<(&'static str, Arc<dyn TokenCredential>)>,
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for ChainedCredential {
+    async fn get_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let mut errors = Vec::new();
+        for (kind, credential) in &self.sources {
+            match credential.get_token(scopes).await {
+                Ok(token) => {
+                    debug!(source = kind, "Token issued by credential source");
+                    return Ok(token);
+                }
+                Err(e) => errors.push(format!("{kind}: {e}")),
+            }
+        }
+        Err(azure_core::Error::message(
+            azure_core::error::ErrorKind::Credential,
+            format!("All credential sources failed: {}", errors.join("; ")),
+        ))
+    }
+
+    async fn clear_cache(&self) -> azure_core::Result<()> {
+        for (_, credential) in &self.sources {
+            credential.clear_cache().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Service principal authenticating with a client secret
+#[derive(Debug, Clone)]
+pub struct ClientSecretProvider {
+    pub tenant_id: String,
+    pub client_id: String,
+    pub client_secret: Secret,
+}
+
+impl CredentialProvider for ClientSecretProvider {
+    fn kind(&self) -> &'static str {
+        "client-secret"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        Ok(Arc::new(ClientSecretCredential::new(
+            options.http_client(),
+            options.authority_host()?,
+            self.tenant_id.clone(),
+            self.client_id.clone(),
+            self.client_secret.secret().to_string(),
+        )))
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        Some(Arc::new(Self {
+            tenant_id: tenant_id.to_string(),
+            ..self.clone()
+        }))
+    }
+}
+
+/// Service principal authenticating with a certificate (base64-encoded PKCS#12)
+#[derive(Debug, Clone)]
+pub struct ClientCertificateProvider {
+    pub tenant_id: String,
+    pub client_id: String,
+    pub certificate: Secret,
+    pub password: Secret,
+}
+
+impl ClientCertificateProvider {
+    /// Builds the provider from a PEM certificate (chain) and private key, e.g. from a
+    /// `kubernetes.io/tls` Secret; the SDK only accepts PKCS#12, so the pair is converted
+    pub fn from_pem(
+        tenant_id: String,
+        client_id: String,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<Self> {
+        let key = PKey::private_key_from_pem(key_pem)
+            .context("Failed to parse client certificate private key")?;
+        let mut chain =
+            X509::stack_from_pem(cert_pem).context("Failed to parse client certificate")?;
+        if chain.is_empty() {
+            bail!("No certificate found in PEM data");
+        }
+        let leaf = chain.remove(0);
+
+        let mut builder = Pkcs12::builder();
+        builder.pkey(&key).cert(&leaf);
+        if !chain.is_empty() {
+            let mut ca = Stack::new()?;
+            for cert in chain {
+                ca.push(cert)?;
+            }
+            builder.ca(ca);
+        }
+        let der = builder
+            .build2("")
+            .and_then(|p| p.to_der())
+            .context("Failed to bundle client certificate as PKCS#12")?;
+
+        Ok(Self {
+            tenant_id,
+            client_id,
+            certificate: Secret::new(azure_core::base64::encode(der)),
+            password: Secret::new(""),
+        })
+    }
+}
+
+impl CredentialProvider for ClientCertificateProvider {
+    fn kind(&self) -> &'static str {
+        "client-certificate"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        let credential = ClientCertificateCredential::new(
+            self.tenant_id.clone(),
+            self.client_id.clone(),
+            self.certificate.clone(),
+            self.password.clone(),
+            ClientCertificateCredentialOptions::new(options, true),
+        )
+        .context("Failed to initialize ClientCertificateCredential")?;
+        Ok(Arc::new(credential))
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        Some(Arc::new(Self {
+            tenant_id: tenant_id.to_string(),
+            ..self.clone()
+        }))
+    }
+}
+
+/// Where the federated (Kubernetes ServiceAccount) token comes from
+#[derive(Debug, Clone)]
+pub enum FederatedToken {
+    /// Projected token file, rotated by the kubelet
+    File(String),
+    /// Token minted through the TokenRequest API
+    Inline(Secret),
+}
+
+/// Workload identity federation: a ServiceAccount token exchanged for an Azure AD token
+#[derive(Debug, Clone)]
+pub struct WorkloadIdentityProvider {
+    pub tenant_id: String,
+    pub client_id: String,
+    pub token: FederatedToken,
+}
+
+impl CredentialProvider for WorkloadIdentityProvider {
+    fn kind(&self) -> &'static str {
+        "workload-identity"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        let token = match &self.token {
+            // Projected tokens are rotated by the kubelet, so read the file every time
+            FederatedToken::File(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read federated token file {path}"))?,
+            FederatedToken::Inline(token) => token.secret().to_string(),
+        };
+        Ok(Arc::new(WorkloadIdentityCredential::new(
+            options.http_client(),
+            options.authority_host()?,
+            self.tenant_id.clone(),
+            self.client_id.clone(),
+            token.trim().to_string(),
+        )))
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Option<Arc<dyn CredentialProvider>> {
+        Some(Arc::new(Self {
+            tenant_id: tenant_id.to_string(),
+            ..self.clone()
+        }))
+    }
+}
+
+/// Which managed identity IMDS should issue a token for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManagedIdentityId {
+    SystemAssigned,
+    ClientId(String),
+    ResourceId(String),
+}
+
+/// Managed identity through IMDS. The SDK only exposes the system-assigned identity,
+/// so user-assigned identities (by client ID or resource ID) are requested directly.
+#[derive(Debug)]
+pub struct ManagedIdentityProvider {
+    pub id: ManagedIdentityId,
+}
+
+impl CredentialProvider for ManagedIdentityProvider {
+    fn kind(&self) -> &'static str {
+        "managed-identity"
+    }
+
+    fn credential(&self, options: TokenCredentialOptions) -> Result<Arc<dyn TokenCredential>> {
+        // IMDS is node-local, so only the HTTP client applies
+        Ok(Arc::new(ImdsCredential {
+            id: self.id.clone(),
+            http_client: options.http_client(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct ImdsCredential {
+    id: ManagedIdentityId,
+    http_client: Arc<dyn HttpClient>,
+}
+
+#[derive(Deserialize)]
+struct ImdsTokenResponse {
+    access_token: String,
+    expires_on: String,
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for ImdsCredential {
+    async fn get_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let resource = scopes
+            .first()
+            .map(|s| s.trim_end_matches("/.default"))
+            .unwrap_or_default();
+
+        let mut url = Url::parse(IMDS_ENDPOINT)?;
+        url.query_pairs_mut()
+            .append_pair("api-version", IMDS_API_VERSION)
+            .append_pair("resource", resource);
+        match &self.id {
+            ManagedIdentityId::SystemAssigned => {}
+            ManagedIdentityId::ClientId(id) => {
+                url.query_pairs_mut().append_pair("client_id", id);
+            }
+            ManagedIdentityId::ResourceId(id) => {
+                url.query_pairs_mut().append_pair("msi_res_id", id);
+            }
+        }
+
+        let mut request = Request::new(url, Method::Get);
+        request.insert_header("metadata", "true");
+
+        let response = self
+            .http_client
+            .execute_request_check_status(&request)
+            .await?;
+        let token: ImdsTokenResponse = serde_json::from_slice(response.body())?;
+
+        let expires_on = token
+            .expires_on
+            .parse::<i64>()
+            .ok()
+            .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
+            .unwrap_or_else(OffsetDateTime::now_utc);
+
+        Ok(AccessToken::new(
+            Secret::new(token.access_token),
+            expires_on,
+        ))
+    }
+
+    async fn clear_cache(&self) -> azure_core::Result<()> {
+        Ok(())
+    }
+}
+
+fn required_env(key: &str) -> Result<String> {
+    std::env::var(key).with_context(|| format!("{key} must be set for this credential kind"))
+}
+
+/// Builds the provider for one credential kind from its environment variables
+fn provider_for_kind(kind: &str) -> Result<Arc<dyn CredentialProvider>> {
+    let provider: Arc<dyn CredentialProvider> = match kind {
+        "default" => Arc::new(DefaultProvider),
+        "environment" => Arc::new(EnvironmentProvider),
+        "azure-cli" => Arc::new(AzureCliProvider),
+        "client-secret" => Arc::new(ClientSecretProvider {
+            tenant_id: required_env("AZURE_TENANT_ID")?,
+            client_id: required_env("AZURE_CLIENT_ID")?,
+            client_secret: Secret::new(required_env("AZURE_CLIENT_SECRET")?),
+        }),
+        "client-certificate" => {
+            let path = required_env("AZURE_CLIENT_CERTIFICATE_PATH")?;
+            let der = std::fs::read(&path)
+                .with_context(|| format!("Failed to read client certificate {path}"))?;
+            Arc::new(ClientCertificateProvider {
+                tenant_id: required_env("AZURE_TENANT_ID")?,
+                client_id: required_env("AZURE_CLIENT_ID")?,
+                certificate: Secret::new(azure_core::base64::encode(der)),
+                password: Secret::new(
+                    std::env::var("AZURE_CLIENT_CERTIFICATE_PASSWORD").unwrap_or_default(),
+                ),
+            })
+        }
+        "workload-identity" => Arc::new(WorkloadIdentityProvider {
+            tenant_id: required_env("AZURE_TENANT_ID")?,
+            client_id: required_env("AZURE_CLIENT_ID")?,
+            token: FederatedToken::File(required_env("AZURE_FEDERATED_TOKEN_FILE")?),
+        }),
+        "managed-identity" => {
+            let id = match (
+                std::env::var("AZURE_MANAGED_IDENTITY_CLIENT_ID"),
+                std::env::var("AZURE_MANAGED_IDENTITY_RESOURCE_ID"),
+            ) {
+                (Ok(client_id), _) => ManagedIdentityId::ClientId(client_id),
+                (_, Ok(resource_id)) => ManagedIdentityId::ResourceId(resource_id),
+                _ => ManagedIdentityId::SystemAssigned,
+            };
+            Arc::new(ManagedIdentityProvider { id })
+        }
+        other => bail!("Unknown credential kind '{other}'"),
+    };
+    Ok(provider)
+}
+
+/// Kinds that can be ordered in `AZURE_CREDENTIAL_CHAIN`; `default` is itself a chain
+const CHAIN_KINDS: &[&str] = &[
+    "environment",
+    "client-secret",
+    "client-certificate",
+    "workload-identity",
+    "managed-identity",
+    "azure-cli",
+];
+
+/// Builds a `ChainProvider` from a comma-separated list of kinds.
+/// Kinds whose variables are missing are left out so one chain works across environments.
+fn chain_from_list(list: &str) -> Result<ChainProvider> {
+    let mut sources = Vec::new();
+    for kind in list.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        if !CHAIN_KINDS.contains(&kind) {
+            bail!(
+                "'{kind}' cannot be part of AZURE_CREDENTIAL_CHAIN (expected one of {})",
+                CHAIN_KINDS.join(", ")
+            );
+        }
+        match provider_for_kind(kind) {
+            Ok(provider) => sources.push(provider),
+            Err(e) => warn!(%kind, error = %e, "Credential source left out of the chain"),
+        }
+    }
+    if sources.is_empty() {
+        bail!("AZURE_CREDENTIAL_CHAIN '{list}' has no usable credential source");
+    }
+    Ok(ChainProvider { sources })
+}
+
+/// Selects the operator-wide provider from `AZURE_CREDENTIAL_KIND` (default, environment,
+/// client-secret, client-certificate, workload-identity, managed-identity, azure-cli), or an
+/// explicit fallback order from `AZURE_CREDENTIAL_CHAIN` (e.g. `workload-identity,managed-identity`)
+#[instrument]
+pub fn provider_from_env() -> Result<Arc<dyn CredentialProvider>> {
+    let kind = std::env::var("AZURE_CREDENTIAL_KIND").ok();
+    let chain = std::env::var("AZURE_CREDENTIAL_CHAIN").ok();
+
+    let provider: Arc<dyn CredentialProvider> = match (kind, chain) {
+        (Some(_), Some(_)) => {
+            bail!("Set either AZURE_CREDENTIAL_KIND or AZURE_CREDENTIAL_CHAIN, not both")
+        }
+        (None, Some(list)) => {
+            debug!(%list, "Building operator credential chain");
+            let chain = chain_from_list(&list)?;
+            let order: Vec<&str> = chain.sources.iter().map(|s| s.kind()).collect();
+            info!(order = %order.join(","), "Operator credential chain selected");
+            Arc::new(chain)
+        }
+        (kind, None) => {
+            let kind = kind.unwrap_or_else(|| "default".into());
+            debug!(%kind, "Selecting operator credential provider");
+            provider_for_kind(&kind).map_err(|e| e.context("Invalid AZURE_CREDENTIAL_KIND"))?
+        }
+    };
+
+    info!(
+        kind = provider.kind(),
+        "Operator credential provider selected"
+    );
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn retarget(vars: &[(&str, &str)]) -> Option<&'static str> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        environment_for_tenant("contoso", |key| vars.get(key).cloned()).map(|p| p.kind())
+    }
+
+    #[test]
+    fn environment_identities_follow_the_tenant() {
+        let client = ("AZURE_CLIENT_ID", "app");
+        let token_file = ("AZURE_FEDERATED_TOKEN_FILE", "/var/run/token");
+        let secret = ("AZURE_CLIENT_SECRET", "hunter2");
+        assert_eq!(
+            retarget(&[client, token_file, secret]),
+            Some("workload-identity")
+        );
+        assert_eq!(retarget(&[client, secret]), Some("client-secret"));
+        assert_eq!(retarget(&[secret]), None);
+        assert_eq!(retarget(&[client]), None);
+        assert_eq!(
+            retarget(&[
+                client,
+                ("AZURE_CLIENT_CERTIFICATE_PATH", "/nonexistent/cert.pfx")
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    fn home_tenant_sources_cannot_be_retargeted() {
+        let imds = ManagedIdentityProvider {
+            id: ManagedIdentityId::SystemAssigned,
+        };
+        assert!(imds.for_tenant("contoso").is_none());
+        assert!(AzureCliProvider.for_tenant("contoso").is_none());
+    }
+}
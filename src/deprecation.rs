@@ -0,0 +1,110 @@
+use crate::crd::{ContextData, SasGenerator, SasGeneratorStatus};
+use crate::events::publish;
+use crate::status::{remove_condition, set_condition, CONDITION_DEPRECATION_WARNING};
+use kube::runtime::events::EventType;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+/// A deprecated field or behavior, what replaces it, and how to tell a CR still relies on it
+pub struct Deprecation {
+    /// Stable identifier, also used as the event reason suffix
+    pub id: &'static str,
+    pub subject: &'static str,
+    pub replacement: &'static str,
+    in_use: fn(&SasGenerator, &SasGeneratorStatus) -> bool,
+}
+
+impl Deprecation {
+    fn message(&self) -> String {
+        format!(
+            "[{}] {} is deprecated; {}",
+            self.id, self.subject, self.replacement
+        )
+    }
+}
+
+/// Every deprecation the operator reports. Removing legacy behavior starts by listing it here.
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        id: "IntegerHourTtl",
+        subject: "spec.sasTtlHours/spec.sasRenewalHours",
+        replacement: "use the duration fields of the v1beta1 API",
+        in_use: |sasgen, _| {
+            sasgen.spec.sas_ttl_hours.is_some() || sasgen.spec.sas_renewal_hours.is_some()
+        },
+    },
+    Deprecation {
+        id: "DefaultFullPermissions",
+        subject: "issuing tokens with every permission by default",
+        replacement: "list the permissions the token needs explicitly",
+        in_use: |sasgen, _| sasgen.spec.blob_scope.is_none(),
+    },
+];
+
+/// Deprecations the CR currently relies on
+pub fn in_use(sasgen: &SasGenerator, status: &SasGeneratorStatus) -> Vec<&'static Deprecation> {
+    DEPRECATIONS
+        .iter()
+        .filter(|d| (d.in_use)(sasgen, status))
+        .collect()
+}
+
+/// Sets or clears the non-blocking DeprecationWarning condition on `status`.
+/// Returns the deprecations that were not reported by the previous condition.
+pub fn sync_deprecation_condition(
+    sasgen: &SasGenerator,
+    status: &mut SasGeneratorStatus,
+    now: OffsetDateTime,
+) -> Vec<&'static Deprecation> {
+    let active = in_use(sasgen, status);
+    if active.is_empty() {
+        remove_condition(status, CONDITION_DEPRECATION_WARNING);
+        return Vec::new();
+    }
+
+    let previous = status
+        .conditions
+        .iter()
+        .find(|c| c.type_ == CONDITION_DEPRECATION_WARNING)
+        .and_then(|c| c.message.clone())
+        .unwrap_or_default();
+    let new: Vec<&'static Deprecation> = active
+        .iter()
+        .copied()
+        .filter(|d| !previous.contains(&format!("[{}]", d.id)))
+        .collect();
+
+    let message: Vec<String> = active.iter().map(|d| d.message()).collect();
+    set_condition(
+        status,
+        CONDITION_DEPRECATION_WARNING,
+        true,
+        "DeprecatedUsage",
+        message.join("\n"),
+        now,
+    );
+    new
+}
+
+/// Emits one Warning event per newly reported deprecation, naming its replacement
+pub async fn publish_deprecations(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    deprecations: &[&Deprecation],
+) {
+    for deprecation in deprecations {
+        warn!(id = deprecation.id, "CR relies on deprecated behavior");
+        publish(
+            sasgen,
+            ctx,
+            EventType::Warning,
+            &format!("Deprecated{}", deprecation.id),
+            "Reconcile",
+            deprecation.message(),
+        )
+        .await;
+    }
+    if !deprecations.is_empty() {
+        info!(count = deprecations.len(), "Reported deprecations");
+    }
+}
@@ -0,0 +1,428 @@
+use crate::bluegreen::current_tokens;
+use crate::crd::{
+    ContextData, DistributionState, SasGenerator, SasGeneratorStatus, SecretDistribution,
+    SecretTarget,
+};
+use crate::events::{publish, REASON_SECRET_UPDATED};
+use crate::reconcile::ReconcileError;
+use crate::remote::push_remote;
+use crate::sas::SasTokenInfo;
+use crate::secret::{ensure_secret, secret_data, SecretValues, TOKEN_CHECKSUM_ANNOTATION};
+use crate::utils::{format_rfc3339, token_hash};
+use crate::versioned::ensure_versioned_secret;
+use k8s_openapi::api::core::v1::Secret;
+use kube::runtime::events::EventType;
+use kube::runtime::reflector::ObjectRef;
+use kube::{Api, ResourceExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+
+/// Inputs of the last write of a Secret, so drift can be repaired without issuing new tokens
+#[derive(Debug, Clone)]
+pub struct WrittenSecret {
+    pub data: BTreeMap<String, String>,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// Last write of every Secret since the operator started, keyed by namespace and name. The
+/// tokens live nowhere else, so after a restart a deleted Secret needs new ones.
+#[derive(Debug, Default)]
+pub struct WrittenSecrets(Mutex<HashMap<(String, String), WrittenSecret>>);
+
+impl WrittenSecrets {
+    pub fn record(&self, target: &SecretTarget, written: WrittenSecret) {
+        self.lock()
+            .insert((target.namespace.clone(), target.name.clone()), written);
+    }
+
+    pub fn get(&self, namespace: &str, name: &str) -> Option<WrittenSecret> {
+        self.lock()
+            .get(&(namespace.to_string(), name.to_string()))
+            .cloned()
+    }
+
+    /// Forgets keys removed from the Secret since its last write
+    pub fn remove_keys(&self, target: &SecretTarget, keys: &[String]) {
+        let key = (target.namespace.clone(), target.name.clone());
+        if let Some(written) = self.lock().get_mut(&key) {
+            written.data.retain(|k, _| !keys.contains(k));
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, String), WrittenSecret>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Fingerprint of the whole `data` of a Secret, including keys written by others
+pub fn data_hash(secret: &Secret) -> String {
+    let entries: Vec<String> = secret
+        .data
+        .iter()
+        .flatten()
+        .map(|(key, value)| format!("{key}={}", String::from_utf8_lossy(&value.0)))
+        .collect();
+    token_hash(&entries.join("\n"))
+}
+
+/// A rollout that stopped part-way must be redone before the CR is considered up to date
+pub fn rollout_incomplete(status: Option<&SasGeneratorStatus>) -> bool {
+    status.is_some_and(|s| {
+        s.distribution
+            .iter()
+            .any(|d| d.state != DistributionState::Applied)
+    })
+}
+
+/// Outcome of checking the rolled-out Secrets against the cached cluster state
+pub enum Drift {
+    /// Every Secret still holds the data written last
+    None,
+    /// Drifted Secrets were rewritten with their last data; the updated distribution
+    Repaired(Vec<SecretDistribution>),
+    /// A drifted Secret has no copy of its last write, so it needs new tokens
+    Unrepairable,
+}
+
+/// Rolled-out Secrets that were deleted or whose data was edited, as seen by the Secret watch
+async fn drifted_secrets<'a>(
+    ctx: &ContextData,
+    distribution: &'a [SecretDistribution],
+) -> Vec<&'a SecretDistribution> {
+    // An unfilled cache would report every Secret as deleted
+    for store in &ctx.secrets {
+        if store.wait_until_ready().await.is_err() {
+            warn!("Secret cache was dropped; skipping drift detection");
+            return Vec::new();
+        }
+    }
+    distribution
+        .iter()
+        .filter(|d| d.state == DistributionState::Applied)
+        // Secrets outside the watched namespaces are not cached, so their drift is not detected
+        .filter(|d| ctx.watch_namespaces.is_empty() || ctx.watch_namespaces.contains(&d.namespace))
+        .filter(|applied| {
+            let key = ObjectRef::new(&applied.secret).within(&applied.namespace);
+            let observed = ctx.secrets.iter().find_map(|store| store.get(&key));
+            let drifted = match (&observed, &applied.data_hash) {
+                (None, _) => true,
+                (Some(secret), Some(recorded)) => data_hash(secret) != *recorded,
+                // Written before data hashes were recorded; only deletion is detectable
+                (Some(_), None) => false,
+            };
+            if drifted {
+                info!(
+                    secret = %applied.secret,
+                    namespace = %applied.namespace,
+                    deleted = observed.is_none(),
+                    "Secret drifted from the last rollout"
+                );
+            }
+            drifted
+        })
+        .collect()
+}
+
+/// Rewrites deleted or edited Secrets with the data of their last write. Label and
+/// annotation edits are not drift; the owned-Secret watch triggers this right after a change.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn repair_drift(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    status: Option<&SasGeneratorStatus>,
+) -> Result<Drift, ReconcileError> {
+    let Some(status) = status else {
+        return Ok(Drift::None);
+    };
+    let drifted = drifted_secrets(ctx, &status.distribution).await;
+    if drifted.is_empty() {
+        return Ok(Drift::None);
+    }
+    let Some(writes) = drifted
+        .iter()
+        .map(|d| ctx.written.get(&d.namespace, &d.secret))
+        .collect::<Option<Vec<_>>>()
+    else {
+        info!("No copy of a drifted Secret's last write; issuing new tokens");
+        return Ok(Drift::Unrepairable);
+    };
+
+    let mut distribution = status.distribution.clone();
+    for (applied, written) in drifted.into_iter().zip(writes) {
+        let target = SecretTarget {
+            namespace: applied.namespace.clone(),
+            name: applied.secret.clone(),
+            containers: Vec::new(),
+        };
+        let resource_version = ensure_secret(
+            sasgen,
+            ctx,
+            &target,
+            written.data,
+            written.labels,
+            written.annotations,
+        )
+        .await?;
+        let (resource_version, hash) = confirm_secret(ctx, &target, resource_version).await?;
+        publish(
+            sasgen,
+            ctx,
+            EventType::Normal,
+            REASON_SECRET_UPDATED,
+            "Repair",
+            format!(
+                "Restored drifted Secret {}/{}",
+                target.namespace, target.name
+            ),
+        )
+        .await;
+        if let Some(state) = distribution
+            .iter_mut()
+            .find(|d| d.namespace == target.namespace && d.secret == target.name)
+        {
+            state.resource_version = resource_version;
+            state.data_hash = Some(hash);
+        }
+    }
+    Ok(Drift::Repaired(distribution))
+}
+
+/// Orders targets by `rollout.namespaceOrder`; unlisted namespaces keep their relative order
+fn ordered_targets<'a>(
+    sasgen: &SasGenerator,
+    targets: &'a [SecretTarget],
+) -> Vec<&'a SecretTarget> {
+    let order = sasgen
+        .spec
+        .rollout
+        .as_ref()
+        .and_then(|r| r.namespace_order.clone())
+        .unwrap_or_default();
+
+    let mut ordered: Vec<&SecretTarget> = targets.iter().collect();
+    ordered.sort_by_key(|t| {
+        order
+            .iter()
+            .position(|ns| *ns == t.namespace)
+            .unwrap_or(order.len())
+    });
+    ordered
+}
+
+/// Re-reads the Secret so the recorded resourceVersion is what the API server actually serves
+async fn confirm_secret(
+    ctx: &ContextData,
+    target: &SecretTarget,
+    written: Option<String>,
+) -> Result<(Option<String>, String), ReconcileError> {
+    let api: Api<Secret> = Api::namespaced(ctx.client.clone(), &target.namespace);
+    let secret = api.get(&target.name).await?;
+    let observed = secret.resource_version();
+    if observed != written {
+        warn!(secret = %target.name, ?written, ?observed, "Secret changed again after our write");
+    }
+    debug!(secret = %target.name, ?observed, "Confirmed Secret write");
+    Ok((observed, data_hash(&secret)))
+}
+
+/// Writes every target Secret in rollout order, confirming each write before moving on.
+/// Returns the per-Secret states together with the first error, if any.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any(), targets = targets.len()))]
+pub async fn distribute(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    targets: &[SecretTarget],
+    tokens: &[(String, SasTokenInfo)],
+    base_values: &SecretValues,
+    annotations: &BTreeMap<String, String>,
+) -> (Vec<SecretDistribution>, Result<(), ReconcileError>) {
+    let abort_on_error = sasgen
+        .spec
+        .rollout
+        .as_ref()
+        .and_then(|r| r.abort_on_error)
+        .unwrap_or(true);
+
+    let mut states = Vec::new();
+    let mut first_error = None;
+
+    for target in ordered_targets(sasgen, targets) {
+        let mut state = SecretDistribution {
+            namespace: target.namespace.clone(),
+            secret: target.name.clone(),
+            state: DistributionState::Pending,
+            resource_version: None,
+            data_hash: None,
+            message: None,
+        };
+
+        if first_error.is_some() && abort_on_error {
+            states.push(state);
+            continue;
+        }
+
+        let target_tokens: Vec<(String, String)> = tokens
+            .iter()
+            .filter(|(container, _)| target.containers.contains(container))
+            .map(|(container, info)| (container.clone(), info.token.clone()))
+            .collect();
+        let previous_tokens = if sasgen.blue_green() {
+            match current_tokens(sasgen, ctx, target).await {
+                Ok(previous) => previous,
+                Err(e) => {
+                    warn!(secret = %target.name, %e, "Failed to read the tokens being replaced");
+                    state.state = DistributionState::Failed;
+                    state.message = Some(e.to_string());
+                    first_error.get_or_insert(e);
+                    states.push(state);
+                    continue;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let values = SecretValues {
+            tokens: target_tokens,
+            previous_tokens,
+            expiry: tokens
+                .first()
+                .map(|(_, info)| format_rfc3339(info.expiry))
+                .unwrap_or_default(),
+            ..base_values.clone()
+        };
+
+        let result = async {
+            let data = secret_data(sasgen, &values)?;
+            let labels = sasgen.secret_labels(target);
+            let mut annotations = annotations.clone();
+            annotations.insert(TOKEN_CHECKSUM_ANNOTATION.into(), values.token_checksum());
+            let written = if sasgen.spec.immutable_secrets.unwrap_or(false) {
+                ensure_versioned_secret(
+                    sasgen,
+                    ctx,
+                    target,
+                    data.clone(),
+                    labels.clone(),
+                    annotations,
+                )
+                .await?
+            } else {
+                ensure_secret(
+                    sasgen,
+                    ctx,
+                    target,
+                    data.clone(),
+                    labels.clone(),
+                    annotations,
+                )
+                .await?
+            };
+            let confirmed = confirm_secret(ctx, target, written).await?;
+            // Remote clusters get the plain payload; versioning only applies locally
+            push_remote(sasgen, ctx, target, &data, &labels).await?;
+            Ok::<_, ReconcileError>(confirmed)
+        }
+        .await;
+
+        match result {
+            Ok((resource_version, hash)) => {
+                publish(
+                    sasgen,
+                    ctx,
+                    EventType::Normal,
+                    REASON_SECRET_UPDATED,
+                    "Rollout",
+                    format!("Wrote Secret {}/{}", target.namespace, target.name),
+                )
+                .await;
+                state.state = DistributionState::Applied;
+                state.resource_version = resource_version;
+                state.data_hash = Some(hash);
+            }
+            Err(e) => {
+                warn!(secret = %target.name, namespace = %target.namespace, %e, "Secret rollout failed");
+                state.state = DistributionState::Failed;
+                state.message = Some(e.to_string());
+                first_error.get_or_insert(e);
+            }
+        }
+        states.push(state);
+    }
+
+    info!(
+        applied = states
+            .iter()
+            .filter(|s| s.state == DistributionState::Applied)
+            .count(),
+        total = states.len(),
+        "Secret rollout finished"
+    );
+
+    (states, first_error.map_or(Ok(()), Err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::ByteString;
+
+    fn secret(data: &[(&str, &str)]) -> Secret {
+        Secret {
+            data: Some(
+                data.iter()
+                    .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn target() -> SecretTarget {
+        SecretTarget {
+            name: "sas".into(),
+            namespace: "apps".into(),
+            containers: vec!["logs".into()],
+        }
+    }
+
+    #[test]
+    fn data_hash_ignores_metadata_but_not_values() {
+        let written = secret(&[("sasToken", "sv=1&sig=a"), ("accountName", "acct")]);
+        let mut relabelled = written.clone();
+        relabelled.metadata.resource_version = Some("42".into());
+        relabelled.metadata.labels = Some([("team".into(), "x".into())].into());
+        assert_eq!(data_hash(&written), data_hash(&relabelled));
+
+        let edited = secret(&[("sasToken", "sv=1&sig=b"), ("accountName", "acct")]);
+        assert_ne!(data_hash(&written), data_hash(&edited));
+        let extended = secret(&[
+            ("sasToken", "sv=1&sig=a"),
+            ("accountName", "acct"),
+            ("extra", "1"),
+        ]);
+        assert_ne!(data_hash(&written), data_hash(&extended));
+    }
+
+    #[test]
+    fn written_secrets_forget_removed_keys() {
+        let written = WrittenSecrets::default();
+        written.record(
+            &target(),
+            WrittenSecret {
+                data: [
+                    ("sasToken".into(), "new".into()),
+                    ("previousSasToken".into(), "old".into()),
+                ]
+                .into(),
+                labels: BTreeMap::new(),
+                annotations: BTreeMap::new(),
+            },
+        );
+        written.remove_keys(&target(), &["previousSasToken".into()]);
+        let cached = written.get("apps", "sas").expect("recorded");
+        assert_eq!(cached.data.keys().collect::<Vec<_>>(), ["sasToken"]);
+        assert!(written.get("other", "sas").is_none());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::crd::{ContextData, SasGenerator};
+use kube::runtime::events::{Event, EventType};
+use kube::{Resource, ResourceExt};
+use tracing::warn;
+
+/// First token issued for a CR
+pub const REASON_TOKEN_GENERATED: &str = "TokenGenerated";
+/// A token was replaced by a new one
+pub const REASON_TOKEN_RENEWED: &str = "TokenRenewed";
+/// A reconcile failed; the note carries the error
+pub const REASON_GENERATION_FAILED: &str = "GenerationFailed";
+/// One target Secret was written
+pub const REASON_SECRET_UPDATED: &str = "SecretUpdated";
+
+/// Publishes an event on the CR. Events are informational, so failures are only logged.
+pub async fn publish(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    type_: EventType,
+    reason: &str,
+    action: &str,
+    note: impl Into<String>,
+) {
+    let event = Event {
+        type_,
+        reason: reason.to_string(),
+        note: Some(note.into()),
+        action: action.to_string(),
+        secondary: None,
+    };
+
+    if let Err(e) = ctx.recorder.publish(&event, &sasgen.object_ref(&())).await {
+        warn!(cr_name = %sasgen.name_any(), %reason, ?e, "Failed to publish event");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::crd::{ContextData, SasGenerator};
+use crate::credentials::{
+    ClientCertificateProvider, ClientSecretProvider, CredentialProvider, FederatedToken,
+    ManagedIdentityId, ManagedIdentityProvider, WorkloadIdentityProvider,
+};
+use crate::http::new_http_client;
+use crate::reconcile::ReconcileError;
+use crate::redact::redact;
+use crate::sas::{account_key_from_connection_string, StorageAuth};
+use crate::secret::{read_secret_data, read_secret_key};
+use azure_core::auth::Secret;
+use azure_identity::TokenCredentialOptions;
+use k8s_openapi::api::authentication::v1::{TokenRequest, TokenRequestSpec};
+use k8s_openapi::api::core::v1::ServiceAccount;
+use kube::api::{Api, PostParams};
+use kube::ResourceExt;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+/// Audience Azure AD expects on federated ServiceAccount tokens
+const AZURE_TOKEN_EXCHANGE_AUDIENCE: &str = "api://AzureADTokenExchange";
+
+/// Annotation a ServiceAccount must carry before CRs may federate with it: a comma-separated
+/// list of SasGenerator names in its namespace, or `*` for all of them. Without it any CR
+/// author could borrow any ServiceAccount's Azure identity through the operator.
+pub const ALLOWED_GENERATORS_ANNOTATION: &str = "sas.azure.com/allowed-sasgenerators";
+
+/// Requested lifetime of federated ServiceAccount tokens; a fresh one is minted every reconcile
+const FEDERATED_TOKEN_TTL_SECONDS: i64 = 3600;
+
+/// Picks the credential provider for this CR, retargeted at `spec.tenantId` when set
+async fn credential_provider(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+) -> Result<Arc<dyn CredentialProvider>, ReconcileError> {
+    let provider = identity_provider(sasgen, ctx).await?;
+    let Some(tenant_id) = &sasgen.spec.tenant_id else {
+        return Ok(provider);
+    };
+
+    info!(%tenant_id, kind = provider.kind(), "Authenticating in the CR's tenant");
+    provider.for_tenant(tenant_id).ok_or_else(|| {
+        ReconcileError::Credentials(format!(
+            "{} credentials cannot authenticate in another tenant; spec.tenantId needs a \
+             service principal or workload identity",
+            provider.kind()
+        ))
+    })
+}
+
+/// The operator-wide provider unless the CR brings its own identity
+async fn identity_provider(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+) -> Result<Arc<dyn CredentialProvider>, ReconcileError> {
+    if let Some(identity) = &sasgen.spec.azure_identity {
+        match (&identity.service_account_name, &identity.client_id) {
+            (Some(service_account), Some(client_id)) => {
+                return federated_provider(sasgen, ctx, service_account, client_id).await;
+            }
+            (None, Some(client_id)) => {
+                info!(%client_id, "Using the CR's user-assigned managed identity");
+                return Ok(Arc::new(ManagedIdentityProvider {
+                    id: ManagedIdentityId::ClientId(client_id.clone()),
+                }));
+            }
+            _ => {}
+        }
+    }
+    if let Some(secret_ref) = &sasgen.spec.credentials_secret_ref {
+        let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+        return service_principal_provider(ctx, &ns, &secret_ref.name).await;
+    }
+    Ok(ctx.credentials.clone())
+}
+
+/// Service principal from a Secret with `tenantId` and `clientId`, authenticating with
+/// `clientSecret` or, when present, the certificate in `tls.crt`/`tls.key`
+async fn service_principal_provider(
+    ctx: &ContextData,
+    ns: &str,
+    name: &str,
+) -> Result<Arc<dyn CredentialProvider>, ReconcileError> {
+    let missing =
+        |key: &str| ReconcileError::Credentials(format!("Secret {ns}/{name} has no '{key}' key"));
+    let mut data = read_secret_data(ctx, ns, name)
+        .await?
+        .ok_or_else(|| ReconcileError::Credentials(format!("Secret {ns}/{name} not found")))?;
+    let certificate = data.remove("tls.crt").zip(data.remove("tls.key"));
+    let text = |key: &str| {
+        data.get(key)
+            .map(|v| String::from_utf8_lossy(v).trim().to_string())
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| missing(key))
+    };
+    let tenant_id = text("tenantId")?;
+    let client_id = text("clientId")?;
+
+    if let Some((cert, key)) = certificate {
+        info!(secret = %name, "Using the CR's service principal certificate");
+        let provider = ClientCertificateProvider::from_pem(tenant_id, client_id, &cert, &key)
+            .map_err(|e| {
+                ReconcileError::Credentials(redact(&format!("Secret {ns}/{name}: {e:#}")).into())
+            })?;
+        return Ok(Arc::new(provider));
+    }
+
+    info!(secret = %name, "Using the CR's service principal client secret");
+    Ok(Arc::new(ClientSecretProvider {
+        tenant_id,
+        client_id,
+        client_secret: Secret::new(text("clientSecret")?),
+    }))
+}
+
+/// Whether the ServiceAccount lists the CR in `ALLOWED_GENERATORS_ANNOTATION`
+fn allows_generator(service_account: &ServiceAccount, generator: &str) -> bool {
+    service_account
+        .annotations()
+        .get(ALLOWED_GENERATORS_ANNOTATION)
+        .is_some_and(|allowed| {
+            allowed
+                .split(',')
+                .map(str::trim)
+                .any(|name| name == "*" || name == generator)
+        })
+}
+
+/// Workload identity federation for the CR's namespace: a ServiceAccount token minted through
+/// the TokenRequest API is exchanged for an Azure AD token of the federated app `client_id`.
+/// The ServiceAccount must opt in with `ALLOWED_GENERATORS_ANNOTATION`.
+#[instrument(skip(sasgen, ctx))]
+async fn federated_provider(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    service_account: &str,
+    client_id: &str,
+) -> Result<Arc<dyn CredentialProvider>, ReconcileError> {
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let tenant_id = sasgen
+        .spec
+        .tenant_id
+        .clone()
+        .or_else(|| std::env::var("AZURE_TENANT_ID").ok())
+        .ok_or_else(|| {
+            ReconcileError::Credentials(
+                "workload identity federation needs spec.tenantId or AZURE_TENANT_ID".into(),
+            )
+        })?;
+
+    let api: Api<ServiceAccount> = Api::namespaced(ctx.client.clone(), &ns);
+    let account = api.get_opt(service_account).await?.ok_or_else(|| {
+        ReconcileError::Credentials(format!("ServiceAccount {ns}/{service_account} not found"))
+    })?;
+    if !allows_generator(&account, &sasgen.name_any()) {
+        return Err(ReconcileError::Credentials(format!(
+            "ServiceAccount {ns}/{service_account} does not allow this SasGenerator; \
+             add its name to the {ALLOWED_GENERATORS_ANNOTATION} annotation"
+        )));
+    }
+    let request = TokenRequest {
+        spec: TokenRequestSpec {
+            audiences: vec![AZURE_TOKEN_EXCHANGE_AUDIENCE.to_string()],
+            bound_object_ref: None,
+            expiration_seconds: Some(FEDERATED_TOKEN_TTL_SECONDS),
+        },
+        ..Default::default()
+    };
+    let token = api
+        .create_token_request(service_account, &PostParams::default(), &request)
+        .await?
+        .status
+        .map(|s| s.token)
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| {
+            ReconcileError::Credentials(format!(
+                "TokenRequest for ServiceAccount {ns}/{service_account} returned no token"
+            ))
+        })?;
+
+    info!(%ns, %service_account, "Using workload identity federation with the CR's ServiceAccount");
+    Ok(Arc::new(WorkloadIdentityProvider {
+        tenant_id,
+        client_id: client_id.to_string(),
+        token: FederatedToken::Inline(Secret::new(token)),
+    }))
+}
+
+/// Picks how this CR authenticates: a referenced account key (directly or from a connection
+/// string), otherwise an Azure AD credential
+pub async fn storage_auth(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+) -> Result<StorageAuth, ReconcileError> {
+    if let Some(key_ref) = &sasgen.spec.account_key_secret_ref {
+        let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+        let key = key_ref.key.as_deref().unwrap_or("accountKey");
+        info!(secret = %key_ref.name, "Signing with the account key");
+        return Ok(StorageAuth::AccountKey(Secret::new(
+            required_secret_key(ctx, &ns, &key_ref.name, key).await?,
+        )));
+    }
+    if let Some(cs_ref) = &sasgen.spec.connection_string_secret_ref {
+        let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+        let key = cs_ref.key.as_deref().unwrap_or("connectionString");
+        info!(secret = %cs_ref.name, "Signing with the account key from a connection string");
+        let value = required_secret_key(ctx, &ns, &cs_ref.name, key).await?;
+        return account_key_from_connection_string(&value, &sasgen.spec.storage_account)
+            .map(StorageAuth::AccountKey)
+            .map_err(|e| {
+                ReconcileError::Credentials(
+                    redact(&format!("Secret {ns}/{}: {e:#}", cs_ref.name)).into(),
+                )
+            });
+    }
+    let mut options = TokenCredentialOptions::from(new_http_client());
+    if let Some(cloud) = sasgen.spec.cloud {
+        options.set_authority_host(cloud.authority_host().to_string());
+    }
+    Ok(StorageAuth::Aad {
+        provider: credential_provider(sasgen, ctx).await?,
+        options,
+    })
+}
+
+/// Reads a credential field from a referenced Secret, failing the reconcile when it is missing
+async fn required_secret_key(
+    ctx: &ContextData,
+    ns: &str,
+    name: &str,
+    key: &str,
+) -> Result<String, ReconcileError> {
+    read_secret_key(ctx, ns, name, key)
+        .await?
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| {
+            ReconcileError::Credentials(format!("Secret {ns}/{name} has no '{key}' key"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_account(allowed: Option<&str>) -> ServiceAccount {
+        let mut account = ServiceAccount::default();
+        if let Some(allowed) = allowed {
+            account.metadata.annotations =
+                Some([(ALLOWED_GENERATORS_ANNOTATION.into(), allowed.into())].into());
+        }
+        account
+    }
+
+    #[test]
+    fn service_accounts_must_opt_in() {
+        assert!(!allows_generator(&service_account(None), "logs"));
+        assert!(!allows_generator(&service_account(Some("")), "logs"));
+        assert!(!allows_generator(
+            &service_account(Some("logs-reader")),
+            "logs"
+        ));
+    }
+
+    #[test]
+    fn opt_in_lists_names_or_wildcard() {
+        assert!(allows_generator(&service_account(Some("logs")), "logs"));
+        assert!(allows_generator(
+            &service_account(Some("a, logs ,b")),
+            "logs"
+        ));
+        assert!(allows_generator(&service_account(Some("*")), "logs"));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::crd::{ContextData, SasGenerator, SasGeneratorStatus, SecretKeyRef, SecretTarget};
+use crate::reconcile::ReconcileError;
+use crate::sas::parse_token_validity;
+use crate::secret::{
+    ensure_secret, read_secret_key, secret_data, SecretValues, TOKEN_CHECKSUM_ANNOTATION,
+};
+use crate::status::update_crd_status;
+use crate::utils::{format_rfc3339, to_time, token_hash};
+use crate::versioned::ensure_versioned_secret;
+use kube::ResourceExt;
+use time::{Duration, OffsetDateTime};
+use tracing::{info, instrument, warn};
+
+/// Adopts an externally issued token from `spec.importSecretRef` so migrating CRs do not
+/// rotate immediately. Returns `false` when nothing was imported and normal issuance applies.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any(), secret = %import.name))]
+pub async fn import_token(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    import: &SecretKeyRef,
+    targets: &[SecretTarget],
+    base_values: &SecretValues,
+    renewal_hours: i64,
+    now: OffsetDateTime,
+) -> Result<bool, ReconcileError> {
+    let [target] = targets else {
+        warn!("Token import requires a single target Secret; issuing a new token instead");
+        return Ok(false);
+    };
+    let [container] = target.containers.as_slice() else {
+        warn!("Token import requires a single container; issuing a new token instead");
+        return Ok(false);
+    };
+
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let key = import.key.as_deref().unwrap_or("sas_token");
+    let Some(token) = read_secret_key(ctx, &ns, &import.name, key).await? else {
+        return Ok(false);
+    };
+
+    let Some((start, expiry)) = parse_token_validity(&token) else {
+        warn!("Imported token has no parsable expiry (se); issuing a new token instead");
+        return Ok(false);
+    };
+    if now >= expiry - Duration::hours(renewal_hours) {
+        info!(%expiry, "Imported token is already within the renewal window; not adopting it");
+        return Ok(false);
+    }
+
+    let status = SasGeneratorStatus {
+        token_hash: Some(token_hash(&token)),
+        target_secret: Some(target.name.clone()),
+        target_secrets: Vec::new(),
+        generated: Some(to_time(start.unwrap_or(now))),
+        expiry: Some(to_time(expiry)),
+        imported_from: Some(import.name.clone()),
+        next_renewal_time: Some(format_rfc3339(expiry - Duration::hours(renewal_hours))),
+        ..sasgen.status.clone().unwrap_or_default()
+    };
+
+    let values = SecretValues {
+        tokens: vec![(container.clone(), token)],
+        expiry: format_rfc3339(expiry),
+        ..base_values.clone()
+    };
+    let data = secret_data(sasgen, &values)?;
+
+    update_crd_status(sasgen, ctx, status.clone()).await?;
+    let labels = sasgen.secret_labels(target);
+    let mut annotations = sasgen.secret_annotations(&status);
+    annotations.insert(TOKEN_CHECKSUM_ANNOTATION.into(), values.token_checksum());
+    if sasgen.spec.immutable_secrets.unwrap_or(false) {
+        ensure_versioned_secret(sasgen, ctx, target, data, labels, annotations).await?;
+    } else {
+        ensure_secret(sasgen, ctx, target, data, labels, annotations).await?;
+    }
+
+    info!(%expiry, "Adopted imported SAS token; rotation takes over near expiry");
+    Ok(true)
+}
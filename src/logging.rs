@@ -0,0 +1,170 @@
+use crate::redact::RedactingWriter;
+use crate::utils::format_rfc3339;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Value};
+use std::fmt;
+use time::OffsetDateTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Installs the global subscriber: human-readable text, or with `LOG_FORMAT=json` one JSON
+/// object per line, with secrets redacted either way. `RUST_LOG` overrides `default_level`.
+/// With `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also exported over OTLP/HTTP; keep the
+/// returned guard alive until exit so the last batch is flushed.
+pub fn init(writer: BoxMakeWriter, default_level: &str) -> Telemetry {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level));
+    let logs = tracing_subscriber::fmt::layer()
+        .with_writer(RedactingWriter(writer))
+        .with_target(false);
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> =
+        if std::env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+            vec![logs
+                .fmt_fields(JsonFields::new())
+                .event_format(FlatJson)
+                .boxed()]
+        } else {
+            vec![logs.with_ansi(true).boxed()]
+        };
+
+    let provider = match otlp_provider() {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("OTLP trace export disabled: {e}");
+            None
+        }
+    };
+    if let Some(provider) = &provider {
+        let tracer = provider.tracer("sas-operator");
+        // Only spans are exported: their fields are identifiers, while event messages may
+        // quote Azure errors and are left to the redacted logs
+        layers.push(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(filter_fn(|metadata| metadata.is_span()))
+                .boxed(),
+        );
+    }
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+    Telemetry(provider)
+}
+
+/// Flushes exported spans when dropped
+pub struct Telemetry(Option<SdkTracerProvider>);
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.0.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush OTLP spans: {e}");
+            }
+        }
+    }
+}
+
+/// Span exporter configured by the standard `OTEL_*` variables; `None` unless an endpoint is
+/// set. The service is named `sas-operator` unless `OTEL_SERVICE_NAME` says otherwise.
+fn otlp_provider() -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|key| std::env::var(key).is_ok_and(|v| !v.is_empty()));
+    if !configured {
+        return Ok(None);
+    }
+    let exporter = SpanExporter::builder().with_http().build()?;
+    let mut resource = Resource::builder();
+    if std::env::var("OTEL_SERVICE_NAME").is_err() {
+        resource = resource.with_service_name("sas-operator");
+    }
+    Ok(Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource.build())
+            .build(),
+    ))
+}
+
+/// JSON lines with the fields of the event and of every enclosing span (`cr_name`,
+/// `account`, `container`, ...) as top-level keys, so log pipelines can index them without
+/// unnesting; inner spans and the event win over outer spans on conflicts
+struct FlatJson;
+
+impl<S, N> FormatEvent<S, N> for FlatJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            format_rfc3339(OffsetDateTime::now_utc()).into(),
+        );
+        line.insert("level".into(), event.metadata().level().as_str().into());
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = Vec::new();
+            for span in scope.from_root() {
+                spans.push(span.name());
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .and_then(|fields| serde_json::from_str::<Map<String, Value>>(fields).ok());
+                line.extend(fields.into_iter().flatten());
+            }
+            line.insert("span".into(), spans.join(":").into());
+        }
+        event.record(&mut JsonVisitor(&mut line));
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+}
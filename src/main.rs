@@ -1,78 +1,179 @@
+mod admin;
+mod audit;
+mod backoff;
+mod bluegreen;
+mod circuit;
+mod cleanup;
+mod cli;
+mod config;
+mod controller;
+mod conversion;
 mod crd;
+mod crdinstall;
+mod credentials;
+mod deprecation;
+mod distribute;
+mod events;
+mod http;
+mod identity;
+mod import;
+mod logging;
+mod manifests;
+mod metrics;
+mod output;
+mod policy;
+mod ratelimit;
+mod rbac;
 mod reconcile;
+mod redact;
+mod remote;
 mod sas;
+mod schema;
 mod secret;
+mod sentry;
+mod shard;
+mod signature;
 mod status;
+mod template;
 mod utils;
+mod validate;
+mod versioned;
+mod watchdog;
+mod webhook;
+mod webhookcert;
 
-use crate::crd::{generate_crd, ContextData, SasGenerator};
-use crate::reconcile::{error_policy, reconcile};
-use futures::StreamExt;
-use kube::{
-    api::Api, runtime::controller::Controller, runtime::watcher::Config as WatcherConfig, Client,
-};
+use crate::config::Config;
+use crate::crd::{generate_crd, ContextData};
+use azure_identity::TokenCredentialOptions;
+use kube::Client;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
-
-fn env_var_or_default<T: std::str::FromStr>(key: &str, default: T) -> T {
-    std::env::var(key)
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(default)
-}
-
-struct Config {
-    sas_renewal_hours: i64,
-    sas_ttl_hours: i64,
-}
-
-impl Config {
-    fn from_env() -> Self {
-        Self {
-            sas_renewal_hours: env_var_or_default("SAS_RENEWAL_HOURS", 24),
-            sas_ttl_hours: env_var_or_default("SAS_TTL_HOURS", 48),
-        }
-    }
-}
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .with_target(false)
-        .with_ansi(true)
-        .init();
+    let mut args = std::env::args().skip(1);
+    let command = args.next().filter(|arg| !arg.starts_with("--"));
+    // Subcommands print their results on stdout, so their logs go to stderr and stay quiet
+    let (writer, default_level) = match command {
+        Some(_) => (BoxMakeWriter::new(std::io::stderr), "warn"),
+        None => (BoxMakeWriter::new(std::io::stdout), "info"),
+    };
+    let _telemetry = logging::init(writer, default_level);
 
     if std::env::args().any(|arg| arg == "--crd") {
+        warn!("--crd is deprecated; use `crd -o crd.yaml` instead");
         generate_crd()?;
         return Ok(());
     }
 
-    let client = Client::try_default().await?;
     let config = Config::from_env();
+    if let Some(shard) = config.shard {
+        if shard.index >= shard.count {
+            return Err(format!(
+                "SHARD_INDEX must be below SHARD_COUNT ({}); set it or run as a StatefulSet",
+                shard.count
+            )
+            .into());
+        }
+        info!(?shard, "Reconciling one shard of the SasGenerators");
+    }
+    if let Some(command) = command {
+        if let Err(e) = cli::run(&command, args.collect(), config).await {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if std::env::args().any(|arg| arg == "--print-rbac") {
+        print!("{}", rbac::render(&config)?);
+        return Ok(());
+    }
+
+    if std::env::args().any(|arg| arg == "--webhook") {
+        let client = Client::try_default().await?;
+        return webhook::serve(config, client).await;
+    }
 
+    http::init_proxy(config.proxy.clone());
+    ratelimit::init_rate_limit(config.azure_requests_per_minute);
+    if let Some(path) = &config.audit_log_file {
+        audit::init_audit_log(path)?;
+    }
+    sentry::init_sentry(&config.sentry)?;
+    let client = Client::try_default().await?;
+    if config.install_crds {
+        crdinstall::install_crds(&client, &config).await?;
+    }
+
+    let watchdog = Arc::new(watchdog::Watchdog::new(config.watchdog_silence_seconds));
+    let circuit = Arc::new(circuit::CircuitBreaker::new(
+        config.circuit_breaker_threshold,
+        config.circuit_breaker_cooldown_seconds,
+    ));
+    let backoff = Arc::new(backoff::ErrorBackoff::default());
+    let controllers = controller::Controllers::new(&client, &config, &watchdog, &circuit, &backoff);
     let context = Arc::new(ContextData::new(
         client.clone(),
-        config.sas_renewal_hours,
-        config.sas_ttl_hours,
+        &config,
+        credentials::provider_from_env()?,
+        controllers.secrets.clone(),
+        circuit,
+        backoff,
     ));
-    let cr_api = Api::<SasGenerator>::all(client.clone());
-
-    let controller = Controller::new(cr_api, WatcherConfig::default())
-        .run(reconcile, error_policy, context)
-        .for_each(|res| async move {
-            match res {
-                Ok((_obj_ref, action)) => info!(?action, "Reconciliation complete"),
-                Err(err) => error!(?err, "Controller error"),
+    let admin_state = Arc::new(admin::AdminState {
+        metrics: context.metrics.clone(),
+        crs: controllers.stores.clone(),
+        rbac: rbac::render(&config)?,
+        readiness: admin::Readiness::default(),
+        watchdog: watchdog.clone(),
+        circuit: context.circuit.clone(),
+    });
+    // The provider only checks its settings; building the credential catches the rest early
+    let credential_ready = match context
+        .credentials
+        .credential(TokenCredentialOptions::default())
+    {
+        Ok(_) => true,
+        // Azurite mode signs with the emulator key and never uses the credential
+        Err(_) if context.azurite.is_some() => true,
+        Err(e) => {
+            warn!(
+                ?e,
+                "Failed to construct the Azure credential; not reporting ready"
+            );
+            false
+        }
+    };
+    admin_state
+        .readiness
+        .credential_ready
+        .store(credential_ready, Ordering::Relaxed);
+    let readiness_state = admin_state.clone();
+    let caches = controllers.stores.clone();
+    tokio::spawn(async move {
+        for cache in caches {
+            if let Err(e) = cache.wait_until_ready().await {
+                error!(?e, "Controller cache was dropped before it became ready");
+                return;
             }
-        });
+        }
+        info!("Initial SasGenerator list loaded");
+        readiness_state
+            .readiness
+            .cache_synced
+            .store(true, Ordering::Relaxed);
+    });
+    let admin_server = admin::serve(config.admin_address.clone(), admin_state);
+    let controller = controllers.run(context, watchdog);
 
     info!("Controller started; waiting for Ctrl+C to stop");
     tokio::select! {
         _ = controller => {},
+        result = admin_server => {
+            error!(?result, "Admin server stopped");
+        }
         _ = tokio::signal::ctrl_c() => {
             info!("Received Ctrl+C, shutting down gracefully");
         }
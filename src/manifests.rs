@@ -0,0 +1,390 @@
+use crate::config::Config;
+use crate::conversion::{WEBHOOK_SERVICE_NAME, WEBHOOK_SERVICE_NAMESPACE};
+use crate::rbac::{cluster_role, cluster_role_binding};
+use crate::webhookcert::CERT_SECRET_NAME;
+use k8s_openapi::api::admissionregistration::v1::{
+    MutatingWebhook, MutatingWebhookConfiguration, RuleWithOperations, ServiceReference,
+    ValidatingWebhook, ValidatingWebhookConfiguration, WebhookClientConfig,
+};
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, ContainerPort, EmptyDirVolumeSource, EnvVar, HTTPGetAction, PodSpec,
+    PodTemplateSpec, Probe, SecretVolumeSource, SecurityContext, Service, ServiceAccount,
+    ServicePort, ServiceSpec, TCPSocketAction, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+
+/// Name of the Deployment and the label value selecting its pods
+pub const DEPLOYMENT_NAME: &str = "sas-operator";
+
+/// Annotation asking cert-manager's CA injector to fill in the webhook CA bundle
+const INJECT_CA_ANNOTATION: &str = "cert-manager.io/inject-ca-from";
+
+/// Deployment settings that are not operator settings
+pub struct ManifestOptions<'a> {
+    pub namespace: &'a str,
+    pub service_account: &'a str,
+    pub image: &'a str,
+    /// Operator settings passed to the container, e.g. `WATCH_NAMESPACE`
+    pub env: &'a [(String, String)],
+}
+
+/// ServiceAccount, ClusterRole, ClusterRoleBinding and Deployment of the operator as
+/// multi-document YAML. The ClusterRole is derived from `config`, which must hold the same
+/// settings as `env`, so it grants exactly what the deployed features need.
+/// With `WEBHOOK_ENABLED` the webhook server follows: its Deployment, the Service the API
+/// server calls and the webhook configurations named `WEBHOOK_CONFIGURATION_NAME`. Its
+/// certificate is self-signed with `WEBHOOK_SELF_SIGNED_CERT`, otherwise cert-manager is
+/// expected to issue it into the `sas-operator-webhook-cert` Secret.
+pub fn render(
+    config: &Config,
+    options: &ManifestOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let service_account = ServiceAccount {
+        metadata: ObjectMeta {
+            name: Some(options.service_account.to_string()),
+            namespace: Some(options.namespace.to_string()),
+            labels: Some(labels()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut documents = vec![
+        serde_yaml::to_string(&service_account)?,
+        serde_yaml::to_string(&cluster_role(config))?,
+        serde_yaml::to_string(&cluster_role_binding(
+            options.namespace,
+            options.service_account,
+        ))?,
+        serde_yaml::to_string(&deployment(config, options))?,
+    ];
+    if config.webhook_enabled {
+        // The CRD and the self-signed certificate name the Service in this namespace
+        if options.namespace != WEBHOOK_SERVICE_NAMESPACE {
+            return Err(format!(
+                "WEBHOOK_ENABLED needs --namespace {WEBHOOK_SERVICE_NAMESPACE}, where the \
+                 conversion webhook Service is expected"
+            )
+            .into());
+        }
+        documents.extend([
+            serde_yaml::to_string(&webhook_deployment(config, options))?,
+            serde_yaml::to_string(&webhook_service())?,
+            serde_yaml::to_string(&validating_webhooks(config))?,
+            serde_yaml::to_string(&mutating_webhooks(config))?,
+        ]);
+    }
+    Ok(documents.join("---\n"))
+}
+
+fn labels() -> BTreeMap<String, String> {
+    labels_for(DEPLOYMENT_NAME)
+}
+
+fn labels_for(name: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([("app.kubernetes.io/name".to_string(), name.to_string())])
+}
+
+fn port(address: &str, default: i32) -> i32 {
+    address
+        .rsplit(':')
+        .next()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(default)
+}
+
+fn env(options: &ManifestOptions) -> Option<Vec<EnvVar>> {
+    Some(
+        options
+            .env
+            .iter()
+            .map(|(name, value)| EnvVar {
+                name: name.clone(),
+                value: Some(value.clone()),
+                ..Default::default()
+            })
+            .collect(),
+    )
+    .filter(|env: &Vec<EnvVar>| !env.is_empty())
+}
+
+fn security_context() -> SecurityContext {
+    SecurityContext {
+        run_as_non_root: Some(true),
+        read_only_root_filesystem: Some(true),
+        allow_privilege_escalation: Some(false),
+        capabilities: Some(Capabilities {
+            drop: Some(vec!["ALL".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn deployment(config: &Config, options: &ManifestOptions) -> Deployment {
+    let admin_port = port(&config.admin_address, 8080);
+    let probe = |path: &str| Probe {
+        http_get: Some(HTTPGetAction {
+            path: Some(path.to_string()),
+            port: IntOrString::String("admin".to_string()),
+            ..Default::default()
+        }),
+        period_seconds: Some(10),
+        ..Default::default()
+    };
+    let container = Container {
+        name: "operator".to_string(),
+        image: Some(options.image.to_string()),
+        env: env(options),
+        ports: Some(vec![ContainerPort {
+            name: Some("admin".to_string()),
+            container_port: admin_port,
+            ..Default::default()
+        }]),
+        liveness_probe: Some(probe("/healthz")),
+        readiness_probe: Some(probe("/readyz")),
+        security_context: Some(security_context()),
+        ..Default::default()
+    };
+    pod_deployment(DEPLOYMENT_NAME, options, container, Vec::new())
+}
+
+fn pod_deployment(
+    name: &str,
+    options: &ManifestOptions,
+    container: Container,
+    volumes: Vec<Volume>,
+) -> Deployment {
+    Deployment {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(options.namespace.to_string()),
+            labels: Some(labels_for(name)),
+            ..Default::default()
+        },
+        spec: Some(DeploymentSpec {
+            replicas: Some(1),
+            selector: LabelSelector {
+                match_labels: Some(labels_for(name)),
+                ..Default::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels_for(name)),
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    service_account_name: Some(options.service_account.to_string()),
+                    containers: vec![container],
+                    volumes: Some(volumes).filter(|v| !v.is_empty()),
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The same image run with `--webhook`, serving the certificate mounted at `WEBHOOK_CERT_DIR`
+/// or, when self-signed, written there by the server itself
+fn webhook_deployment(config: &Config, options: &ManifestOptions) -> Deployment {
+    let certs = if config.webhook_self_signed_cert {
+        Volume {
+            name: "certs".to_string(),
+            empty_dir: Some(EmptyDirVolumeSource::default()),
+            ..Default::default()
+        }
+    } else {
+        Volume {
+            name: "certs".to_string(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(CERT_SECRET_NAME.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    };
+    let container = Container {
+        name: "webhook".to_string(),
+        image: Some(options.image.to_string()),
+        args: Some(vec!["--webhook".to_string()]),
+        env: env(options),
+        ports: Some(vec![ContainerPort {
+            name: Some("webhook".to_string()),
+            container_port: port(&config.webhook_address, 8443),
+            ..Default::default()
+        }]),
+        readiness_probe: Some(Probe {
+            tcp_socket: Some(TCPSocketAction {
+                port: IntOrString::String("webhook".to_string()),
+                ..Default::default()
+            }),
+            period_seconds: Some(10),
+            ..Default::default()
+        }),
+        volume_mounts: Some(vec![VolumeMount {
+            name: "certs".to_string(),
+            mount_path: config.webhook_cert_dir.clone(),
+            read_only: Some(!config.webhook_self_signed_cert),
+            ..Default::default()
+        }]),
+        security_context: Some(security_context()),
+        ..Default::default()
+    };
+    pod_deployment(WEBHOOK_SERVICE_NAME, options, container, vec![certs])
+}
+
+fn webhook_service() -> Service {
+    Service {
+        metadata: ObjectMeta {
+            name: Some(WEBHOOK_SERVICE_NAME.to_string()),
+            namespace: Some(WEBHOOK_SERVICE_NAMESPACE.to_string()),
+            labels: Some(labels_for(WEBHOOK_SERVICE_NAME)),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            selector: Some(labels_for(WEBHOOK_SERVICE_NAME)),
+            ports: Some(vec![ServicePort {
+                name: Some("webhook".to_string()),
+                port: 443,
+                target_port: Some(IntOrString::String("webhook".to_string())),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Metadata of a webhook configuration; without a self-signed certificate cert-manager
+/// injects the CA bundle of the certificate it issued
+fn webhook_metadata(config: &Config) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(config.webhook_configuration_name.clone()),
+        labels: Some(labels_for(WEBHOOK_SERVICE_NAME)),
+        annotations: (!config.webhook_self_signed_cert).then(|| {
+            BTreeMap::from([(
+                INJECT_CA_ANNOTATION.to_string(),
+                format!("{WEBHOOK_SERVICE_NAMESPACE}/{CERT_SECRET_NAME}"),
+            )])
+        }),
+        ..Default::default()
+    }
+}
+
+fn client_config(path: &str) -> WebhookClientConfig {
+    WebhookClientConfig {
+        service: Some(ServiceReference {
+            name: WEBHOOK_SERVICE_NAME.to_string(),
+            namespace: WEBHOOK_SERVICE_NAMESPACE.to_string(),
+            path: Some(path.to_string()),
+            port: Some(443),
+        }),
+        ..Default::default()
+    }
+}
+
+fn sasgenerator_rules(operations: &[&str]) -> Option<Vec<RuleWithOperations>> {
+    Some(vec![RuleWithOperations {
+        api_groups: Some(vec!["sas.azure.com".to_string()]),
+        api_versions: Some(vec!["*".to_string()]),
+        operations: Some(operations.iter().map(|o| o.to_string()).collect()),
+        resources: Some(vec!["sasgenerators".to_string()]),
+        ..Default::default()
+    }])
+}
+
+/// `/validate` on every write, rejecting SasGenerators the controller would refuse
+fn validating_webhooks(config: &Config) -> ValidatingWebhookConfiguration {
+    ValidatingWebhookConfiguration {
+        metadata: webhook_metadata(config),
+        webhooks: Some(vec![ValidatingWebhook {
+            name: "validate.sas.azure.com".to_string(),
+            admission_review_versions: vec!["v1".to_string()],
+            client_config: client_config("/validate"),
+            rules: sasgenerator_rules(&["CREATE", "UPDATE"]),
+            side_effects: "None".to_string(),
+            failure_policy: Some("Fail".to_string()),
+            ..Default::default()
+        }]),
+    }
+}
+
+/// `/mutate` on creation, writing the operator defaults into the spec
+fn mutating_webhooks(config: &Config) -> MutatingWebhookConfiguration {
+    MutatingWebhookConfiguration {
+        metadata: webhook_metadata(config),
+        webhooks: Some(vec![MutatingWebhook {
+            name: "mutate.sas.azure.com".to_string(),
+            admission_review_versions: vec!["v1".to_string()],
+            client_config: client_config("/mutate"),
+            rules: sasgenerator_rules(&["CREATE"]),
+            side_effects: "None".to_string(),
+            failure_policy: Some("Fail".to_string()),
+            ..Default::default()
+        }]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(namespace: &str) -> ManifestOptions<'_> {
+        ManifestOptions {
+            namespace,
+            service_account: "sas-operator",
+            image: "sas-operator:test",
+            env: &[],
+        }
+    }
+
+    fn kinds(rendered: &str) -> Vec<String> {
+        rendered
+            .lines()
+            .filter_map(|line| line.strip_prefix("kind: "))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn webhook_is_rendered_only_when_enabled() {
+        let mut config = Config::from_env();
+        config.webhook_enabled = false;
+        let rendered = render(&config, &options("sas-operator")).unwrap();
+        assert_eq!(
+            kinds(&rendered),
+            [
+                "ServiceAccount",
+                "ClusterRole",
+                "ClusterRoleBinding",
+                "Deployment"
+            ]
+        );
+
+        config.webhook_enabled = true;
+        let rendered = render(&config, &options("sas-operator")).unwrap();
+        assert_eq!(
+            kinds(&rendered)[4..],
+            [
+                "Deployment",
+                "Service",
+                "ValidatingWebhookConfiguration",
+                "MutatingWebhookConfiguration"
+            ]
+        );
+        assert!(rendered.contains(&format!("name: {WEBHOOK_SERVICE_NAME}")));
+        assert!(rendered.contains("- --webhook"));
+    }
+
+    #[test]
+    fn webhook_needs_the_service_namespace() {
+        let mut config = Config::from_env();
+        config.webhook_enabled = true;
+        assert!(render(&config, &options("elsewhere")).is_err());
+    }
+}
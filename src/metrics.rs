@@ -0,0 +1,186 @@
+use crate::crd::SasGenerator;
+use crate::utils::from_time;
+use kube::ResourceExt;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+
+const RECONCILES_TOTAL: &str = "sas_operator_reconciles_total";
+const RECONCILE_ERRORS_TOTAL: &str = "sas_operator_reconcile_errors_total";
+const REQUEUES_TOTAL: &str = "sas_operator_requeues_total";
+const RECONCILE_DURATION: &str = "sas_operator_reconcile_duration_seconds";
+const TOKEN_EXPIRY: &str = "sas_token_expiry_timestamp_seconds";
+const AZURE_LAST_SUCCESS: &str = "sas_operator_azure_last_success_timestamp_seconds";
+
+/// Name and help text of every counter, in render order
+const COUNTERS: &[(&str, &str)] = &[
+    (RECONCILES_TOTAL, "Reconciles by result"),
+    (RECONCILE_ERRORS_TOTAL, "Failed reconciles by error kind"),
+    (REQUEUES_TOTAL, "Requeues by reason"),
+];
+
+/// Expiry of the current token of every CR that has one. Rendered from the controller's cache
+/// rather than recorded, so deleted CRs disappear instead of leaving stale series behind.
+pub fn render_token_expiry(crs: &[std::sync::Arc<SasGenerator>]) -> String {
+    let mut out = format!(
+        "# HELP {TOKEN_EXPIRY} Expiry of the current SAS token per SasGenerator\n\
+         # TYPE {TOKEN_EXPIRY} gauge\n"
+    );
+    for cr in crs {
+        let Some(expiry) = cr.status.as_ref().and_then(|s| s.expiry.as_ref()) else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "{TOKEN_EXPIRY}{{namespace=\"{}\",name=\"{}\"}} {}",
+            escape(&cr.namespace().unwrap_or_default()),
+            escape(&cr.name_any()),
+            from_time(expiry).unix_timestamp()
+        );
+    }
+    out
+}
+
+/// Upper bounds of the reconcile duration buckets; reconciles wait on Azure and the API server
+const DURATION_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Cumulative Prometheus histogram over `DURATION_BUCKETS`
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last entry counts `+Inf`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; DURATION_BUCKETS.len() + 1];
+        }
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        let bounds = DURATION_BUCKETS
+            .iter()
+            .map(|b| b.to_string())
+            .chain(["+Inf".to_string()]);
+        for (bound, count) in bounds.zip(self.buckets.iter().chain(std::iter::repeat(&0))) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_sum {}\n{name}_count {}", self.sum, self.count);
+    }
+}
+
+/// Minimal in-process metric registry shared by all reconciles
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Unix timestamp of the last successful Azure call, keyed by storage account
+    azure_last_success: Mutex<BTreeMap<String, i64>>,
+    /// Counter values keyed by metric name and rendered label set
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+    reconcile_duration: Mutex<Histogram>,
+}
+
+/// Prometheus label value escaping
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn record_azure_success(&self, account: &str, at: OffsetDateTime) {
+        self.azure_last_success
+            .lock()
+            .unwrap()
+            .insert(account.to_string(), at.unix_timestamp());
+    }
+
+    pub fn azure_last_success(&self, account: &str) -> Option<OffsetDateTime> {
+        self.azure_last_success
+            .lock()
+            .unwrap()
+            .get(account)
+            .and_then(|ts| OffsetDateTime::from_unix_timestamp(*ts).ok())
+    }
+
+    fn increment(&self, name: &'static str, label: &str, value: &str) {
+        let labels = format!("{label}=\"{}\"", escape(value));
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_default() += 1;
+    }
+
+    /// Counts a finished reconcile; `result` is `success` or `error`
+    pub fn record_reconcile(&self, result: &str) {
+        self.increment(RECONCILES_TOTAL, "result", result);
+    }
+
+    /// Counts a failed reconcile by error kind (`Kube`, `Azure`, `CrdApply`, ...)
+    pub fn record_error(&self, kind: &str) {
+        self.increment(RECONCILE_ERRORS_TOTAL, "kind", kind);
+    }
+
+    pub fn observe_reconcile_duration(&self, duration: std::time::Duration) {
+        self.reconcile_duration
+            .lock()
+            .unwrap()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Counts why a CR was scheduled for its next reconcile
+    pub fn record_requeue(&self, reason: &str) {
+        self.increment(REQUEUES_TOTAL, "reason", reason);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = self.counters.lock().unwrap();
+        for (name, help) in COUNTERS {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+            for ((_, labels), value) in counters.iter().filter(|((n, _), _)| n == name) {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP {RECONCILE_DURATION} Time spent in reconcile\n\
+             # TYPE {RECONCILE_DURATION} histogram"
+        );
+        self.reconcile_duration
+            .lock()
+            .unwrap()
+            .render(&mut out, RECONCILE_DURATION);
+
+        let _ = writeln!(
+            out,
+            "# HELP {AZURE_LAST_SUCCESS} Last successful Azure call per storage account\n\
+             # TYPE {AZURE_LAST_SUCCESS} gauge"
+        );
+        for (account, ts) in self.azure_last_success.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "{AZURE_LAST_SUCCESS}{{account=\"{}\"}} {ts}",
+                escape(account)
+            );
+        }
+        out
+    }
+}
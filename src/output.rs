@@ -0,0 +1,284 @@
+use crate::crd::{OutputFormat, SasGenerator};
+use crate::secret::SecretValues;
+use std::collections::BTreeMap;
+
+/// File name rclone looks for when the Secret is mounted as its config directory
+const RCLONE_CONFIG_KEY: &str = "rclone.conf";
+
+/// Key the Velero docs reference from `--secret-file` and the BackupStorageLocation credential
+const VELERO_CREDENTIALS_KEY: &str = "cloud";
+
+/// `KEY=value` lines as read by env-file based tools
+fn env_file(entries: &[(&str, &str)]) -> String {
+    entries
+        .iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect()
+}
+
+/// Container and token of single-container formats (enforced by spec validation)
+fn single_token(values: &SecretValues) -> (&str, &str) {
+    values
+        .tokens
+        .first()
+        .map(|(c, t)| (c.as_str(), t.as_str()))
+        .unwrap_or_default()
+}
+
+/// DNS suffix of the blob endpoint when it is not the public cloud's, for tools that build
+/// the account URL themselves
+fn endpoint_suffix(values: &SecretValues) -> Option<String> {
+    let host = values
+        .blob_endpoint
+        .strip_prefix("https://")?
+        .strip_prefix(&format!("{}.blob.", values.account))?;
+    (host != "core.windows.net").then(|| host.to_string())
+}
+
+/// Environment variable name for a per-container key, e.g. `AZCOPY_SAS_URL_MY_BACKUPS`
+fn env_suffixed(base: &str, container: &str) -> String {
+    format!("{base}_{}", container.to_uppercase().replace('-', "_"))
+}
+
+/// Secret entries of a tool-specific output format; `None` for the default layout
+pub fn preset_data(
+    sasgen: &SasGenerator,
+    values: &SecretValues,
+) -> Option<BTreeMap<String, String>> {
+    let mut data = BTreeMap::new();
+    match sasgen.spec.output_format.unwrap_or_default() {
+        OutputFormat::Default => return None,
+        // azcopy takes the SAS URL as its source/destination argument, e.g. `azcopy sync "$AZCOPY_SAS_URL" ...`
+        OutputFormat::Azcopy => match values.tokens.as_slice() {
+            [(container, token)] => {
+                data.insert(
+                    "AZCOPY_SAS_URL".into(),
+                    values.container_url(container, token),
+                );
+            }
+            tokens => {
+                for (container, token) in tokens {
+                    data.insert(
+                        env_suffixed("AZCOPY_SAS_URL", container),
+                        values.container_url(container, token),
+                    );
+                }
+            }
+        },
+        OutputFormat::Rclone => {
+            let remotes: Vec<String> = values
+                .tokens
+                .iter()
+                .map(|(container, token)| {
+                    format!(
+                        "[{container}]\ntype = azureblob\nsas_url = {}\n",
+                        values.container_url(container, token)
+                    )
+                })
+                .collect();
+            data.insert(RCLONE_CONFIG_KEY.into(), remotes.join("\n"));
+        }
+        OutputFormat::Velero => {
+            let (_, token) = single_token(values);
+            let cloud = sasgen.spec.cloud.unwrap_or_default();
+            data.insert(
+                VELERO_CREDENTIALS_KEY.into(),
+                env_file(&[
+                    ("AZURE_STORAGE_ACCOUNT_ID", &values.account),
+                    ("AZURE_STORAGE_ACCOUNT_SAS_TOKEN", token),
+                    ("AZURE_CLOUD_NAME", cloud.environment_name()),
+                ]),
+            );
+        }
+        OutputFormat::Restic => {
+            let (container, token) = single_token(values);
+            data.insert("AZURE_ACCOUNT_NAME".into(), values.account.clone());
+            data.insert("AZURE_ACCOUNT_SAS".into(), token.to_string());
+            data.insert("RESTIC_REPOSITORY".into(), format!("azure:{container}:/"));
+            if let Some(suffix) = endpoint_suffix(values) {
+                data.insert("AZURE_ENDPOINT_SUFFIX".into(), suffix);
+            }
+        }
+        OutputFormat::Kopia => {
+            let (container, token) = single_token(values);
+            data.insert("KOPIA_REPOSITORY".into(), format!("azure://{container}/"));
+            data.insert("AZURE_STORAGE_ACCOUNT".into(), values.account.clone());
+            data.insert("AZURE_STORAGE_SAS_TOKEN".into(), token.to_string());
+            if let Some(suffix) = endpoint_suffix(values) {
+                data.insert("AZURE_STORAGE_DOMAIN".into(), format!("blob.{suffix}"));
+            }
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::BlobScope;
+    use serde_json::{json, Value};
+
+    const PUBLIC: &str = "https://backupacct.blob.core.windows.net";
+    const CHINA: &str = "https://backupacct.blob.core.chinacloudapi.cn";
+
+    /// Output format, `spec.cloud`, Secret values and the expected entries
+    type Case = (
+        &'static str,
+        Value,
+        SecretValues,
+        Vec<(&'static str, &'static str)>,
+    );
+
+    fn sasgen(format: &str, cloud: Value) -> SasGenerator {
+        serde_json::from_value(json!({
+            "apiVersion": "sas.azure.com/v1alpha1",
+            "kind": "SasGenerator",
+            "metadata": { "name": "backup", "namespace": "apps" },
+            "spec": {
+                "storageAccount": "backupacct",
+                "containerName": "data",
+                "secretName": "backup-sas",
+                "outputFormat": format,
+                "cloud": cloud,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn values(endpoint: &str, tokens: &[(&str, &str)], scope: Option<BlobScope>) -> SecretValues {
+        SecretValues {
+            account: "backupacct".into(),
+            blob_endpoint: endpoint.into(),
+            tokens: tokens
+                .iter()
+                .map(|(c, t)| (c.to_string(), t.to_string()))
+                .collect(),
+            blob_scope: scope,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn presets_lay_out_tokens_for_their_tool() {
+        let dump = BlobScope {
+            blob_name: "db/dump.bak".into(),
+            snapshot: None,
+            version_id: Some("2024-03-09T01:42:34.9360000Z".into()),
+        };
+        let cases: Vec<Case> = vec![
+            (
+                "azcopy",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1")], None),
+                vec![(
+                    "AZCOPY_SAS_URL",
+                    "https://backupacct.blob.core.windows.net/data?sv=1",
+                )],
+            ),
+            (
+                "azcopy",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1"), ("my-logs", "sv=2")], None),
+                vec![
+                    (
+                        "AZCOPY_SAS_URL_DATA",
+                        "https://backupacct.blob.core.windows.net/data?sv=1",
+                    ),
+                    (
+                        "AZCOPY_SAS_URL_MY_LOGS",
+                        "https://backupacct.blob.core.windows.net/my-logs?sv=2",
+                    ),
+                ],
+            ),
+            (
+                "azcopy",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1")], Some(dump)),
+                vec![(
+                    "AZCOPY_SAS_URL",
+                    "https://backupacct.blob.core.windows.net/data/db/dump.bak\
+                     ?versionid=2024-03-09T01%3A42%3A34.9360000Z&sv=1",
+                )],
+            ),
+            (
+                "rclone",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1"), ("logs", "sv=2")], None),
+                vec![(
+                    "rclone.conf",
+                    "[data]\ntype = azureblob\n\
+                     sas_url = https://backupacct.blob.core.windows.net/data?sv=1\n\n\
+                     [logs]\ntype = azureblob\n\
+                     sas_url = https://backupacct.blob.core.windows.net/logs?sv=2\n",
+                )],
+            ),
+            (
+                "velero",
+                json!("AzureChina"),
+                values(CHINA, &[("data", "sv=1")], None),
+                vec![(
+                    "cloud",
+                    "AZURE_STORAGE_ACCOUNT_ID=backupacct\n\
+                     AZURE_STORAGE_ACCOUNT_SAS_TOKEN=sv=1\n\
+                     AZURE_CLOUD_NAME=AzureChinaCloud\n",
+                )],
+            ),
+            (
+                "restic",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1")], None),
+                vec![
+                    ("AZURE_ACCOUNT_NAME", "backupacct"),
+                    ("AZURE_ACCOUNT_SAS", "sv=1"),
+                    ("RESTIC_REPOSITORY", "azure:data:/"),
+                ],
+            ),
+            (
+                "restic",
+                json!("AzureChina"),
+                values(CHINA, &[("data", "sv=1")], None),
+                vec![
+                    ("AZURE_ACCOUNT_NAME", "backupacct"),
+                    ("AZURE_ACCOUNT_SAS", "sv=1"),
+                    ("AZURE_ENDPOINT_SUFFIX", "core.chinacloudapi.cn"),
+                    ("RESTIC_REPOSITORY", "azure:data:/"),
+                ],
+            ),
+            (
+                "kopia",
+                Value::Null,
+                values(PUBLIC, &[("data", "sv=1")], None),
+                vec![
+                    ("AZURE_STORAGE_ACCOUNT", "backupacct"),
+                    ("AZURE_STORAGE_SAS_TOKEN", "sv=1"),
+                    ("KOPIA_REPOSITORY", "azure://data/"),
+                ],
+            ),
+            (
+                "kopia",
+                json!("AzureChina"),
+                values(CHINA, &[("data", "sv=1")], None),
+                vec![
+                    ("AZURE_STORAGE_ACCOUNT", "backupacct"),
+                    ("AZURE_STORAGE_DOMAIN", "blob.core.chinacloudapi.cn"),
+                    ("AZURE_STORAGE_SAS_TOKEN", "sv=1"),
+                    ("KOPIA_REPOSITORY", "azure://data/"),
+                ],
+            ),
+        ];
+        for (format, cloud, values, expected) in cases {
+            let data = preset_data(&sasgen(format, cloud), &values).unwrap();
+            let expected: BTreeMap<String, String> = expected
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(data, expected, "{format} with {:?}", values.tokens);
+        }
+    }
+
+    #[test]
+    fn default_format_has_no_preset() {
+        let values = values(PUBLIC, &[("data", "sv=1")], None);
+        assert_eq!(preset_data(&sasgen("default", Value::Null), &values), None);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::crd::{AzureIdentity, ContextData, SasGenerator};
+use crate::reconcile::ReconcileError;
+use crate::validate::ACCOUNT_NAME_PATTERN;
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::core::{Selector, SelectorExt};
+use kube::{Api, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{debug, instrument};
+
+/// Annotation a namespace must carry before SasGenerators of other namespaces may write
+/// Secrets into it: a comma-separated list of their namespaces, or `*` for all of them
+pub const ALLOWED_SOURCES_ANNOTATION: &str = "sas.azure.com/allowed-source-namespaces";
+
+/// Restricts which storage accounts and containers the SasGenerators of some namespaces may
+/// target. Namespaces that no policy applies to are unrestricted; where several apply, any of
+/// them may allow the account. Node-attached managed identities are the exception: a CR may
+/// only use one that an applying policy lists for its account.
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "sas.azure.com",
+    version = "v1alpha1",
+    kind = "SasAccountPolicy",
+    shortname = "sasap",
+    category = "azure",
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct SasAccountPolicySpec {
+    /// Namespaces the policy applies to by name
+    pub namespaces: Option<Vec<String>>,
+    /// Namespaces the policy applies to by label; with neither set it applies to all
+    pub namespace_selector: Option<LabelSelector>,
+    /// Storage accounts the SasGenerators in these namespaces may target
+    pub allowed_accounts: Vec<AllowedAccount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedAccount {
+    #[schemars(regex(pattern = ACCOUNT_NAME_PATTERN), length(min = 3, max = 24))]
+    pub name: String,
+    /// Containers of the account that may be targeted; a trailing `*` matches by prefix.
+    /// Unset allows every container.
+    pub containers: Option<Vec<String>>,
+    /// Client IDs of the node-attached user-assigned managed identities that SasGenerators may
+    /// name in `azureIdentity.clientId` (without a ServiceAccount) for this account
+    pub managed_identities: Option<Vec<String>>,
+}
+
+impl AllowedAccount {
+    fn allows_managed_identity(&self, client_id: &str) -> bool {
+        self.managed_identities
+            .iter()
+            .flatten()
+            .any(|id| id.eq_ignore_ascii_case(client_id))
+    }
+
+    fn allows_container(&self, container: &str) -> bool {
+        self.containers.as_ref().is_none_or(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => container.starts_with(prefix),
+                    None => container == pattern,
+                })
+        })
+    }
+}
+
+impl SasAccountPolicy {
+    fn applies_to(&self, namespace: &str, labels: &BTreeMap<String, String>) -> bool {
+        let spec = &self.spec;
+        let by_name = spec
+            .namespaces
+            .as_ref()
+            .is_some_and(|names| names.iter().any(|n| n == namespace));
+        // An invalid selector selects nothing rather than everything
+        let by_label = spec.namespace_selector.clone().is_some_and(|selector| {
+            Selector::try_from(selector).is_ok_and(|selector| selector.matches(labels))
+        });
+        by_name || by_label || (spec.namespaces.is_none() && spec.namespace_selector.is_none())
+    }
+}
+
+/// Account entries that the policies applying to a CR's namespace allow for its account;
+/// `None` if no policy applies
+pub struct PolicyCheck {
+    policies: Vec<String>,
+    allowed: Option<Vec<AllowedAccount>>,
+}
+
+impl PolicyCheck {
+    /// Loads the policies applying to the namespace of `sasgen`
+    #[instrument(skip_all)]
+    pub async fn load(sasgen: &SasGenerator, ctx: &ContextData) -> Result<Self, ReconcileError> {
+        let policies = Api::<SasAccountPolicy>::all(ctx.client.clone())
+            .list(&Default::default())
+            .await?
+            .items;
+        let namespace = sasgen.namespace().unwrap_or_default();
+        let labels = if policies.iter().any(|p| p.spec.namespace_selector.is_some()) {
+            Api::<Namespace>::all(ctx.client.clone())
+                .get(&namespace)
+                .await?
+                .labels()
+                .clone()
+        } else {
+            BTreeMap::new()
+        };
+        let applicable: Vec<&SasAccountPolicy> = policies
+            .iter()
+            .filter(|p| p.applies_to(&namespace, &labels))
+            .collect();
+        debug!(
+            policies = ?applicable.iter().map(|p| p.name_any()).collect::<Vec<_>>(),
+            "Loaded account policies"
+        );
+        Ok(Self {
+            policies: applicable.iter().map(|p| p.name_any()).collect(),
+            allowed: (!applicable.is_empty()).then(|| {
+                applicable
+                    .iter()
+                    .flat_map(|p| &p.spec.allowed_accounts)
+                    .filter(|a| a.name == sasgen.spec.storage_account)
+                    .cloned()
+                    .collect()
+            }),
+        })
+    }
+
+    /// Refuses storage accounts that no applying policy allows
+    pub fn check_account(&self, sasgen: &SasGenerator) -> Result<(), ReconcileError> {
+        match &self.allowed {
+            Some(allowed) if allowed.is_empty() => Err(ReconcileError::Policy(format!(
+                "storage account '{}' is not allowed in namespace '{}' by SasAccountPolicy {}",
+                sasgen.spec.storage_account,
+                sasgen.namespace().unwrap_or_default(),
+                self.policies.join(", ")
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses node-attached managed identities that no applying policy lists for the account.
+    /// Every pod on the node may use them, so unlike accounts they are refused by default.
+    pub fn check_managed_identity(&self, sasgen: &SasGenerator) -> Result<(), ReconcileError> {
+        let Some(AzureIdentity {
+            client_id: Some(client_id),
+            service_account_name: None,
+        }) = &sasgen.spec.azure_identity
+        else {
+            return Ok(());
+        };
+        let allowed = self
+            .allowed
+            .iter()
+            .flatten()
+            .any(|a| a.allows_managed_identity(client_id));
+        if allowed {
+            return Ok(());
+        }
+        Err(ReconcileError::Policy(format!(
+            "managed identity '{client_id}' is not allowed for storage account '{}' in \
+             namespace '{}'; list it in managedIdentities of a SasAccountPolicy",
+            sasgen.spec.storage_account,
+            sasgen.namespace().unwrap_or_default()
+        )))
+    }
+
+    /// Refuses containers that no applying policy allows for the account
+    pub fn check_containers(&self, containers: &[String]) -> Result<(), ReconcileError> {
+        let Some(allowed) = &self.allowed else {
+            return Ok(());
+        };
+        match containers
+            .iter()
+            .find(|c| !allowed.iter().any(|a| a.allows_container(c)))
+        {
+            Some(container) => Err(ReconcileError::Policy(format!(
+                "container '{container}' is not allowed by SasAccountPolicy {}",
+                self.policies.join(", ")
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `namespace` accepts Secrets from SasGenerators in `source`; a CR's own namespace
+/// always does
+pub fn accepts_secrets_from(namespace: &Namespace, source: &str) -> bool {
+    namespace.name_any() == source
+        || namespace
+            .annotations()
+            .get(ALLOWED_SOURCES_ANNOTATION)
+            .is_some_and(|allowed| {
+                allowed
+                    .split(',')
+                    .map(str::trim)
+                    .any(|name| name == "*" || name == source)
+            })
+}
+
+/// Refuses target namespaces other than the CR's own that have not opted in with
+/// `ALLOWED_SOURCES_ANNOTATION`
+#[instrument(skip_all)]
+pub async fn check_target_namespaces(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    namespaces: &[String],
+) -> Result<(), ReconcileError> {
+    let source = sasgen.namespace().unwrap_or_default();
+    let api = Api::<Namespace>::all(ctx.client.clone());
+    for name in namespaces.iter().filter(|n| **n != source) {
+        let accepted = api
+            .get_opt(name)
+            .await?
+            .is_some_and(|namespace| accepts_secrets_from(&namespace, &source));
+        if !accepted {
+            return Err(ReconcileError::Policy(format!(
+                "namespace '{name}' does not accept Secrets from namespace '{source}'; \
+                 add it to the {ALLOWED_SOURCES_ANNOTATION} annotation of '{name}'"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::ObjectMeta;
+    use serde_json::{json, Value};
+
+    fn namespace(name: &str, allowed: Option<&str>) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.into()),
+                annotations: allowed
+                    .map(|a| BTreeMap::from([(ALLOWED_SOURCES_ANNOTATION.into(), a.into())])),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn sasgen(identity: Value) -> SasGenerator {
+        serde_json::from_value(json!({
+            "apiVersion": "sas.azure.com/v1alpha1",
+            "kind": "SasGenerator",
+            "metadata": { "name": "backup", "namespace": "apps" },
+            "spec": {
+                "storageAccount": "backupacct",
+                "containerName": "data",
+                "secretName": "backup-sas",
+                "azureIdentity": identity,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn check(allowed: Option<Value>) -> PolicyCheck {
+        PolicyCheck {
+            policies: vec!["backup".into()],
+            allowed: allowed.map(|a| serde_json::from_value(a).unwrap()),
+        }
+    }
+
+    #[test]
+    fn managed_identities_must_be_listed() {
+        let node_identity = sasgen(json!({ "clientId": "11111111-1111-1111-1111-111111111111" }));
+        assert!(check(None).check_managed_identity(&node_identity).is_err());
+        assert!(check(Some(json!([{ "name": "backupacct" }])))
+            .check_managed_identity(&node_identity)
+            .is_err());
+        let listed = json!([{
+            "name": "backupacct",
+            "managedIdentities": ["11111111-1111-1111-1111-111111111111"],
+        }]);
+        assert!(check(Some(listed))
+            .check_managed_identity(&node_identity)
+            .is_ok());
+
+        let federated = sasgen(json!({
+            "clientId": "11111111-1111-1111-1111-111111111111",
+            "serviceAccountName": "backup",
+        }));
+        assert!(check(None).check_managed_identity(&federated).is_ok());
+    }
+
+    #[test]
+    fn own_namespace_always_accepts() {
+        assert!(accepts_secrets_from(&namespace("apps", None), "apps"));
+    }
+
+    #[test]
+    fn other_namespaces_must_opt_in() {
+        assert!(!accepts_secrets_from(
+            &namespace("kube-system", None),
+            "apps"
+        ));
+        assert!(!accepts_secrets_from(
+            &namespace("shared", Some("billing")),
+            "apps"
+        ));
+        assert!(accepts_secrets_from(
+            &namespace("shared", Some("billing, apps")),
+            "apps"
+        ));
+        assert!(accepts_secrets_from(
+            &namespace("shared", Some("*")),
+            "apps"
+        ));
+    }
+}
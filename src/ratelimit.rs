@@ -0,0 +1,51 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+
+static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Token bucket shared by all reconciles, refilled continuously at `per_minute` tokens a minute
+/// and holding at most one minute's worth, so mass renewals cannot burst past the limit
+#[derive(Debug)]
+struct RateLimiter {
+    per_minute: u32,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+/// Installs the operator-wide limit on user delegation key requests (0 = unlimited); call
+/// once at startup before any Azure call
+pub fn init_rate_limit(per_minute: u32) {
+    let limiter = RateLimiter {
+        per_minute,
+        bucket: Mutex::new((f64::from(per_minute), Instant::now())),
+    };
+    if LIMITER.set(limiter).is_err() {
+        error!("Azure rate limit was already initialized; ignoring");
+    }
+}
+
+/// Waits until the limit allows another user delegation key request
+pub async fn acquire() {
+    let Some(limiter) = LIMITER.get().filter(|l| l.per_minute > 0) else {
+        return;
+    };
+    let rate = f64::from(limiter.per_minute) / 60.0;
+    loop {
+        let wait = {
+            let mut bucket = limiter.bucket.lock().await;
+            let (tokens, last) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate)
+                .min(f64::from(limiter.per_minute));
+            *last = now;
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+                return;
+            }
+            Duration::from_secs_f64((1.0 - *tokens) / rate)
+        };
+        debug!(?wait, "Azure rate limit reached; waiting");
+        tokio::time::sleep(wait).await;
+    }
+}
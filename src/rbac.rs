@@ -0,0 +1,204 @@
+use crate::config::Config;
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, RoleRef, Subject};
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+
+/// Name of the ClusterRole emitted by `--print-rbac`
+pub const CLUSTER_ROLE_NAME: &str = "sas-operator";
+
+/// Namespace and ServiceAccount the operator runs as unless told otherwise
+pub const DEFAULT_NAMESPACE: &str = "sas-operator";
+pub const SERVICE_ACCOUNT_NAME: &str = "sas-operator";
+
+/// One Kubernetes permission and the feature that needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub feature: &'static str,
+    pub api_group: &'static str,
+    pub resource: &'static str,
+    pub verbs: &'static [&'static str],
+}
+
+const fn requirement(
+    feature: &'static str,
+    api_group: &'static str,
+    resource: &'static str,
+    verbs: &'static [&'static str],
+) -> Requirement {
+    Requirement {
+        feature,
+        api_group,
+        resource,
+        verbs,
+    }
+}
+
+/// Permissions required by the feature set enabled in `config`.
+/// Features gated by operator settings push their rules only when they are turned on.
+pub fn requirements(config: &Config) -> Vec<Requirement> {
+    let mut requirements = vec![
+        requirement(
+            "controller",
+            "sas.azure.com",
+            "sasgenerators",
+            &["get", "list", "watch"],
+        ),
+        requirement(
+            "crossNamespaceCleanup",
+            "sas.azure.com",
+            "sasgenerators",
+            &["patch"],
+        ),
+        requirement(
+            "accountPolicies",
+            "sas.azure.com",
+            "sasaccountpolicies",
+            &["get", "list", "watch"],
+        ),
+        // Policies with a namespaceSelector are matched against the CR namespace's labels
+        requirement("accountPolicies", "", "namespaces", &["get"]),
+        requirement(
+            "status",
+            "sas.azure.com",
+            "sasgenerators/status",
+            &["get", "patch"],
+        ),
+        requirement("secrets", "", "secrets", &["get", "create", "patch"]),
+        requirement("secretDrift", "", "secrets", &["list", "watch"]),
+        requirement("secretType", "", "secrets", &["delete"]),
+        requirement("immutableSecrets", "", "secrets", &["list", "delete"]),
+        requirement("importSecretRef", "", "secrets", &["get"]),
+        requirement("credentialSecretRefs", "", "secrets", &["get"]),
+        requirement("additionalDataFrom", "", "secrets", &["get"]),
+        requirement("clusterRefs", "", "secrets", &["get"]),
+        requirement("additionalDataFrom", "", "configmaps", &["get"]),
+        requirement(
+            "workloadIdentityFederation",
+            "",
+            "serviceaccounts/token",
+            &["create"],
+        ),
+        requirement(
+            "workloadIdentityFederation",
+            "",
+            "serviceaccounts",
+            &["get"],
+        ),
+        requirement("events", "events.k8s.io", "events", &["create", "patch"]),
+    ];
+    // Namespaces are only watched cluster-wide; WATCH_NAMESPACE runs with namespaced RBAC
+    if config.watch_namespaces.is_empty() {
+        requirements.push(requirement(
+            "targetNamespaceSelector",
+            "",
+            "namespaces",
+            &["get", "list", "watch"],
+        ));
+    }
+    if config.webhook_self_signed_cert {
+        requirements.extend([
+            requirement("webhookCert", "", "secrets", &["get", "create", "update"]),
+            requirement(
+                "webhookCert",
+                "admissionregistration.k8s.io",
+                "validatingwebhookconfigurations",
+                &["get", "update"],
+            ),
+            requirement(
+                "webhookCert",
+                "admissionregistration.k8s.io",
+                "mutatingwebhookconfigurations",
+                &["get", "update"],
+            ),
+            requirement(
+                "webhookCert",
+                "apiextensions.k8s.io",
+                "customresourcedefinitions",
+                &["get", "update"],
+            ),
+        ]);
+    }
+    if config.install_crds {
+        requirements.push(requirement(
+            "installCrds",
+            "apiextensions.k8s.io",
+            "customresourcedefinitions",
+            &["get", "create", "patch", "watch", "list"],
+        ));
+    }
+    requirements
+}
+
+/// Merges the requirements into one rule per API group and resource, keeping verbs sorted
+pub fn policy_rules(requirements: &[Requirement]) -> Vec<PolicyRule> {
+    let mut merged: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    for req in requirements {
+        let verbs = merged.entry((req.api_group, req.resource)).or_default();
+        for verb in req.verbs {
+            if !verbs.iter().any(|v| v == verb) {
+                verbs.push(verb.to_string());
+            }
+        }
+    }
+
+    merged
+        .into_iter()
+        .map(|((group, resource), mut verbs)| {
+            verbs.sort();
+            PolicyRule {
+                api_groups: Some(vec![group.to_string()]),
+                resources: Some(vec![resource.to_string()]),
+                verbs,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Least-privilege ClusterRole for the enabled feature set.
+/// The features behind it are listed in an annotation so the output can be diffed on upgrade.
+pub fn cluster_role(config: &Config) -> ClusterRole {
+    let requirements = requirements(config);
+    let mut features: Vec<&str> = requirements.iter().map(|r| r.feature).collect();
+    features.dedup();
+
+    ClusterRole {
+        metadata: ObjectMeta {
+            name: Some(CLUSTER_ROLE_NAME.to_string()),
+            annotations: Some(BTreeMap::from([(
+                "sas.azure.com/features".to_string(),
+                features.join(","),
+            )])),
+            ..Default::default()
+        },
+        rules: Some(policy_rules(&requirements)),
+        ..Default::default()
+    }
+}
+
+/// Grants the ClusterRole to the operator's ServiceAccount. Cluster-wide even with
+/// `WATCH_NAMESPACE`, as SasAccountPolicies and Namespaces are cluster-scoped.
+pub fn cluster_role_binding(namespace: &str, service_account: &str) -> ClusterRoleBinding {
+    ClusterRoleBinding {
+        metadata: ObjectMeta {
+            name: Some(CLUSTER_ROLE_NAME.to_string()),
+            ..Default::default()
+        },
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "ClusterRole".to_string(),
+            name: CLUSTER_ROLE_NAME.to_string(),
+        },
+        subjects: Some(vec![Subject {
+            kind: "ServiceAccount".to_string(),
+            name: service_account.to_string(),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        }]),
+    }
+}
+
+/// Renders the ClusterRole as YAML, as printed by `--print-rbac`
+pub fn render(config: &Config) -> Result<String, serde_yaml::Error> {
+    serde_yaml::to_string(&cluster_role(config))
+}
@@ -1,24 +1,163 @@
-use crate::crd::{ContextData, SasGenerator, SasGeneratorStatus};
-use crate::sas::{generate_container_sas, SasTokenInfo};
-use crate::secret::ensure_secret;
-use crate::status::update_crd_status;
-use crate::utils::format_rfc3339;
+use crate::audit;
+use crate::bluegreen::drop_previous_tokens;
+use crate::cleanup::{delete_stale_secrets, ensure_finalizer, finalize, owner_key};
+use crate::crd::{ContextData, RotationRecord, SasGenerator, SasGeneratorStatus, SecretTarget};
+use crate::deprecation::{publish_deprecations, sync_deprecation_condition};
+use crate::distribute::{distribute, repair_drift, rollout_incomplete, Drift};
+use crate::events::{
+    publish, REASON_GENERATION_FAILED, REASON_TOKEN_GENERATED, REASON_TOKEN_RENEWED,
+};
+use crate::http::throttled_for;
+use crate::identity::storage_auth;
+use crate::import::import_token;
+use crate::policy::{accepts_secrets_from, check_target_namespaces, PolicyCheck};
+use crate::redact::redact;
+use crate::sas::{
+    blob_endpoint, blob_host, generate_container_sas, list_containers, stamp_container_metadata,
+    SasTokenInfo, StorageAuth,
+};
+use crate::secret::{additional_data, SecretValues};
+use crate::sentry;
+use crate::signature::SasOptions;
+use crate::status::{
+    mark_failed, mark_ready, remove_condition, set_condition, update_crd_status,
+    CONDITION_AZURE_CONNECTION_STALE, CONDITION_INVALID_SPEC,
+};
+use crate::template::TemplateError;
+use crate::utils::{format_rfc3339, format_time, from_time, parse_rfc3339, to_time, token_hash};
+use crate::validate::{validate_spec, SpecError};
+use azure_core::auth::Secret;
+use azure_storage::{CloudLocation, EMULATOR_ACCOUNT_KEY};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Api, ListParams};
 use kube::runtime::controller::Action;
+use kube::runtime::events::EventType;
+use kube::ResourceExt;
 use std::sync::Arc;
 use std::time::Duration as StdDuration;
 use time::{Duration, OffsetDateTime};
-use tracing::{error, info, instrument, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ReconcileError {
     #[error("Kubernetes API error: {0}")]
     Kube(#[from] kube::Error),
 
-    #[error("Azure SAS generation error: {0}")]
-    Azure(String),
+    #[error("Azure SAS generation error: {message}")]
+    Azure {
+        message: String,
+        /// HTTP status of the failed Azure request, if it got that far
+        status: Option<u16>,
+    },
 
     #[error("CRD apply failed: {0}")]
     CrdApply(String),
+
+    #[error("Invalid spec: {0}")]
+    Spec(#[from] SpecError),
+
+    #[error("Credential configuration error: {0}")]
+    Credentials(String),
+
+    #[error("Referenced object error: {0}")]
+    Reference(String),
+
+    #[error("Remote cluster '{cluster}' error: {message}")]
+    RemoteCluster { cluster: String, message: String },
+
+    #[error(
+        "Azure calls for storage account '{account}' are suspended after repeated failures; \
+         retrying in {}s",
+        retry_in.as_secs()
+    )]
+    CircuitOpen {
+        account: String,
+        retry_in: StdDuration,
+    },
+
+    #[error("Refused by policy: {0}")]
+    Policy(String),
+
+    #[error("Secret template error in key '{key}': {source}")]
+    Template { key: String, source: TemplateError },
+}
+
+/// HTTP status of the Azure request behind `error`, if it got a response
+pub fn azure_status(error: &anyhow::Error) -> Option<u16> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<azure_core::Error>())
+        .and_then(|e| match e.kind() {
+            azure_core::error::ErrorKind::HttpResponse { status, .. } => Some(*status),
+            _ => None,
+        })
+        .map(u16::from)
+}
+
+impl ReconcileError {
+    /// Errors that retrying cannot fix; the next spec change triggers a reconcile anyway
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            ReconcileError::Spec(_) | ReconcileError::Template { .. }
+        )
+    }
+
+    /// Errors that are unlikely to go away by themselves, such as 403 Forbidden or a missing
+    /// container; throttling, timeouts, network errors and 5xx responses are transient
+    pub fn is_permanent(&self) -> bool {
+        let permanent_status =
+            |code: u16| (400..500).contains(&code) && !matches!(code, 408 | 409 | 429);
+        match self {
+            ReconcileError::Kube(kube::Error::Api(response)) => permanent_status(response.code),
+            ReconcileError::Kube(_) => false,
+            ReconcileError::Azure { status, .. } => status.is_some_and(permanent_status),
+            ReconcileError::Credentials(_)
+            | ReconcileError::Reference(_)
+            | ReconcileError::Policy(_) => true,
+            _ => self.is_terminal(),
+        }
+    }
+
+    /// Azure failure, keeping the HTTP status of the failed request for classification
+    pub fn azure(error: &anyhow::Error) -> Self {
+        ReconcileError::Azure {
+            message: redact(&format!("{error:#}")).into_owned(),
+            status: azure_status(error),
+        }
+    }
+
+    /// Variant name, used as the `kind` label of the error counter
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReconcileError::Kube(_) => "Kube",
+            ReconcileError::Azure { .. } => "Azure",
+            ReconcileError::CrdApply(_) => "CrdApply",
+            ReconcileError::Spec(_) => "Spec",
+            ReconcileError::Credentials(_) => "Credentials",
+            ReconcileError::Reference(_) => "Reference",
+            ReconcileError::RemoteCluster { .. } => "RemoteCluster",
+            ReconcileError::CircuitOpen { .. } => "CircuitOpen",
+            ReconcileError::Policy(_) => "Policy",
+            ReconcileError::Template { .. } => "Template",
+        }
+    }
+
+    /// Short CamelCase reason used for conditions and events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ReconcileError::Kube(_) => "KubernetesApiError",
+            ReconcileError::Azure { .. } => "AzureError",
+            ReconcileError::CrdApply(_) => "StatusUpdateFailed",
+            ReconcileError::Spec(e) => e.reason(),
+            ReconcileError::Credentials(_) => "CredentialError",
+            ReconcileError::Reference(_) => "ReferenceError",
+            ReconcileError::RemoteCluster { .. } => "RemoteClusterError",
+            ReconcileError::CircuitOpen { .. } => "AzureCircuitOpen",
+            ReconcileError::Policy(_) => "PolicyViolation",
+            ReconcileError::Template { .. } => "TemplateError",
+        }
+    }
 }
 
 fn should_regenerate(
@@ -29,74 +168,848 @@ fn should_regenerate(
     status
         .as_ref()
         .and_then(|s| s.expiry.as_ref())
-        .map_or(true, |expiry| {
-            match OffsetDateTime::parse(expiry, &time::format_description::well_known::Rfc3339) {
-                Ok(parsed) => now >= (parsed - Duration::hours(renewal_hours)),
-                Err(e) => {
-                    warn!(
-                        ?expiry,
-                        ?e,
-                        "Failed to parse expiry; will regenerate SAS token"
-                    );
-                    true
-                }
+        .is_none_or(|expiry| now >= from_time(expiry) - Duration::hours(renewal_hours))
+}
+
+/// Runs an Azure call through the circuit breaker of `account`
+async fn guarded<T>(
+    ctx: &ContextData,
+    account: &str,
+    call: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> Result<T, ReconcileError> {
+    if let Some(retry_in) = ctx.circuit.open_for(account) {
+        return Err(ReconcileError::CircuitOpen {
+            account: account.to_string(),
+            retry_in,
+        });
+    }
+    match call.await {
+        Ok(value) => {
+            ctx.circuit.record_success(account);
+            Ok(value)
+        }
+        Err(e) => {
+            ctx.circuit.record_failure(account);
+            Err(ReconcileError::azure(&e))
+        }
+    }
+}
+
+/// Explicit containers from the spec, or the current result of the container selector
+async fn resolve_containers(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    auth: &StorageAuth,
+    location: &CloudLocation,
+) -> Result<Vec<String>, ReconcileError> {
+    let Some(selector) = &sasgen.spec.container_selector else {
+        return Ok(sasgen.container_names());
+    };
+
+    let mut containers = guarded(
+        ctx,
+        &sasgen.spec.storage_account,
+        list_containers(auth, location, selector.prefix.as_deref()),
+    )
+    .await?;
+    containers.retain(|c| selector.matches(c));
+    containers.sort();
+
+    info!(?containers, "Discovered containers matching selector");
+    Ok(containers)
+}
+
+/// In air-gapped mode, marks the status once Azure has been unreachable for longer than allowed
+async fn report_stale_connection(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    now: OffsetDateTime,
+) -> Result<(), ReconcileError> {
+    let Some(air_gap) = ctx.air_gap else {
+        return Ok(());
+    };
+
+    let mut status = sasgen.status.clone().unwrap_or_default();
+    let last_contact = status
+        .last_azure_contact
+        .as_deref()
+        .and_then(parse_rfc3339)
+        .or_else(|| ctx.metrics.azure_last_success(&sasgen.spec.storage_account));
+
+    if last_contact.is_some_and(|t| now - t < Duration::hours(air_gap.stale_after_hours)) {
+        return Ok(());
+    }
+
+    let message = format!(
+        "No successful Azure call since {}; current token expires at {}",
+        last_contact.map_or_else(|| "operator start".to_string(), format_rfc3339),
+        status
+            .expiry
+            .as_ref()
+            .map_or_else(|| "unknown".to_string(), format_time),
+    );
+    warn!(%message, "Operating on a stale Azure connection");
+
+    set_condition(
+        &mut status,
+        CONDITION_AZURE_CONNECTION_STALE,
+        true,
+        "AzureUnreachable",
+        message,
+        now,
+    );
+    update_crd_status(sasgen, ctx, status).await
+}
+
+/// Namespaces matching `spec.targetNamespaceSelector`, or the statically configured ones.
+/// Other namespaces than the CR's must accept its Secrets: selected ones that do not are
+/// skipped, configured ones fail the reconcile.
+async fn resolve_namespaces(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+) -> Result<Vec<String>, ReconcileError> {
+    let Some(selector) = sasgen.namespace_selector() else {
+        let namespaces = sasgen.target_namespaces();
+        check_target_namespaces(sasgen, ctx, &namespaces).await?;
+        return Ok(namespaces);
+    };
+    let source = sasgen.namespace().unwrap_or_default();
+    let selector = selector.map_err(|e| SpecError::Unsupported(e.to_string()))?;
+
+    let api: Api<Namespace> = Api::all(ctx.client.clone());
+    let mut namespaces: Vec<String> = api
+        .list(&ListParams::default().labels_from(&selector))
+        .await?
+        .items
+        .iter()
+        // Terminating namespaces reject new Secrets
+        .filter(|ns| ns.metadata.deletion_timestamp.is_none())
+        .filter(|ns| {
+            let accepted = accepts_secrets_from(ns, &source);
+            if !accepted {
+                debug!(namespace = %ns.name_any(), "Namespace does not accept Secrets from this CR");
+            }
+            accepted
+        })
+        .map(|ns| ns.name_any())
+        .collect();
+    namespaces.sort();
+
+    info!(?namespaces, "Discovered namespaces matching selector");
+    Ok(namespaces)
+}
+
+/// Adding or removing containers changes the set of Secrets, which must be written right away
+fn targets_changed(targets: &[SecretTarget], status: Option<&SasGeneratorStatus>) -> bool {
+    let Some(status) = status.filter(|s| s.expiry.is_some()) else {
+        return false;
+    };
+    let recorded: Vec<&str> = match &status.target_secret {
+        Some(name) => vec![name.as_str()],
+        None => status.target_secrets.iter().map(String::as_str).collect(),
+    };
+    let wanted: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    // Names repeat across namespaces, so namespace changes show only in the distribution
+    let namespaces_changed = !status.distribution.is_empty()
+        && targets.iter().any(|t| {
+            !status
+                .distribution
+                .iter()
+                .any(|d| d.namespace == t.namespace && d.secret == t.name)
+        });
+    if recorded != wanted || namespaces_changed {
+        info!(
+            ?recorded,
+            ?wanted,
+            "Target Secrets changed; regenerating SAS tokens"
+        );
+        return true;
+    }
+    false
+}
+
+/// An entry of `spec.outputs`, resolved for this reconcile
+struct ResolvedOutput {
+    name: String,
+    view: SasGenerator,
+    targets: Vec<SecretTarget>,
+    options: SasOptions,
+}
+
+/// Issues one token per container; a failure marks the Azure connection as stale
+#[allow(clippy::too_many_arguments)]
+async fn issue_tokens(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    auth: &StorageAuth,
+    location: &CloudLocation,
+    containers: &[String],
+    ttl_hours: i64,
+    now: OffsetDateTime,
+    start_skew_seconds: i64,
+    options: &SasOptions,
+    reason: &str,
+) -> Result<Vec<(String, SasTokenInfo)>, ReconcileError> {
+    let mut tokens = Vec::new();
+    let mut records = Vec::new();
+    for container in containers {
+        let call = generate_container_sas(
+            auth,
+            location,
+            container,
+            ttl_hours,
+            now,
+            start_skew_seconds,
+            options,
+        );
+        let token_info = match guarded(ctx, &sasgen.spec.storage_account, call).await {
+            Ok(info) => info,
+            Err(e) => {
+                report_stale_connection(sasgen, ctx, now).await?;
+                return Err(e);
             }
+        };
+
+        info!(%container, new_expiry = %token_info.expiry, "Generated new SAS token");
+        records.push(audit::IssuanceRecord::new(
+            sasgen,
+            auth,
+            container,
+            options,
+            &token_info,
+            start_skew_seconds,
+            reason,
+        ));
+        tokens.push((container.clone(), token_info));
+    }
+    let hash = tokens_hash(&tokens);
+    for mut record in records {
+        record.tokens_hash = hash.clone();
+        audit::record_issuance(&record);
+    }
+    Ok(tokens)
+}
+
+/// `expiry - renewalHours`, the time `should_regenerate` starts issuing a new token
+fn next_renewal_time(status: &SasGeneratorStatus, renewal_hours: i64) -> Option<String> {
+    let expiry = from_time(status.expiry.as_ref()?);
+    Some(format_rfc3339(expiry - Duration::hours(renewal_hours)))
+}
+
+/// Time until the CR next needs work: its renewal or the end of a blue/green overlap. Never
+/// shorter than the reconcile interval, nor longer than the operator ceiling, which keeps
+/// container discovery and connectivity checks going for idle CRs; the ceiling wins over a
+/// longer interval.
+fn requeue_after(
+    status: &SasGeneratorStatus,
+    renewal_hours: i64,
+    now: OffsetDateTime,
+    interval: StdDuration,
+    ceiling: StdDuration,
+) -> StdDuration {
+    let renewal = status
+        .expiry
+        .as_ref()
+        .map(|expiry| from_time(expiry) - Duration::hours(renewal_hours));
+    let overlap_end = status.overlap_until.as_deref().and_then(parse_rfc3339);
+    let wake = renewal.into_iter().chain(overlap_end).min();
+    let until_wake = wake
+        .map(|at| StdDuration::try_from(at - now).unwrap_or_default())
+        .unwrap_or(ceiling);
+    until_wake.max(interval).min(ceiling)
+}
+
+/// Rotations kept in `status.history`
+const MAX_ROTATION_HISTORY: usize = 10;
+
+/// Requests an immediate rotation when set to a value the CR has not rotated for yet, e.g.
+/// the time of the request as written by `sas rotate`
+pub const ROTATE_ANNOTATION: &str = "sas.azure.com/rotate";
+
+/// Prepends the rotation just rolled out to the bounded history
+fn record_rotation(status: &mut SasGeneratorStatus, reason: &str) {
+    let (Some(generated), Some(expiry)) = (status.generated.clone(), status.expiry.clone()) else {
+        return;
+    };
+    let secret_revisions = status
+        .distribution
+        .iter()
+        .filter_map(|d| {
+            let version = d.resource_version.as_ref()?;
+            Some(format!("{}/{}={version}", d.namespace, d.secret))
         })
+        .collect();
+    status.history.insert(
+        0,
+        RotationRecord {
+            generated,
+            expiry,
+            reason: reason.to_string(),
+            token_hash: status.token_hash.clone(),
+            secret_revisions,
+        },
+    );
+    status.history.truncate(MAX_ROTATION_HISTORY);
 }
 
-fn build_status(token_info: SasTokenInfo, secret_name: &str) -> SasGeneratorStatus {
+/// Fingerprint of all tokens of a rotation, recorded instead of the tokens themselves
+fn tokens_hash(tokens: &[(String, SasTokenInfo)]) -> String {
+    let tokens: Vec<&str> = tokens.iter().map(|(_, info)| info.token.as_str()).collect();
+    token_hash(&tokens.join("\n"))
+}
+
+/// All containers of a CR rotate together, so they share generated/expiry timestamps
+fn build_status(
+    tokens: &[(String, SasTokenInfo)],
+    targets: &[SecretTarget],
+    previous: Option<&SasGeneratorStatus>,
+) -> SasGeneratorStatus {
+    let first = &tokens[0].1;
+    let (target_secret, target_secrets) = match targets {
+        [target] => (Some(target.name.clone()), Vec::new()),
+        _ => (None, targets.iter().map(|t| t.name.clone()).collect()),
+    };
+
     SasGeneratorStatus {
-        token: Some(token_info.token),
-        target_secret: Some(secret_name.to_string()),
-        generated: Some(format_rfc3339(token_info.generated)),
-        expiry: Some(format_rfc3339(token_info.expiry)),
+        token_hash: Some(tokens_hash(tokens)),
+        target_secret,
+        target_secrets,
+        generated: Some(to_time(first.generated)),
+        expiry: Some(to_time(first.expiry)),
+        ..previous.cloned().unwrap_or_default()
     }
 }
 
-pub fn error_policy(
-    _obj: Arc<SasGenerator>,
-    err: &ReconcileError,
-    _ctx: Arc<ContextData>,
-) -> Action {
-    error!(?err, "Reconcile failed");
-    Action::requeue(StdDuration::from_secs(300))
+/// First retry delay after a failure that may clear by itself, e.g. throttling or a 5xx
+/// response, and the longest one as failures repeat
+const TRANSIENT_ERROR_BACKOFF: (StdDuration, StdDuration) =
+    (StdDuration::from_secs(30), StdDuration::from_secs(600));
+
+/// First and longest retry delay after a failure that needs someone to act, e.g. a missing
+/// role assignment
+const PERMANENT_ERROR_BACKOFF: (StdDuration, StdDuration) =
+    (StdDuration::from_secs(300), StdDuration::from_secs(3600));
+
+pub fn error_policy(obj: Arc<SasGenerator>, err: &ReconcileError, ctx: Arc<ContextData>) -> Action {
+    sentry::report_reconcile_error(&obj, err);
+    ctx.metrics.record_requeue(if err.is_terminal() {
+        "await_change"
+    } else if err.is_permanent() {
+        "permanent_error"
+    } else {
+        "transient_error"
+    });
+    match err {
+        // Retrying cannot fix a broken spec; the next spec change triggers a reconcile anyway
+        ReconcileError::Spec(e) => {
+            warn!(%e, "Spec is invalid; waiting for the CR to change");
+            Action::await_change()
+        }
+        ReconcileError::Template { .. } => {
+            warn!(%err, "Secret template cannot be rendered; waiting for the CR to change");
+            Action::await_change()
+        }
+        ReconcileError::CircuitOpen { retry_in, .. } => {
+            let (base, cap) = TRANSIENT_ERROR_BACKOFF;
+            let backoff = ctx.backoff.next_delay(&owner_key(&obj), base, cap);
+            warn!(%err, "Azure circuit is open; waiting for the cool-down");
+            Action::requeue((*retry_in).max(backoff))
+        }
+        _ if err.is_permanent() => {
+            let (base, cap) = PERMANENT_ERROR_BACKOFF;
+            let backoff = ctx.backoff.next_delay(&owner_key(&obj), base, cap);
+            error!(
+                ?err,
+                ?backoff,
+                "Reconcile failed; backing off until the cause is fixed"
+            );
+            Action::requeue(backoff)
+        }
+        _ => {
+            let (base, cap) = TRANSIENT_ERROR_BACKOFF;
+            let backoff = ctx.backoff.next_delay(&owner_key(&obj), base, cap);
+            // Retrying a throttled account before its retry-after only extends the throttling
+            let throttled = throttled_for(&blob_host(&obj.cloud_location()));
+            let backoff = throttled.map_or(backoff, |wait| wait.max(backoff));
+            warn!(?err, ?backoff, "Reconcile failed; retrying later");
+            Action::requeue(backoff)
+        }
+    }
+}
+
+/// Records an InvalidSpec condition (or clears a stale one) before any external call is made
+async fn sync_spec_condition(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    result: &Result<(), SpecError>,
+    now: OffsetDateTime,
+) -> Result<(), ReconcileError> {
+    let mut status = sasgen.status.clone().unwrap_or_default();
+    let changed = match result {
+        Err(e) => {
+            set_condition(
+                &mut status,
+                CONDITION_INVALID_SPEC,
+                true,
+                e.reason(),
+                e.to_string(),
+                now,
+            );
+            true
+        }
+        Ok(()) => remove_condition(&mut status, CONDITION_INVALID_SPEC),
+    };
+
+    if changed {
+        update_crd_status(sasgen, ctx, status).await?;
+    }
+    Ok(())
 }
 
-#[instrument(skip_all)]
+/// Records a failed reconcile in the Ready/Renewing/Degraded conditions. The status is
+/// re-read first, since the failed reconcile may already have written parts of it.
+async fn record_failure(sasgen: &SasGenerator, ctx: &ContextData, error: &ReconcileError) {
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let api: Api<SasGenerator> = Api::namespaced(ctx.client.clone(), &ns);
+    let current = match api.get_status(&sasgen.name_any()).await {
+        Ok(current) => current,
+        Err(e) => {
+            warn!(%e, "Failed to read status; not recording the reconcile failure");
+            return;
+        }
+    };
+
+    publish(
+        sasgen,
+        ctx,
+        EventType::Warning,
+        REASON_GENERATION_FAILED,
+        "Reconcile",
+        error.to_string(),
+    )
+    .await;
+
+    let now = OffsetDateTime::now_utc();
+    let renewal_hours = sasgen
+        .spec
+        .sas_renewal_hours
+        .unwrap_or(ctx.default_renewal_hours);
+    let renewing = current.status.as_ref().is_some_and(|s| s.expiry.is_some())
+        && should_regenerate(now, &current.status, renewal_hours);
+    let mut status = current.status.clone().unwrap_or_default();
+    mark_failed(&mut status, error, renewing, now);
+    if let Err(e) = update_crd_status(&current, ctx, status).await {
+        warn!(%e, "Failed to record the reconcile failure in status");
+    }
+}
+
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any(), cr_namespace = %sasgen.namespace().unwrap_or_default()))]
 pub async fn reconcile(
     sasgen: Arc<SasGenerator>,
     ctx: Arc<ContextData>,
 ) -> Result<Action, ReconcileError> {
-    let target_secret = sasgen.target_secret_name();
-    let labels = sasgen.secret_labels();
-    let annotations = sasgen.secret_annotations();
+    if ctx.is_excluded(&sasgen) {
+        debug!(
+            name = %sasgen.name_any(),
+            namespace = ?sasgen.metadata.namespace,
+            "Ignoring SasGenerator handled by another operator instance"
+        );
+        return Ok(Action::await_change());
+    }
+    let started = std::time::Instant::now();
+    let result = reconcile_sas_generator(sasgen.clone(), ctx.clone()).await;
+    ctx.metrics.observe_reconcile_duration(started.elapsed());
+    match &result {
+        Ok(_) => {
+            ctx.metrics.record_reconcile("success");
+            ctx.backoff.reset(&owner_key(&sasgen));
+        }
+        Err(e) => {
+            ctx.metrics.record_reconcile("error");
+            ctx.metrics.record_error(e.kind());
+            record_failure(&sasgen, &ctx, e).await;
+        }
+    }
+    result
+}
 
+async fn reconcile_sas_generator(
+    sasgen: Arc<SasGenerator>,
+    ctx: Arc<ContextData>,
+) -> Result<Action, ReconcileError> {
     sasgen.log_spec();
 
+    if sasgen.metadata.deletion_timestamp.is_some() {
+        finalize(&sasgen, &ctx).await?;
+        ctx.metrics.record_requeue("deleted");
+        return Ok(Action::await_change());
+    }
+
     let now = OffsetDateTime::now_utc();
     let renewal_hours = sasgen
         .spec
         .sas_renewal_hours
-        .unwrap_or(ctx.sas_renewal_hours);
-    let ttl_hours = sasgen.spec.sas_ttl_hours.unwrap_or(ctx.sas_ttl_hours);
+        .unwrap_or(ctx.default_renewal_hours);
+    let ttl_hours = sasgen.spec.sas_ttl_hours.unwrap_or(ctx.default_ttl_hours);
+    let start_skew_seconds = sasgen
+        .spec
+        .start_skew_seconds
+        .unwrap_or(ctx.start_skew_seconds);
+    let ceiling = StdDuration::from_secs(ctx.max_requeue_seconds);
+    let interval = StdDuration::from_secs(
+        sasgen
+            .spec
+            .reconcile_interval_seconds
+            .unwrap_or(ctx.reconcile_interval_seconds),
+    )
+    .min(ceiling);
 
-    if should_regenerate(now, &sasgen.status, renewal_hours) {
-        let token_info = generate_container_sas(
-            &sasgen.spec.storage_account,
-            &sasgen.spec.container_name,
+    let validation = validate_spec(&sasgen, ttl_hours, renewal_hours);
+    sync_spec_condition(&sasgen, &ctx, &validation, now).await?;
+    validation?;
+
+    let policy = PolicyCheck::load(&sasgen, &ctx).await?;
+    policy.check_account(&sasgen)?;
+    policy.check_managed_identity(&sasgen)?;
+    let (auth, location) = match &ctx.azurite {
+        Some(azurite) => (
+            StorageAuth::AccountKey(Secret::new(EMULATOR_ACCOUNT_KEY)),
+            azurite.cloud_location(),
+        ),
+        None => (storage_auth(&sasgen, &ctx).await?, sasgen.cloud_location()),
+    };
+    let containers = resolve_containers(&sasgen, &ctx, &auth, &location).await?;
+    policy.check_containers(&containers)?;
+    if containers.is_empty() {
+        warn!("No containers matched the selector; nothing to issue");
+        ctx.metrics.record_requeue("no_containers");
+        return Ok(Action::requeue(interval));
+    }
+    let namespaces = resolve_namespaces(&sasgen, &ctx).await?;
+    if namespaces.is_empty() {
+        warn!("No namespaces matched the selector; nothing to write");
+        ctx.metrics.record_requeue("no_namespaces");
+        return Ok(Action::requeue(interval));
+    }
+    let targets = sasgen.secret_targets(&containers, &namespaces);
+    let outputs: Vec<ResolvedOutput> = sasgen
+        .spec
+        .outputs
+        .iter()
+        .flatten()
+        .map(|output| {
+            let view = sasgen.for_output(output);
+            ResolvedOutput {
+                name: output.name.clone(),
+                targets: view.secret_targets(&containers, &namespaces),
+                view,
+                options: sasgen.output_sas_options(output),
+            }
+        })
+        .collect();
+    let all_targets: Vec<SecretTarget> = targets
+        .iter()
+        .chain(outputs.iter().flat_map(|o| &o.targets))
+        .cloned()
+        .collect();
+    ensure_finalizer(&sasgen, &ctx, &all_targets).await?;
+    let base_values = SecretValues {
+        account: sasgen.spec.storage_account.clone(),
+        blob_endpoint: blob_endpoint(&location),
+        additional_data: additional_data(&sasgen, &ctx).await?,
+        blob_scope: sasgen.spec.blob_scope.clone(),
+        ..Default::default()
+    };
+
+    let never_issued = sasgen
+        .status
+        .as_ref()
+        .and_then(|s| s.expiry.as_ref())
+        .is_none();
+    if let Some(import) = sasgen
+        .spec
+        .import_secret_ref
+        .as_ref()
+        .filter(|_| never_issued)
+    {
+        if import_token(
+            &sasgen,
+            &ctx,
+            import,
+            &targets,
+            &base_values,
+            renewal_hours,
+            now,
+        )
+        .await?
+        {
+            ctx.metrics.record_requeue("token_imported");
+            return Ok(Action::requeue(interval));
+        }
+    }
+
+    let rotation_request = sasgen.annotations().get(ROTATE_ANNOTATION);
+    let rotation_requested = rotation_request.is_some_and(|request| {
+        sasgen
+            .status
+            .as_ref()
+            .and_then(|s| s.rotation_request.as_ref())
+            != Some(request)
+    });
+    let mut repaired = None;
+    let rotation_reason = if rotation_requested {
+        Some("Requested")
+    } else if should_regenerate(now, &sasgen.status, renewal_hours) {
+        let initial = sasgen.status.as_ref().is_none_or(|s| s.expiry.is_none());
+        Some(if initial { "Initial" } else { "Renewal" })
+    } else if targets_changed(&all_targets, sasgen.status.as_ref()) {
+        Some("TargetsChanged")
+    } else if rollout_incomplete(sasgen.status.as_ref()) {
+        Some("RolloutIncomplete")
+    } else {
+        match repair_drift(&sasgen, &ctx, sasgen.status.as_ref()).await? {
+            Drift::None => None,
+            Drift::Repaired(distribution) => {
+                repaired = Some(distribution);
+                None
+            }
+            Drift::Unrepairable => Some("SecretDrift"),
+        }
+    };
+
+    let requeue;
+    if let Some(rotation_reason) = rotation_reason {
+        let sas_options = sasgen.sas_options();
+        let tokens = issue_tokens(
+            &sasgen,
+            &ctx,
+            &auth,
+            &location,
+            &containers,
             ttl_hours,
             now,
+            start_skew_seconds,
+            &sas_options,
+            rotation_reason,
         )
-        .await
-        .map_err(|e| ReconcileError::Azure(e.to_string()))?;
+        .await?;
+        let mut output_tokens = Vec::new();
+        for output in &outputs {
+            info!(output = %output.name, "Issuing tokens for output");
+            output_tokens.push(
+                issue_tokens(
+                    &sasgen,
+                    &ctx,
+                    &auth,
+                    &location,
+                    &containers,
+                    ttl_hours,
+                    now,
+                    start_skew_seconds,
+                    &output.options,
+                    rotation_reason,
+                )
+                .await?,
+            );
+        }
+
+        ctx.metrics
+            .record_azure_success(&sasgen.spec.storage_account, now);
+
+        let mut new_status = build_status(&tokens, &all_targets, sasgen.status.as_ref());
+        new_status.last_azure_contact = Some(format_rfc3339(now));
+        new_status.imported_from = None;
+        new_status.rotation_request = rotation_request.cloned();
+        new_status.next_renewal_time = next_renewal_time(&new_status, renewal_hours);
+        new_status.overlap_until = sasgen.blue_green().then(|| {
+            let overlap_hours = sasgen
+                .spec
+                .rotation_strategy
+                .as_ref()
+                .and_then(|r| r.overlap_hours)
+                .unwrap_or(renewal_hours);
+            format_rfc3339(now + Duration::hours(overlap_hours))
+        });
+        new_status.correlation_id = sas_options.correlation_id.clone();
+        remove_condition(&mut new_status, CONDITION_AZURE_CONNECTION_STALE);
+
+        if sasgen.spec.stamp_container_metadata.unwrap_or(false) {
+            let generated = new_status
+                .generated
+                .as_ref()
+                .map(format_time)
+                .unwrap_or_default();
+            let metadata = sasgen.container_metadata(&generated);
+            for (container, _) in &tokens {
+                // Auditing must not block credential rotation
+                if let Err(e) =
+                    stamp_container_metadata(&auth, &location, container, &metadata).await
+                {
+                    warn!(%container, error = ?e, "Failed to stamp container metadata; continuing");
+                }
+            }
+        }
+
+        let annotations = sasgen.secret_annotations(&new_status);
+        let (mut distribution, mut rollout) =
+            distribute(&sasgen, &ctx, &targets, &tokens, &base_values, &annotations).await;
+        for (output, tokens) in outputs.iter().zip(&output_tokens) {
+            let (states, result) = distribute(
+                &output.view,
+                &ctx,
+                &output.targets,
+                tokens,
+                &base_values,
+                &annotations,
+            )
+            .await;
+            distribution.extend(states);
+            rollout = rollout.and(result);
+        }
+        if rollout.is_ok() {
+            let previous = sasgen
+                .status
+                .as_ref()
+                .map(|s| s.distribution.as_slice())
+                .unwrap_or_default();
+            if let Err(e) = delete_stale_secrets(&sasgen, &ctx, previous, &all_targets).await {
+                warn!(%e, "Failed to delete Secrets that are no longer targets");
+            }
+        }
+        new_status.distribution = distribution;
+        record_rotation(&mut new_status, rotation_reason);
+        if rollout.is_ok() {
+            mark_ready(&mut new_status, now);
+            let (reason, verb) = match sasgen.status.as_ref().and_then(|s| s.expiry.as_ref()) {
+                Some(_) => (REASON_TOKEN_RENEWED, "Renewed"),
+                None => (REASON_TOKEN_GENERATED, "Generated"),
+            };
+            publish(
+                &sasgen,
+                &ctx,
+                EventType::Normal,
+                reason,
+                "Rotate",
+                format!(
+                    "{verb} SAS tokens valid until {}",
+                    new_status
+                        .expiry
+                        .as_ref()
+                        .map(format_time)
+                        .unwrap_or_default()
+                ),
+            )
+            .await;
+        }
+        let deprecations = sync_deprecation_condition(&sasgen, &mut new_status, now);
+        requeue = requeue_after(&new_status, renewal_hours, now, interval, ceiling);
 
-        info!(new_expiry = %token_info.expiry, "Generated new SAS token");
+        update_crd_status(&sasgen, &ctx, new_status).await?;
+        publish_deprecations(&sasgen, &ctx, &deprecations).await;
+        rollout?;
+    } else {
+        let mut status = sasgen.status.clone().unwrap_or_default();
+        let drift_repaired = repaired.is_some();
+        if let Some(distribution) = repaired {
+            status.distribution = distribution;
+        }
+        let overlap_ended = status
+            .overlap_until
+            .as_deref()
+            .and_then(parse_rfc3339)
+            .is_some_and(|until| now >= until);
+        if overlap_ended {
+            drop_previous_tokens(&sasgen, &ctx, &targets, &mut status.distribution).await?;
+            for output in &outputs {
+                drop_previous_tokens(
+                    &output.view,
+                    &ctx,
+                    &output.targets,
+                    &mut status.distribution,
+                )
+                .await?;
+            }
+            status.overlap_until = None;
+        }
+        // The renewal window may have changed without a rotation
+        let next_renewal = next_renewal_time(&status, renewal_hours);
+        let renewal_moved = status.next_renewal_time != next_renewal;
+        status.next_renewal_time = next_renewal;
+        mark_ready(&mut status, now);
+        let deprecations = sync_deprecation_condition(&sasgen, &mut status, now);
+        requeue = requeue_after(&status, renewal_hours, now, interval, ceiling);
+        if overlap_ended
+            || drift_repaired
+            || renewal_moved
+            || sasgen.status.as_ref().is_none_or(|s| {
+                s.conditions != status.conditions
+                    || s.consecutive_failures != status.consecutive_failures
+            })
+        {
+            update_crd_status(&sasgen, &ctx, status).await?;
+        }
+        publish_deprecations(&sasgen, &ctx, &deprecations).await;
+    }
+
+    debug!(?requeue, "Next reconcile scheduled");
+    ctx.metrics.record_requeue("scheduled");
+    Ok(Action::requeue(requeue))
+}
 
-        let new_status = build_status(token_info, &target_secret);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::crd::SasGeneratorStatus;
+    use crate::utils::to_time;
 
-        update_crd_status(&sasgen, &ctx, new_status.clone()).await?;
-        ensure_secret(&sasgen, &ctx, &target_secret, labels, annotations).await?;
+    #[test]
+    fn idle_crs_requeue_within_the_default_watchdog_window() {
+        let config = Config::from_env();
+        let window = StdDuration::from_secs(config.watchdog_silence_seconds);
+        let ceiling = StdDuration::from_secs(config.requeue_ceiling_seconds());
+        let now = OffsetDateTime::now_utc();
+        let renewing_later = SasGeneratorStatus {
+            expiry: Some(to_time(now + Duration::days(30))),
+            ..Default::default()
+        };
+        for status in [SasGeneratorStatus::default(), renewing_later] {
+            for interval in [config.reconcile_interval_seconds, 24 * 3600] {
+                let interval = StdDuration::from_secs(interval).min(ceiling);
+                let requeue =
+                    requeue_after(&status, config.sas_renewal_hours, now, interval, ceiling);
+                assert!(requeue < window, "{requeue:?} >= {window:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn requeue_ceiling_follows_the_watchdog() {
+        let mut config = Config::from_env();
+        config.max_requeue_seconds = 3600;
+        config.watchdog_silence_seconds = 900;
+        assert_eq!(config.requeue_ceiling_seconds(), 450);
+        config.watchdog_silence_seconds = 0;
+        assert_eq!(config.requeue_ceiling_seconds(), 3600);
+        config.max_requeue_seconds = 60;
+        config.watchdog_silence_seconds = 900;
+        assert_eq!(config.requeue_ceiling_seconds(), 60);
     }
 
-    Ok(Action::requeue(std::time::Duration::from_secs(15)))
+    #[test]
+    fn requeue_wakes_for_renewal_but_not_before_the_interval() {
+        let now = OffsetDateTime::now_utc();
+        let status = SasGeneratorStatus {
+            expiry: Some(to_time(now + Duration::hours(24) + Duration::minutes(5))),
+            ..Default::default()
+        };
+        let (interval, ceiling) = (StdDuration::from_secs(15), StdDuration::from_secs(450));
+        let requeue = requeue_after(&status, 24, now, interval, ceiling);
+        assert!(requeue <= StdDuration::from_secs(300) && requeue > StdDuration::from_secs(290));
+        let due = SasGeneratorStatus {
+            expiry: Some(to_time(now)),
+            ..Default::default()
+        };
+        assert_eq!(requeue_after(&due, 24, now, interval, ceiling), interval);
+    }
 }
@@ -0,0 +1,222 @@
+use crate::cleanup::{owner_key, OWNER_ANNOTATION};
+use crate::crd::{ClusterRef, ContextData, SasGenerator, SecretDistribution, SecretTarget};
+use crate::reconcile::ReconcileError;
+use crate::secret::read_secret_key;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Api, Client, ResourceExt};
+use std::collections::BTreeMap;
+use tracing::{info, instrument, warn};
+
+/// Default key of the kubeconfig in `kubeconfigSecretRef`
+const KUBECONFIG_KEY: &str = "kubeconfig";
+
+/// Only inline credentials are accepted: `exec` and `auth-provider` plugins would run
+/// commands inside the operator pod, and file paths would read its files, e.g. its own
+/// ServiceAccount token or Azure credentials
+fn check_kubeconfig(kubeconfig: &Kubeconfig) -> Result<(), String> {
+    for user in &kubeconfig.auth_infos {
+        let Some(auth) = &user.auth_info else {
+            continue;
+        };
+        let refused = [
+            ("exec", auth.exec.is_some()),
+            ("auth-provider", auth.auth_provider.is_some()),
+            ("tokenFile", auth.token_file.is_some()),
+            ("client-certificate", auth.client_certificate.is_some()),
+            ("client-key", auth.client_key.is_some()),
+        ];
+        if let Some((field, _)) = refused.iter().find(|(_, set)| *set) {
+            return Err(format!(
+                "user '{}' uses '{field}'; only token and client-certificate-data/client-key-data are supported",
+                user.name
+            ));
+        }
+    }
+    for cluster in &kubeconfig.clusters {
+        if cluster
+            .cluster
+            .as_ref()
+            .is_some_and(|c| c.certificate_authority.is_some())
+        {
+            return Err(format!(
+                "cluster '{}' uses 'certificate-authority'; use certificate-authority-data",
+                cluster.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds a client for `cluster` from the kubeconfig Secret in the CR namespace
+async fn remote_client(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    cluster: &ClusterRef,
+) -> Result<Client, ReconcileError> {
+    let remote_error = |message: String| ReconcileError::RemoteCluster {
+        cluster: cluster.name.clone(),
+        message,
+    };
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let secret_ref = &cluster.kubeconfig_secret_ref;
+    let key = secret_ref.key.as_deref().unwrap_or(KUBECONFIG_KEY);
+
+    let kubeconfig = read_secret_key(ctx, &ns, &secret_ref.name, key)
+        .await?
+        .ok_or_else(|| {
+            ReconcileError::Reference(format!(
+                "kubeconfig Secret {ns}/{} has no key '{key}'",
+                secret_ref.name
+            ))
+        })?;
+    let kubeconfig = Kubeconfig::from_yaml(&kubeconfig).map_err(|e| remote_error(e.to_string()))?;
+    check_kubeconfig(&kubeconfig).map_err(remote_error)?;
+    let config = kube::Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default())
+        .await
+        .map_err(|e| remote_error(e.to_string()))?;
+    Client::try_from(config).map_err(|e| remote_error(e.to_string()))
+}
+
+/// Writes the Secret for `target` into every cluster of `clusterRefs`, under the same
+/// namespace and name. Remote copies carry the owner annotation instead of an owner reference;
+/// a remote Secret without it is not overwritten.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any(), secret = %target.name))]
+pub async fn push_remote(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    target: &SecretTarget,
+    data: &BTreeMap<String, String>,
+    labels: &BTreeMap<String, String>,
+) -> Result<(), ReconcileError> {
+    for cluster in sasgen.spec.cluster_refs.iter().flatten() {
+        let client = remote_client(sasgen, ctx, cluster).await?;
+        let api: Api<Secret> = Api::namespaced(client, &target.namespace);
+        let remote_error = |e: kube::Error| ReconcileError::RemoteCluster {
+            cluster: cluster.name.clone(),
+            message: e.to_string(),
+        };
+        let existing = api.get_opt(&target.name).await.map_err(remote_error)?;
+        if existing
+            .is_some_and(|s| s.annotations().get(OWNER_ANNOTATION) != Some(&owner_key(sasgen)))
+        {
+            return Err(ReconcileError::RemoteCluster {
+                cluster: cluster.name.clone(),
+                message: format!(
+                    "Secret {}/{} exists and was not written by this SasGenerator; refusing to overwrite it",
+                    target.namespace, target.name
+                ),
+            });
+        }
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(target.name.clone()),
+                namespace: Some(target.namespace.clone()),
+                labels: Some(labels.clone()),
+                annotations: Some(BTreeMap::from([(
+                    OWNER_ANNOTATION.to_string(),
+                    owner_key(sasgen),
+                )])),
+                ..Default::default()
+            },
+            string_data: Some(data.clone()),
+            type_: Some(sasgen.secret_type().to_string()),
+            ..Default::default()
+        };
+        api.patch(
+            &target.name,
+            &PatchParams::apply("sas-operator").force(),
+            &Patch::Apply(&secret),
+        )
+        .await
+        .map_err(remote_error)?;
+        info!(cluster = %cluster.name, namespace = %target.namespace, "Secret written to remote cluster");
+    }
+    Ok(())
+}
+
+/// Deletes the remote copies of the `written` Secrets that carry this CR's owner annotation.
+/// An unreachable cluster must not hold up the CR's deletion, so its copies are left behind
+/// with a warning.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn delete_remote(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    written: &[SecretDistribution],
+) {
+    for cluster in sasgen.spec.cluster_refs.iter().flatten() {
+        if let Err(e) = delete_remote_copies(sasgen, ctx, cluster, written).await {
+            warn!(cluster = %cluster.name, error = %e, "Failed to delete remote Secrets; leaving them");
+        }
+    }
+}
+
+async fn delete_remote_copies(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    cluster: &ClusterRef,
+    written: &[SecretDistribution],
+) -> Result<(), ReconcileError> {
+    let client = remote_client(sasgen, ctx, cluster).await?;
+    for secret in written {
+        let api: Api<Secret> = Api::namespaced(client.clone(), &secret.namespace);
+        let owned = api
+            .get_opt(&secret.secret)
+            .await?
+            .is_some_and(|s| s.annotations().get(OWNER_ANNOTATION) == Some(&owner_key(sasgen)));
+        if owned {
+            api.delete(&secret.secret, &DeleteParams::default()).await?;
+            info!(cluster = %cluster.name, namespace = %secret.namespace, secret = %secret.secret, "Deleted remote Secret");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kubeconfig(user: &str) -> Kubeconfig {
+        Kubeconfig::from_yaml(&format!(
+            "apiVersion: v1
+kind: Config
+clusters:
+- name: dr
+  cluster:
+    server: https://dr.example.com
+    certificate-authority-data: Zm9v
+users:
+- name: sas
+  user:
+{user}
+contexts:
+- name: dr
+  context: {{ cluster: dr, user: sas }}
+current-context: dr
+"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn inline_credentials_are_accepted() {
+        assert!(check_kubeconfig(&kubeconfig("    token: abc")).is_ok());
+        assert!(check_kubeconfig(&kubeconfig(
+            "    client-certificate-data: Zm9v\n    client-key-data: YmFy"
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn plugins_and_files_are_refused() {
+        for user in [
+            "    exec: { apiVersion: client.authentication.k8s.io/v1, command: sh }",
+            "    auth-provider: { name: oidc }",
+            "    tokenFile: /var/run/secrets/kubernetes.io/serviceaccount/token",
+            "    client-certificate: /etc/tls.crt\n    client-key: /etc/tls.key",
+        ] {
+            assert!(check_kubeconfig(&kubeconfig(user)).is_err(), "{user}");
+        }
+    }
+}
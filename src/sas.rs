@@ -1,12 +1,21 @@
-use anyhow::{Context, Result};
-use azure_identity::{DefaultAzureCredential, TokenCredentialOptions};
-use azure_storage::prelude::SasToken;
+use crate::credentials::CredentialProvider;
+use crate::http::{new_http_client, throttled_for};
+use crate::ratelimit;
+use crate::signature::{ContainerSas, SasOptions, SigningKey};
+use anyhow::{bail, Context, Result};
+use azure_core::auth::{Secret, TokenCredential};
+use azure_core::headers::{AUTHORIZATION, MS_DATE, VERSION};
+use azure_core::{Method, Request, TransportOptions};
+use azure_identity::TokenCredentialOptions;
+use azure_storage::clients::ServiceType;
 use azure_storage::shared_access_signature::service_sas::BlobSasPermissions;
+use azure_storage::{CloudLocation, ConnectionString, StorageCredentials};
 use azure_storage_blobs::prelude::*;
+use futures::StreamExt;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use time::{Duration, OffsetDateTime};
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
-use tokio_retry::Retry;
 use tracing::{debug, info, instrument, warn};
 
 pub const SAS_PERMISSIONS: BlobSasPermissions = BlobSasPermissions {
@@ -25,6 +34,95 @@ pub const SAS_PERMISSIONS: BlobSasPermissions = BlobSasPermissions {
     permissions: true,
 };
 
+/// Snapshots and versions are immutable, so tokens scoped to one only need to read it
+pub const BLOB_SCOPE_SAS_PERMISSIONS: BlobSasPermissions = BlobSasPermissions {
+    read: true,
+    write: false,
+    add: false,
+    create: false,
+    delete: false,
+    delete_version: false,
+    permanent_delete: false,
+    list: false,
+    tags: false,
+    move_: false,
+    execute: false,
+    ownership: false,
+    permissions: false,
+};
+
+/// AAD scope for data-plane calls against Azure Storage
+const STORAGE_SCOPE: &str = "https://storage.azure.com/.default";
+
+/// REST API version used for data-plane calls not covered by the SDK
+const STORAGE_API_VERSION: &str = "2023-11-03";
+
+/// How the operator authenticates against a storage account and signs its tokens
+#[derive(Debug, Clone)]
+pub enum StorageAuth {
+    /// Azure AD identity; tokens are user delegation SAS
+    Aad {
+        provider: Arc<dyn CredentialProvider>,
+        options: TokenCredentialOptions,
+    },
+    /// Shared account key; tokens are service SAS signed locally, without a delegation key
+    AccountKey(Secret),
+}
+
+impl StorageAuth {
+    fn storage_credentials(&self, account: &str) -> Result<StorageCredentials> {
+        Ok(match self {
+            StorageAuth::Aad { provider, options } => {
+                StorageCredentials::token_credential(create_credential(provider.as_ref(), options)?)
+            }
+            StorageAuth::AccountKey(key) => {
+                StorageCredentials::access_key(account.to_string(), key.clone())
+            }
+        })
+    }
+
+    fn service_client(&self, location: &CloudLocation) -> Result<BlobServiceClient> {
+        let credentials = self.storage_credentials(location.account())?;
+        Ok(ClientBuilder::with_location(location.clone(), credentials)
+            .transport(TransportOptions::new(new_http_client()))
+            .blob_service_client())
+    }
+}
+
+/// Extracts the shared key from a connection string, which must be issued for `account`
+pub fn account_key_from_connection_string(value: &str, account: &str) -> Result<Secret> {
+    let parsed = ConnectionString::new(value).context("Failed to parse connection string")?;
+    match parsed.account_name {
+        Some(name) if name == account => {}
+        Some(name) => bail!("connection string is for account '{name}', not '{account}'"),
+        None => bail!("connection string has no AccountName"),
+    }
+    let key = parsed.account_key.context(
+        "connection string has no AccountKey; SAS connection strings cannot sign tokens",
+    )?;
+    Ok(Secret::new(key.to_string()))
+}
+
+/// Blob service endpoint of `location`, without a trailing slash
+pub fn blob_endpoint(location: &CloudLocation) -> String {
+    location
+        .url(ServiceType::Blob)
+        .map(|url| url.as_str().trim_end_matches('/').to_string())
+        .unwrap_or_default()
+}
+
+/// Host of the blob endpoint, under which Azure throttling is tracked
+pub fn blob_host(location: &CloudLocation) -> String {
+    location
+        .url(ServiceType::Blob)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/// Longest retry-after waited out inside a reconcile before handing over to the requeue
+const MAX_IN_RECONCILE_WAIT: std::time::Duration = std::time::Duration::from_secs(30);
+
 #[derive(Debug, Clone)]
 pub struct SasTokenInfo {
     pub token: String,
@@ -32,25 +130,44 @@ pub struct SasTokenInfo {
     pub generated: OffsetDateTime,
 }
 
-#[instrument(skip_all, fields(account = %account, container = %container, expiry_hours = expiry_hours))]
+#[instrument(skip_all, fields(account = %location.account(), container = %container, expiry_hours, start_skew_seconds))]
 pub async fn generate_container_sas(
-    account: &str,
+    auth: &StorageAuth,
+    location: &CloudLocation,
     container: &str,
     expiry_hours: i64,
     now: OffsetDateTime,
+    start_skew_seconds: i64,
+    options: &SasOptions,
 ) -> Result<SasTokenInfo> {
-    let start = now - Duration::seconds(5);
+    let start = now - Duration::seconds(start_skew_seconds);
     let expiry = now + Duration::hours(expiry_hours);
 
+    let account = location.account();
+
     info!("Starting SAS token generation for container");
 
-    let credential =
-        create_credential().context("Failed to create Azure DefaultAzureCredential")?;
-    debug!("Azure DefaultAzureCredential initialized successfully");
+    if let StorageAuth::AccountKey(key) = auth {
+        debug!("Signing service SAS with the account key");
+        let token = ContainerSas {
+            key: SigningKey::Account(key),
+            account,
+            container,
+            permissions: sas_permissions(options),
+            start,
+            expiry,
+            options,
+        }
+        .token()
+        .context("Failed to sign SAS token with the account key")?;
+        return Ok(SasTokenInfo {
+            token,
+            expiry,
+            generated: now,
+        });
+    }
 
-    let storage_credentials = azure_storage::StorageCredentials::token_credential(credential);
-    let service_client = BlobServiceClient::new(account.to_string(), storage_credentials);
-    let container_client = service_client.container_client(container);
+    let container_client = auth.service_client(location)?.container_client(container);
 
     let retry_strategy = ExponentialBackoff::from_millis(500)
         .factor(2)
@@ -63,20 +180,26 @@ pub async fn generate_container_sas(
         "Attempting SAS generation with exponential backoff"
     );
 
-    let sas_token = Retry::spawn(retry_strategy, || async {
-        match generate_client(&container_client, start, expiry).await {
+    let host = blob_host(location);
+    let mut delays = retry_strategy;
+    let sas_token = loop {
+        let error = match generate_client(&container_client, start, expiry, options).await {
             Ok(token) => {
                 info!("SAS token generated successfully on this attempt");
-                Ok(token)
-            }
-            Err(e) => {
-                warn!(error = ?e, "SAS generation attempt failed; retrying...");
-                Err(e)
+                break token;
             }
-        }
-    })
-    .await
-    .context("Failed to generate SAS token after retries")?;
+            Err(e) => e,
+        };
+        // A throttled account says when to come back; retrying earlier only prolongs the
+        // throttling. Long waits are left to the controller requeue instead of this reconcile.
+        let delay = match (throttled_for(&host), delays.next()) {
+            (Some(wait), Some(_)) if wait <= MAX_IN_RECONCILE_WAIT => wait,
+            (None, Some(backoff)) => backoff,
+            _ => return Err(error.context("Failed to generate SAS token after retries")),
+        };
+        warn!(error = ?error, ?delay, "SAS generation attempt failed; retrying...");
+        tokio::time::sleep(delay).await;
+    };
 
     info!(
         %account,
@@ -92,15 +215,54 @@ pub async fn generate_container_sas(
     })
 }
 
-#[instrument]
-fn create_credential() -> Result<Arc<DefaultAzureCredential>> {
-    debug!("Initializing DefaultAzureCredential (supports env vars, managed identity, workload identity)");
+/// Explicit permissions if set, else full permissions for container tokens and read-only for
+/// snapshot/version scoped ones
+pub fn sas_permissions(options: &SasOptions) -> String {
+    match (&options.permissions, &options.blob_scope) {
+        (Some(permissions), _) => permissions.clone(),
+        (None, Some(_)) => BLOB_SCOPE_SAS_PERMISSIONS.to_string(),
+        (None, None) => SAS_PERMISSIONS.to_string(),
+    }
+}
+
+/// Parses SAS permission letters (`racwdxyltmeop`); returns the first unknown letter on error
+pub fn parse_permissions(letters: &str) -> Result<BlobSasPermissions, char> {
+    let mut permissions = BlobSasPermissions::default();
+    for letter in letters.chars() {
+        let flag = match letter {
+            'r' => &mut permissions.read,
+            'a' => &mut permissions.add,
+            'c' => &mut permissions.create,
+            'w' => &mut permissions.write,
+            'd' => &mut permissions.delete,
+            'x' => &mut permissions.delete_version,
+            'y' => &mut permissions.permanent_delete,
+            'l' => &mut permissions.list,
+            't' => &mut permissions.tags,
+            'm' => &mut permissions.move_,
+            'e' => &mut permissions.execute,
+            'o' => &mut permissions.ownership,
+            'p' => &mut permissions.permissions,
+            other => return Err(other),
+        };
+        *flag = true;
+    }
+    Ok(permissions)
+}
+
+#[instrument(skip_all, fields(kind = provider.kind()))]
+fn create_credential(
+    provider: &dyn CredentialProvider,
+    options: &TokenCredentialOptions,
+) -> Result<Arc<dyn TokenCredential>> {
+    debug!("Initializing Azure token credential");
 
-    let credential = DefaultAzureCredential::create(TokenCredentialOptions::default())
-        .context("Failed to initialize DefaultAzureCredential")?;
+    let credential = provider
+        .credential(options.clone())
+        .context("Failed to create Azure token credential")?;
 
-    info!("DefaultAzureCredential created successfully");
-    Ok(Arc::new(credential))
+    info!("Azure token credential created successfully");
+    Ok(credential)
 }
 
 #[instrument(skip_all, fields(container = %container_client.container_name()))]
@@ -108,7 +270,9 @@ async fn generate_client(
     container_client: &ContainerClient,
     start: OffsetDateTime,
     expiry: OffsetDateTime,
+    options: &SasOptions,
 ) -> Result<String> {
+    ratelimit::acquire().await;
     debug!("Requesting user delegation key from Azure Storage");
 
     let user_delegation_key = container_client
@@ -125,18 +289,168 @@ async fn generate_client(
         "Generating SAS token using delegation key and predefined permissions"
     );
 
-    let client = container_client
-        .user_delegation_shared_access_signature(
-            SAS_PERMISSIONS,
-            &user_delegation_key.user_deligation_key,
-        )
-        .await
-        .context("Failed to generate SAS token")?;
+    let token = ContainerSas {
+        key: SigningKey::UserDelegation(&user_delegation_key.user_deligation_key),
+        account: container_client.service_client().account(),
+        container: container_client.container_name(),
+        permissions: sas_permissions(options),
+        start,
+        expiry,
+        options,
+    }
+    .token()
+    .context("Failed to generate SAS token")?;
 
     info!(
         container = %container_client.container_name(),
         "SAS token generated successfully"
     );
 
-    Ok(client.token()?)
+    Ok(token)
+}
+
+/// Fetches an Azure AD token for the storage scope, proving the credential itself works
+pub async fn check_storage_token(
+    provider: &dyn CredentialProvider,
+    options: &TokenCredentialOptions,
+) -> Result<()> {
+    create_credential(provider, options)?
+        .get_token(&[STORAGE_SCOPE])
+        .await
+        .context("Failed to get an Azure AD token for Azure Storage")?;
+    Ok(())
+}
+
+/// Requests a short-lived user delegation key, proving the identity may sign user
+/// delegation SAS for the account
+pub async fn check_user_delegation_key(auth: &StorageAuth, location: &CloudLocation) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    auth.service_client(location)?
+        .get_user_deligation_key(now, now + Duration::minutes(5))
+        .await
+        .context("Failed to fetch user delegation key")?;
+    Ok(())
+}
+
+/// Lists at most one blob of the container, proving it exists and its data can be read
+pub async fn check_container_read(
+    auth: &StorageAuth,
+    location: &CloudLocation,
+    container: &str,
+) -> Result<()> {
+    let mut pages = auth
+        .service_client(location)?
+        .container_client(container)
+        .list_blobs()
+        .max_results(std::num::NonZeroU32::MIN)
+        .into_stream();
+    if let Some(page) = pages.next().await {
+        page.context("Failed to list blobs")?;
+    }
+    Ok(())
+}
+
+/// Merges `entries` into the container metadata so storage-side auditors can see rotations.
+/// The SDK has no Set Container Metadata operation, so the request is issued directly.
+#[instrument(skip_all, fields(account = %location.account(), container = %container))]
+pub async fn stamp_container_metadata(
+    auth: &StorageAuth,
+    location: &CloudLocation,
+    container: &str,
+    entries: &BTreeMap<String, String>,
+) -> Result<()> {
+    let StorageAuth::Aad { provider, options } = auth else {
+        bail!("Container metadata stamping needs an Azure AD identity");
+    };
+    let credential = create_credential(provider.as_ref(), options)?;
+
+    let storage_credentials = StorageCredentials::token_credential(credential.clone());
+    let container_client = ClientBuilder::with_location(location.clone(), storage_credentials)
+        .transport(TransportOptions::new(new_http_client()))
+        .container_client(container);
+
+    // Set Container Metadata replaces everything, so keep what is already there
+    let mut metadata = container_client
+        .get_properties()
+        .await
+        .context("Failed to read container properties")?
+        .container
+        .metadata;
+    metadata.extend(entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    let token = credential
+        .get_token(&[STORAGE_SCOPE])
+        .await
+        .context("Failed to acquire storage access token")?;
+
+    let mut url = container_client.url()?;
+    url.query_pairs_mut()
+        .append_pair("restype", "container")
+        .append_pair("comp", "metadata");
+
+    let mut request = Request::new(url, Method::Put);
+    request.insert_header(AUTHORIZATION, format!("Bearer {}", token.token.secret()));
+    request.insert_header(VERSION, STORAGE_API_VERSION);
+    request.insert_header(
+        MS_DATE,
+        azure_core::date::to_rfc1123(&OffsetDateTime::now_utc()),
+    );
+    for (key, value) in &metadata {
+        request.insert_header(format!("x-ms-meta-{key}"), value.clone());
+    }
+    request.insert_header("content-length", "0");
+
+    debug!(keys = metadata.len(), "Setting container metadata");
+
+    new_http_client()
+        .execute_request_check_status(&request)
+        .await
+        .context("Failed to set container metadata")?;
+
+    info!("Container metadata stamped successfully");
+    Ok(())
+}
+
+/// Reads the signed expiry (`se`) and start (`st`) out of an existing SAS token
+pub fn parse_token_validity(token: &str) -> Option<(Option<OffsetDateTime>, OffsetDateTime)> {
+    let query = token.trim_start_matches('?');
+    let field = |name: &str| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| parse_sas_time(&v))
+    };
+    Some((field("st"), field("se")?))
+}
+
+/// SAS times are ISO 8601 in UTC, either full timestamps or plain dates
+fn parse_sas_time(value: &str) -> Option<OffsetDateTime> {
+    use time::format_description::well_known::Rfc3339;
+    OffsetDateTime::parse(value, &Rfc3339)
+        .or_else(|_| OffsetDateTime::parse(&format!("{value}T00:00:00Z"), &Rfc3339))
+        .ok()
+}
+
+/// Lists the containers of a storage account, optionally filtered by name prefix
+#[instrument(skip_all, fields(account = %location.account(), prefix = ?prefix))]
+pub async fn list_containers(
+    auth: &StorageAuth,
+    location: &CloudLocation,
+    prefix: Option<&str>,
+) -> Result<Vec<String>> {
+    let service_client = auth.service_client(location)?;
+
+    let mut builder = service_client.list_containers();
+    if let Some(prefix) = prefix {
+        builder = builder.prefix(prefix.to_string());
+    }
+
+    let mut names = Vec::new();
+    let mut pages = builder.into_stream();
+    while let Some(page) = pages.next().await {
+        let page = page.context("Failed to list containers")?;
+        names.extend(page.containers.into_iter().map(|c| c.name));
+    }
+
+    debug!(count = names.len(), "Listed containers");
+    Ok(names)
 }
@@ -0,0 +1,136 @@
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+    JSONSchemaProps, JSONSchemaPropsOrArray, JSONSchemaPropsOrBool,
+};
+use serde_json::Value;
+
+/// Checks `value` against a CRD's structural schema the way the API server does before
+/// storing it: types, required and unknown fields, enums, patterns, lengths and bounds.
+/// Returns one message per violation, prefixed with the field path.
+pub fn check(value: &Value, schema: &JSONSchemaProps, path: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    visit(value, schema, path, &mut errors);
+    errors
+}
+
+fn visit(value: &Value, schema: &JSONSchemaProps, path: &str, errors: &mut Vec<String>) {
+    if value.is_null() && schema.nullable == Some(true) {
+        return;
+    }
+    if let Some(branches) = &schema.any_of {
+        let failures: Vec<Vec<String>> = branches.iter().map(|b| check(value, b, path)).collect();
+        if !failures.iter().any(Vec::is_empty) {
+            errors.extend(failures.into_iter().next().unwrap_or_default());
+            return;
+        }
+    }
+    if let Some(allowed) = &schema.enum_ {
+        if !allowed.iter().any(|a| a.0 == *value) {
+            let allowed: Vec<String> = allowed.iter().map(|a| a.0.to_string()).collect();
+            errors.push(format!("{path}: must be one of {}", allowed.join(", ")));
+            return;
+        }
+    }
+    if schema.x_kubernetes_int_or_string == Some(true) {
+        if !(value.is_i64() || value.is_u64() || value.is_string()) {
+            errors.push(format!("{path}: must be an integer or a string"));
+        }
+        return;
+    }
+
+    match (schema.type_.as_deref(), value) {
+        (None, _) => {}
+        (Some("object"), Value::Object(fields)) => {
+            for name in schema.required.iter().flatten() {
+                if !fields.contains_key(name) {
+                    errors.push(format!("{}: required", join(path, name)));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = join(path, name);
+                match (&schema.properties, &schema.additional_properties) {
+                    (Some(properties), _) if properties.contains_key(name) => {
+                        visit(field, &properties[name], &field_path, errors)
+                    }
+                    (_, Some(JSONSchemaPropsOrBool::Schema(values))) => {
+                        visit(field, values, &field_path, errors)
+                    }
+                    (_, Some(JSONSchemaPropsOrBool::Bool(true))) => {}
+                    _ if schema.x_kubernetes_preserve_unknown_fields == Some(true) => {}
+                    _ => errors.push(format!("{field_path}: unknown field")),
+                }
+            }
+        }
+        (Some("array"), Value::Array(items)) => {
+            if let Some(JSONSchemaPropsOrArray::Schema(item_schema)) = &schema.items {
+                for (i, item) in items.iter().enumerate() {
+                    visit(item, item_schema, &format!("{path}[{i}]"), errors);
+                }
+            }
+            check_bounds(
+                path,
+                items.len(),
+                schema.min_items,
+                schema.max_items,
+                "items",
+                errors,
+            );
+        }
+        (Some("string"), Value::String(s)) => {
+            let length = s.chars().count();
+            check_bounds(
+                path,
+                length,
+                schema.min_length,
+                schema.max_length,
+                "characters",
+                errors,
+            );
+            if let Some(pattern) = &schema.pattern {
+                if regex::Regex::new(pattern).is_ok_and(|re| !re.is_match(s)) {
+                    errors.push(format!("{path}: must match {pattern}"));
+                }
+            }
+        }
+        (Some("integer"), Value::Number(n)) if n.is_i64() || n.is_u64() => {
+            check_range(path, n.as_f64(), schema, errors)
+        }
+        (Some("number"), Value::Number(n)) => check_range(path, n.as_f64(), schema, errors),
+        (Some("boolean"), Value::Bool(_)) => {}
+        (Some(expected), _) => errors.push(format!("{path}: must be of type {expected}")),
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+fn check_bounds(
+    path: &str,
+    len: usize,
+    min: Option<i64>,
+    max: Option<i64>,
+    unit: &str,
+    errors: &mut Vec<String>,
+) {
+    let len = len as i64;
+    if let Some(min) = min.filter(|min| len < *min) {
+        errors.push(format!("{path}: must have at least {min} {unit}"));
+    }
+    if let Some(max) = max.filter(|max| len > *max) {
+        errors.push(format!("{path}: must have at most {max} {unit}"));
+    }
+}
+
+fn check_range(path: &str, n: Option<f64>, schema: &JSONSchemaProps, errors: &mut Vec<String>) {
+    let Some(n) = n else { return };
+    if let Some(min) = schema.minimum.filter(|min| n < *min) {
+        errors.push(format!("{path}: must be at least {min}"));
+    }
+    if let Some(max) = schema.maximum.filter(|max| n > *max) {
+        errors.push(format!("{path}: must be at most {max}"));
+    }
+}
@@ -1,63 +1,462 @@
-use crate::crd::{ContextData, SasGenerator};
+use crate::cleanup::{owned_by, secret_owner};
+use crate::crd::{BlobScope, ContextData, SasGenerator, SecretKeys, SecretTarget};
+use crate::distribute::WrittenSecret;
+use crate::output;
 use crate::reconcile::ReconcileError;
-use k8s_openapi::api::core::v1::Secret;
+use crate::template;
+use crate::utils::{format_rfc3339, token_hash};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
 use kube::api::{Patch, PatchParams};
-use kube::{Api, Resource, ResourceExt};
+use kube::{Api, ResourceExt};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use time::OffsetDateTime;
 use tracing::{debug, info, instrument, warn};
+use url::Url;
 
-#[instrument(skip(ctx), fields(cr_name = %sasgen.name_any()))]
+pub const REVISIONS_ANNOTATION: &str = "sas.azure.com/revisions";
+
+/// Hash of the tokens in a Secret, changing on every rotation; meant to be copied into a
+/// pod template annotation so Deployments roll when the token changes
+pub const TOKEN_CHECKSUM_ANNOTATION: &str = "sas.azure.com/token-checksum";
+
+/// Number of revisions kept in the revision log annotation
+const MAX_REVISIONS: usize = 10;
+
+/// One entry of the per-Secret revision log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecretRevision {
+    changed: String,
+    keys: Vec<String>,
+    token_hash: String,
+}
+
+/// Appends a revision for every key whose value differs from the existing Secret,
+/// keeping the newest `MAX_REVISIONS` entries. `token_checksum` is the Secret's
+/// `SecretValues::token_checksum`, so entries match its `TOKEN_CHECKSUM_ANNOTATION`.
+fn next_revision_log(
+    existing: Option<&Secret>,
+    data: &BTreeMap<String, String>,
+    token_checksum: &str,
+    now: OffsetDateTime,
+) -> Option<String> {
+    let previous_data = existing.and_then(|s| s.data.as_ref());
+    let mut log: Vec<SecretRevision> = existing
+        .and_then(|s| s.metadata.annotations.as_ref())
+        .and_then(|a| a.get(REVISIONS_ANNOTATION))
+        .and_then(|raw| serde_json::from_str(raw).ok())
+        .unwrap_or_default();
+
+    let changed: Vec<String> = data
+        .iter()
+        .filter(|(key, value)| {
+            previous_data
+                .and_then(|d| d.get(*key))
+                .is_none_or(|old| old.0 != value.as_bytes())
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    if !changed.is_empty() {
+        log.push(SecretRevision {
+            changed: format_rfc3339(now),
+            keys: changed,
+            token_hash: token_checksum.to_string(),
+        });
+        let overflow = log.len().saturating_sub(MAX_REVISIONS);
+        log.drain(..overflow);
+    }
+
+    (!log.is_empty()).then(|| serde_json::to_string(&log).unwrap_or_default())
+}
+
+/// Reads all keys of a Secret; `Ok(None)` when the Secret does not exist
+#[instrument(skip(ctx))]
+pub async fn read_secret_data(
+    ctx: &ContextData,
+    ns: &str,
+    name: &str,
+) -> Result<Option<BTreeMap<String, Vec<u8>>>, ReconcileError> {
+    let api: Api<Secret> = Api::namespaced(ctx.client.clone(), ns);
+    let Some(secret) = api.get_opt(name).await? else {
+        warn!(%name, %ns, "Referenced Secret not found");
+        return Ok(None);
+    };
+
+    Ok(Some(
+        secret
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.0))
+            .collect(),
+    ))
+}
+
+/// Reads a single key from a Secret; `Ok(None)` when the Secret or the key does not exist
+#[instrument(skip(ctx))]
+pub async fn read_secret_key(
+    ctx: &ContextData,
+    ns: &str,
+    name: &str,
+    key: &str,
+) -> Result<Option<String>, ReconcileError> {
+    let Some(mut data) = read_secret_data(ctx, ns, name).await? else {
+        return Ok(None);
+    };
+
+    let value = data
+        .remove(key)
+        .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string());
+    if value.is_none() {
+        warn!(%name, %key, "Referenced Secret has no such key");
+    }
+    Ok(value)
+}
+
+/// Everything one Secret payload is built from
+#[derive(Debug, Clone, Default)]
+pub struct SecretValues {
+    pub account: String,
+    /// Blob service endpoint, without a trailing slash
+    pub blob_endpoint: String,
+    /// `(container, token)` pairs carried by the Secret
+    pub tokens: Vec<(String, String)>,
+    pub expiry: String,
+    /// Merged `spec.additionalDataFrom` and `spec.additionalData` entries
+    pub additional_data: BTreeMap<String, String>,
+    /// `(container, token)` pairs replaced by a blue/green rotation that are still published
+    pub previous_tokens: Vec<(String, String)>,
+    /// The single blob the tokens are scoped to (`spec.blobScope`)
+    pub blob_scope: Option<BlobScope>,
+}
+
+impl SecretValues {
+    /// Fingerprint of the tokens, for `TOKEN_CHECKSUM_ANNOTATION`
+    pub fn token_checksum(&self) -> String {
+        let tokens: Vec<&str> = self.tokens.iter().map(|(_, t)| t.as_str()).collect();
+        token_hash(&tokens.join("\n"))
+    }
+
+    /// Container URL with the token appended, usable as-is by curl or azcopy. With a blob
+    /// scope it is the URL of that blob snapshot or version, as the token grants nothing else.
+    pub fn container_url(&self, container: &str, token: &str) -> String {
+        let container_url = format!("{}/{container}", self.blob_endpoint);
+        let Some(scope) = &self.blob_scope else {
+            return format!("{container_url}?{token}");
+        };
+        let mut url = match Url::parse(&container_url) {
+            Ok(url) => url,
+            Err(_) => return format!("{container_url}/{}?{token}", scope.blob_name),
+        };
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.extend(scope.blob_name.split('/'));
+        }
+        let pin = match (&scope.snapshot, &scope.version_id) {
+            (Some(snapshot), _) => Some(("snapshot", snapshot)),
+            (None, Some(version)) => Some(("versionid", version)),
+            (None, None) => None,
+        };
+        if let Some((key, value)) = pin {
+            url.query_pairs_mut().append_pair(key, value);
+        }
+        // The token is already encoded
+        let pinned = url
+            .query()
+            .filter(|q| !q.is_empty())
+            .map(|q| format!("{q}&"));
+        url.set_query(None);
+        format!("{url}?{}{token}", pinned.unwrap_or_default())
+    }
+
+    /// SAS connection string accepted by the Azure SDKs
+    pub fn connection_string(&self, token: &str) -> String {
+        format!(
+            "BlobEndpoint={}/;SharedAccessSignature={token}",
+            self.blob_endpoint
+        )
+    }
+
+    /// Variables available to `spec.template`
+    fn template_variables(&self) -> BTreeMap<String, String> {
+        let containers: Vec<&str> = self.tokens.iter().map(|(c, _)| c.as_str()).collect();
+        let mut vars = BTreeMap::from([
+            ("account".to_string(), self.account.clone()),
+            ("endpoint".to_string(), self.blob_endpoint.clone()),
+            ("expiry".to_string(), self.expiry.clone()),
+            ("containers".to_string(), containers.join(",")),
+        ]);
+        for (container, token) in &self.tokens {
+            vars.insert(format!("token_{container}"), token.clone());
+            vars.insert(
+                format!("url_{container}"),
+                self.container_url(container, token),
+            );
+            vars.insert(
+                format!("connection_string_{container}"),
+                self.connection_string(token),
+            );
+        }
+        if let [(container, token)] = self.tokens.as_slice() {
+            vars.insert("token".into(), token.clone());
+            vars.insert("container".into(), container.clone());
+            vars.insert("url".into(), self.container_url(container, token));
+            vars.insert("connection_string".into(), self.connection_string(token));
+        }
+        vars
+    }
+}
+
+/// Collects the extra entries from `spec.additionalDataFrom`, in order, overlaid with
+/// `spec.additionalData`. Referenced objects must exist.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any()))]
+pub async fn additional_data(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+) -> Result<BTreeMap<String, String>, ReconcileError> {
+    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    let mut data = BTreeMap::new();
+
+    for source in sasgen.spec.additional_data_from.iter().flatten() {
+        match (&source.secret_ref, &source.config_map_ref) {
+            (Some(secret_ref), None) => {
+                let entries = read_secret_data(ctx, &ns, &secret_ref.name)
+                    .await?
+                    .ok_or_else(|| {
+                        ReconcileError::Reference(format!(
+                            "Secret {ns}/{} not found",
+                            secret_ref.name
+                        ))
+                    })?;
+                data.extend(
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| (k, String::from_utf8_lossy(&v).into_owned())),
+                );
+            }
+            (None, Some(config_map_ref)) => {
+                let api: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), &ns);
+                let config_map = api.get_opt(&config_map_ref.name).await?.ok_or_else(|| {
+                    ReconcileError::Reference(format!(
+                        "ConfigMap {ns}/{} not found",
+                        config_map_ref.name
+                    ))
+                })?;
+                data.extend(config_map.data.unwrap_or_default());
+            }
+            _ => {
+                return Err(ReconcileError::Reference(
+                    "additionalDataFrom entries need exactly one of secretRef and configMapRef"
+                        .into(),
+                ))
+            }
+        }
+    }
+
+    data.extend(sasgen.spec.additional_data.clone().unwrap_or_default());
+    debug!(keys = ?data.keys().collect::<Vec<_>>(), "Resolved additional Secret data");
+    Ok(data)
+}
+
+/// Default layout: a single container gets flat `sas_token`/`container`/`url`/`connection_string`
+/// keys, several containers one suffixed key of each per container. Key names follow `keys`.
+fn default_data(keys: &SecretKeys, values: &SecretValues) -> BTreeMap<String, String> {
+    let mut data = BTreeMap::from([(keys.account().to_string(), values.account.clone())]);
+
+    match values.tokens.as_slice() {
+        [(container, token)] => {
+            data.insert(keys.sas_token().into(), token.clone());
+            data.insert(keys.container().into(), container.clone());
+            data.insert(keys.url().into(), values.container_url(container, token));
+            data.insert(
+                keys.connection_string().into(),
+                values.connection_string(token),
+            );
+        }
+        tokens => {
+            for (container, token) in tokens {
+                data.insert(format!("{}_{container}", keys.sas_token()), token.clone());
+                data.insert(
+                    format!("{}_{container}", keys.url()),
+                    values.container_url(container, token),
+                );
+                data.insert(
+                    format!("{}_{container}", keys.connection_string()),
+                    values.connection_string(token),
+                );
+            }
+            let containers: Vec<&str> = tokens.iter().map(|(c, _)| c.as_str()).collect();
+            data.insert(keys.containers().into(), containers.join(","));
+        }
+    }
+    let single = values.tokens.len() == 1;
+    for (container, token) in &values.previous_tokens {
+        data.insert(
+            keys.previous_sas_token_for(container, single),
+            token.clone(),
+        );
+    }
+    data
+}
+
+/// Builds the Secret payload: additional data, overlaid with the default layout or the
+/// `spec.outputFormat` preset, overlaid with the rendered `spec.template` entries
+pub fn secret_data(
+    sasgen: &SasGenerator,
+    values: &SecretValues,
+) -> Result<BTreeMap<String, String>, ReconcileError> {
+    let mut data = values.additional_data.clone();
+    match output::preset_data(sasgen, values) {
+        Some(preset) => data.extend(preset),
+        None => data.extend(default_data(&sasgen.secret_keys(), values)),
+    }
+
+    if let Some(templates) = &sasgen.spec.template {
+        let vars = values.template_variables();
+        for (key, source) in &templates.data {
+            let rendered =
+                template::render(source, &vars).map_err(|source| ReconcileError::Template {
+                    key: key.clone(),
+                    source,
+                })?;
+            data.insert(key.clone(), rendered);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Creates or patches the target Secret and returns the resourceVersion the API server reported
+#[instrument(skip(ctx, data), fields(cr_name = %sasgen.name_any(), secret = %target.name))]
 pub async fn ensure_secret(
     sasgen: &SasGenerator,
     ctx: &ContextData,
-    secret_name: &str,
+    target: &SecretTarget,
+    data: BTreeMap<String, String>,
     labels: BTreeMap<String, String>,
-    annotations: BTreeMap<String, String>,
-) -> Result<(), ReconcileError> {
-    let ns = sasgen.namespace().unwrap_or_else(|| "default".into());
+    mut annotations: BTreeMap<String, String>,
+) -> Result<Option<String>, ReconcileError> {
+    let ns = target.namespace.clone();
+    let secret_name = target.name.as_str();
     info!(%secret_name, %ns, "Ensuring Secret exists or is up to date");
+    let inputs = WrittenSecret {
+        data: data.clone(),
+        labels: labels.clone(),
+        annotations: annotations.clone(),
+    };
 
     let api: Api<Secret> = Api::namespaced(ctx.client.clone(), &ns);
-    let status = sasgen.status.clone().unwrap_or_default();
+    let existing = api.get_opt(secret_name).await.inspect_err(|e| {
+        warn!(%secret_name, ?e, "Failed to read existing Secret");
+    })?;
+    if let Some(existing) = existing.as_ref().filter(|s| !owned_by(sasgen, s)) {
+        return Err(ReconcileError::Policy(format!(
+            "Secret {ns}/{} exists and was not written by this SasGenerator; refusing to overwrite it",
+            existing.name_any()
+        )));
+    }
+
+    // Callers set the checksum from `SecretValues::token_checksum`; pointer Secrets of
+    // versioned Secrets carry it too, although their data holds no token
+    let token_checksum = annotations
+        .get(TOKEN_CHECKSUM_ANNOTATION)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(log) = next_revision_log(
+        existing.as_ref(),
+        &data,
+        &token_checksum,
+        OffsetDateTime::now_utc(),
+    ) {
+        annotations.insert(REVISIONS_ANNOTATION.into(), log);
+    }
 
     let secret = Secret {
         metadata: kube::api::ObjectMeta {
             name: Some(secret_name.to_string()),
             namespace: Some(ns.clone()),
             labels: Some(labels),
+            owner_references: secret_owner(sasgen, &ns, &mut annotations),
             annotations: Some(annotations),
-            owner_references: Some(vec![sasgen.controller_owner_ref(&()).unwrap()]),
             ..Default::default()
         },
-        string_data: Some(BTreeMap::from([
-            ("sas_token".into(), status.token.clone().unwrap_or_default()),
-            ("account".into(), sasgen.spec.storage_account.clone()),
-            ("container".into(), sasgen.spec.container_name.clone()),
-        ])),
+        string_data: Some(data),
+        type_: Some(sasgen.secret_type().to_string()),
         ..Default::default()
     };
 
-    match api.get(secret_name).await {
-        Ok(_) => {
-            debug!(%secret_name, "Secret exists; applying patch");
-            api.patch(
+    let type_changed = existing
+        .as_ref()
+        .is_some_and(|s| s.type_.as_deref().unwrap_or("Opaque") != sasgen.secret_type());
+    if type_changed {
+        // The type of a Secret is immutable, so it can only change by recreating it
+        warn!(%secret_name, secret_type = %sasgen.secret_type(), "Secret type changed; recreating it");
+        api.delete(secret_name, &Default::default()).await?;
+    }
+
+    let written = if existing.is_some() && !type_changed {
+        debug!(%secret_name, "Secret exists; applying patch");
+        let patched = api
+            .patch(
                 secret_name,
                 &PatchParams::apply("sas-operator").force(),
                 &Patch::Apply(&secret),
             )
             .await?;
-            info!(%secret_name, "Secret updated successfully");
-        }
-        Err(kube::Error::Api(e)) if e.code == 404 => {
+        info!(%secret_name, "Secret updated successfully");
+        patched
+    } else {
+        if !type_changed {
             warn!(%secret_name, "Secret not found; creating new one");
-            api.create(&Default::default(), &secret).await?;
-            info!(%secret_name, "Secret created successfully");
         }
-        Err(e) => {
-            warn!(%secret_name, ?e, "Failed to apply Secret changes");
-            return Err(ReconcileError::Kube(e));
+        let created = api.create(&Default::default(), &secret).await?;
+        info!(%secret_name, "Secret created successfully");
+        created
+    };
+
+    ctx.written.record(target, inputs);
+    Ok(written.metadata.resource_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(blob_scope: Option<BlobScope>) -> SecretValues {
+        SecretValues {
+            account: "backupacct".into(),
+            blob_endpoint: "https://backupacct.blob.core.windows.net".into(),
+            blob_scope,
+            ..Default::default()
         }
     }
 
-    Ok(())
+    #[test]
+    fn container_url_points_at_the_scoped_blob() {
+        assert_eq!(
+            values(None).container_url("data", "sv=1&sig=a%2B"),
+            "https://backupacct.blob.core.windows.net/data?sv=1&sig=a%2B"
+        );
+        let scoped = values(Some(BlobScope {
+            blob_name: "db/nightly dump.bak".into(),
+            snapshot: Some("2024-03-09T01:42:34.9360000Z".into()),
+            version_id: None,
+        }));
+        assert_eq!(
+            scoped.container_url("data", "sv=1&sig=a%2B"),
+            "https://backupacct.blob.core.windows.net/data/db/nightly%20dump.bak\
+             ?snapshot=2024-03-09T01%3A42%3A34.9360000Z&sv=1&sig=a%2B"
+        );
+        let versioned = values(Some(BlobScope {
+            blob_name: "dump.bak".into(),
+            snapshot: None,
+            version_id: Some("2024-03-09T01:42:34.9360000Z".into()),
+        }));
+        assert_eq!(
+            versioned.container_url("data", "sv=1"),
+            "https://backupacct.blob.core.windows.net/data/dump.bak\
+             ?versionid=2024-03-09T01%3A42%3A34.9360000Z&sv=1"
+        );
+    }
 }
@@ -0,0 +1,196 @@
+use crate::crd::SasGenerator;
+use crate::http::new_reqwest_client;
+use crate::reconcile::ReconcileError;
+use crate::redact::redact;
+use crate::utils::format_rfc3339;
+use anyhow::{anyhow, bail, Context, Result};
+use kube::ResourceExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tracing::{debug, error, warn};
+
+/// The same failure of the same CR is reported at most this often; Sentry counts the
+/// occurrences it does receive, which is enough to alert on persistent failures
+const REPORT_INTERVAL: Duration = Duration::from_secs(3600);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const CLIENT_NAME: &str = concat!("sas-operator/", env!("CARGO_PKG_VERSION"));
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// Sentry (or compatible, e.g. GlitchTip) error reporting; off unless `SENTRY_DSN` is set
+#[derive(Clone, Default)]
+pub struct SentrySettings {
+    pub dsn: Option<String>,
+    pub environment: Option<String>,
+}
+
+impl SentrySettings {
+    pub fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+        Self {
+            dsn: var("SENTRY_DSN"),
+            environment: var("SENTRY_ENVIRONMENT"),
+        }
+    }
+}
+
+// The DSN key lets anyone submit events to the project, so never print it
+impl std::fmt::Debug for SentrySettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dsn = self.dsn.as_deref().map(|raw| match url::Url::parse(raw) {
+            Ok(mut parsed) => {
+                let _ = parsed.set_username("***");
+                let _ = parsed.set_password(None);
+                parsed.to_string()
+            }
+            Err(_) => "<unparsable>".to_string(),
+        });
+        f.debug_struct("SentrySettings")
+            .field("dsn", &dsn)
+            .field("environment", &self.environment)
+            .finish()
+    }
+}
+
+struct Reporter {
+    /// Envelope endpoint of the project, `{scheme}://{host}/api/{project}/envelope/`
+    endpoint: String,
+    auth_header: String,
+    environment: Option<String>,
+    client: reqwest::Client,
+    last_reported: Mutex<HashMap<(String, &'static str), Instant>>,
+}
+
+/// Splits `https://<key>@<host>[/<path>]/<project>` into the envelope endpoint and the key
+fn parse_dsn(dsn: &str) -> Result<(String, String)> {
+    let url = url::Url::parse(dsn).context("SENTRY_DSN is not a URL")?;
+    let key = url.username();
+    if key.is_empty() {
+        bail!("SENTRY_DSN has no public key");
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("SENTRY_DSN has no host"))?;
+    let path = url.path().trim_matches('/');
+    let (prefix, project) = match path.rsplit_once('/') {
+        Some((prefix, project)) => (format!("/{prefix}"), project),
+        None => (String::new(), path),
+    };
+    if project.is_empty() {
+        bail!("SENTRY_DSN has no project ID");
+    }
+    let port = url.port().map(|p| format!(":{p}")).unwrap_or_default();
+    let endpoint = format!(
+        "{}://{host}{port}{prefix}/api/{project}/envelope/",
+        url.scheme()
+    );
+    Ok((endpoint, key.to_string()))
+}
+
+/// Enables error reporting when a DSN is configured; call once at startup
+pub fn init_sentry(settings: &SentrySettings) -> Result<()> {
+    let Some(dsn) = &settings.dsn else {
+        return Ok(());
+    };
+    let (endpoint, key) = parse_dsn(dsn)?;
+    let reporter = Reporter {
+        endpoint,
+        auth_header: format!(
+            "Sentry sentry_version=7, sentry_client={CLIENT_NAME}, sentry_key={key}"
+        ),
+        environment: settings.environment.clone(),
+        client: new_reqwest_client().context("Failed to build the Sentry HTTP client")?,
+        last_reported: Mutex::new(HashMap::new()),
+    };
+    if REPORTER.set(reporter).is_err() {
+        error!("Sentry reporting was already initialized; ignoring");
+    }
+    Ok(())
+}
+
+/// Spec and template errors are the CR author's to fix and are already surfaced on the CR;
+/// an open circuit only echoes the Azure failures reported before it opened
+fn reportable(err: &ReconcileError) -> bool {
+    !err.is_terminal() && !matches!(err, ReconcileError::CircuitOpen { .. })
+}
+
+/// Forwards a reconcile failure with its CR context, in the background. The message goes
+/// through `redact`, and no spec or Secret contents are attached.
+pub fn report_reconcile_error(obj: &SasGenerator, err: &ReconcileError) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if !reportable(err) {
+        return;
+    }
+    let namespace = obj.namespace().unwrap_or_default();
+    let name = obj.name_any();
+    let resource = format!("{namespace}/{name}");
+    {
+        let mut last = reporter
+            .last_reported
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        last.retain(|_, at| now.duration_since(*at) < REPORT_INTERVAL);
+        if last.contains_key(&(resource.clone(), err.kind())) {
+            debug!(
+                kind = err.kind(),
+                "Failure was reported recently; not reporting again"
+            );
+            return;
+        }
+        last.insert((resource.clone(), err.kind()), now);
+    }
+
+    let event_id = uuid::Uuid::new_v4().simple().to_string();
+    let now = format_rfc3339(OffsetDateTime::now_utc());
+    let event = json!({
+        "event_id": event_id,
+        "timestamp": now,
+        "platform": "other",
+        "level": if err.is_permanent() { "error" } else { "warning" },
+        "logger": "sas-operator",
+        "release": CLIENT_NAME,
+        "environment": reporter.environment,
+        "server_name": std::env::var("POD_NAME").ok(),
+        "message": { "formatted": redact(&err.to_string()) },
+        "fingerprint": ["reconcile", err.kind(), resource],
+        "tags": {
+            "kind": err.kind(),
+            "reason": err.reason(),
+            "permanent": err.is_permanent().to_string(),
+            "cr_namespace": namespace,
+            "cr_name": name,
+            "storage_account": obj.spec.storage_account,
+        },
+    });
+    let envelope = format!(
+        "{}\n{}\n{}\n",
+        json!({ "event_id": event_id, "sent_at": now }),
+        json!({ "type": "event" }),
+        event
+    );
+
+    let request = reporter
+        .client
+        .post(&reporter.endpoint)
+        .header("X-Sentry-Auth", &reporter.auth_header)
+        .header("Content-Type", "application/x-sentry-envelope")
+        .timeout(SEND_TIMEOUT)
+        .body(envelope);
+    tokio::spawn(async move {
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(%event_id, "Reported reconcile failure to Sentry");
+            }
+            Ok(response) => {
+                warn!(status = %response.status(), "Sentry rejected the error report");
+            }
+            Err(e) => warn!(error = %e, "Failed to send the error report to Sentry"),
+        }
+    });
+}
@@ -0,0 +1,84 @@
+use sha2::{Digest, Sha256};
+
+/// Slice of the CRs reconciled by one operator replica when a large fleet is split across
+/// several pods; every replica watches all CRs but only reconciles its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// `SHARD_COUNT` replicas, this one being `SHARD_INDEX` or else the StatefulSet ordinal
+    /// at the end of `POD_NAME`. `None` unless more than one shard is configured.
+    pub fn from_env() -> Option<Self> {
+        let count = crate::config::env_var_or_default("SHARD_COUNT", 1u64);
+        if count <= 1 {
+            return None;
+        }
+        let index = std::env::var("SHARD_INDEX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .or_else(|| pod_ordinal(&std::env::var("POD_NAME").ok()?))
+            .unwrap_or(u64::MAX);
+        Some(Self { index, count })
+    }
+
+    /// Whether the CR `namespace/name` hashes into this shard. SHA-256 keeps the assignment
+    /// identical across replicas running different builds.
+    pub fn owns(&self, namespace: &str, name: &str) -> bool {
+        let digest = Sha256::digest(format!("{namespace}/{name}").as_bytes());
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(prefix) % self.count == self.index
+    }
+}
+
+/// Ordinal of a StatefulSet pod, e.g. 2 for `sas-operator-2`
+fn pod_ordinal(pod_name: &str) -> Option<u64> {
+    pod_name.rsplit_once('-')?.1.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordinal_comes_from_the_pod_name_suffix() {
+        assert_eq!(pod_ordinal("sas-operator-2"), Some(2));
+        assert_eq!(pod_ordinal("sas-operator-12"), Some(12));
+        assert_eq!(pod_ordinal("sas-operator"), None);
+        assert_eq!(pod_ordinal("sas-operator-7d9f8b6c5-x2x4z"), None);
+        assert_eq!(pod_ordinal("operator"), None);
+        assert_eq!(pod_ordinal("sas-operator-"), None);
+    }
+
+    #[test]
+    fn every_cr_lands_in_exactly_one_shard() {
+        let count = 3;
+        let shards: Vec<Shard> = (0..count).map(|index| Shard { index, count }).collect();
+        let mut per_shard = vec![0; count as usize];
+        for namespace in ["apps", "billing", "kube-system"] {
+            for i in 0..100 {
+                let name = format!("backup-{i}");
+                let owners: Vec<u64> = shards
+                    .iter()
+                    .filter(|s| s.owns(namespace, &name))
+                    .map(|s| s.index)
+                    .collect();
+                assert_eq!(owners.len(), 1, "{namespace}/{name} owned by {owners:?}");
+                per_shard[owners[0] as usize] += 1;
+            }
+        }
+        assert!(per_shard.iter().all(|&n| n > 0), "{per_shard:?}");
+    }
+
+    #[test]
+    fn unknown_index_owns_nothing() {
+        let shard = Shard {
+            index: u64::MAX,
+            count: 3,
+        };
+        assert!(!shard.owns("apps", "backup"));
+    }
+}
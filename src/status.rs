@@ -1,9 +1,195 @@
-use crate::crd::{ContextData, SasGenerator, SasGeneratorStatus};
+use crate::crd::{ContextData, SasGenerator, SasGeneratorCondition, SasGeneratorStatus};
 use crate::reconcile::ReconcileError;
+use crate::utils::{format_rfc3339, format_time};
 use kube::api::{Api, Patch, PatchParams};
 use kube::ResourceExt;
+use time::OffsetDateTime;
 use tracing::{debug, info, instrument, warn};
 
+pub const CONDITION_INVALID_SPEC: &str = "InvalidSpec";
+pub const CONDITION_AZURE_CONNECTION_STALE: &str = "AzureConnectionStale";
+pub const CONDITION_DEPRECATION_WARNING: &str = "DeprecationWarning";
+/// A SasAccountPolicy does not allow the storage account or a container of the CR
+pub const CONDITION_POLICY_VIOLATION: &str = "PolicyViolation";
+/// Azure calls for the storage account are suspended after repeated failures
+pub const CONDITION_AZURE_CIRCUIT_OPEN: &str = "AzureCircuitOpen";
+/// The Secrets hold a current token and the last reconcile succeeded
+pub const CONDITION_READY: &str = "Ready";
+/// The token is inside its renewal window but its replacement has not been rolled out yet
+pub const CONDITION_RENEWING: &str = "Renewing";
+/// The last reconcile failed in a way retrying is unlikely to fix; the Secrets may still hold
+/// a token that has not expired
+pub const CONDITION_DEGRADED: &str = "Degraded";
+/// kstatus: the operator is retrying a failure that may resolve on its own
+pub const CONDITION_RECONCILING: &str = "Reconciling";
+/// kstatus: the operator waits for a spec change, retrying cannot help
+pub const CONDITION_STALLED: &str = "Stalled";
+
+// Health as seen by kstatus (Flux) and Argo CD, given `status.observedGeneration` matches
+// `metadata.generation`:
+// - Healthy/Current: Ready=True; Reconciling and Stalled are absent
+// - Progressing/InProgress: Reconciling=True (reason `Retrying`), e.g. Azure unreachable;
+//   also while observedGeneration lags behind a spec change
+// - Degraded/Failed: Stalled=True with the error reason, e.g. `InvalidName` or `TemplateError`
+
+/// Sets (or replaces) a condition, keeping lastTransitionTime when the status did not change
+pub fn set_condition(
+    status: &mut SasGeneratorStatus,
+    type_: &str,
+    value: bool,
+    reason: &str,
+    message: impl Into<String>,
+    now: OffsetDateTime,
+) {
+    let value = if value { "True" } else { "False" }.to_string();
+    let previous = status.conditions.iter().position(|c| c.type_ == type_);
+
+    let last_transition_time = match previous.map(|i| &status.conditions[i]) {
+        Some(c) if c.status == value => c.last_transition_time.clone(),
+        _ => Some(format_rfc3339(now)),
+    };
+
+    let condition = SasGeneratorCondition {
+        type_: type_.to_string(),
+        status: value,
+        reason: Some(reason.to_string()),
+        message: Some(message.into()),
+        last_transition_time,
+        observed_generation: None,
+    };
+
+    match previous {
+        Some(i) => status.conditions[i] = condition,
+        None => status.conditions.push(condition),
+    }
+}
+
+/// Marks the CR Ready after a successful reconcile and resets the failure counter
+pub fn mark_ready(status: &mut SasGeneratorStatus, now: OffsetDateTime) {
+    status.consecutive_failures = 0;
+    let expiry = status.expiry.as_ref().map(format_time).unwrap_or_default();
+    set_condition(
+        status,
+        CONDITION_READY,
+        true,
+        "TokenIssued",
+        format!("Secrets hold a token valid until {expiry}"),
+        now,
+    );
+    set_condition(
+        status,
+        CONDITION_RENEWING,
+        false,
+        "TokenCurrent",
+        "Token is outside its renewal window",
+        now,
+    );
+    set_condition(
+        status,
+        CONDITION_DEGRADED,
+        false,
+        "ReconcileSucceeded",
+        "",
+        now,
+    );
+    remove_condition(status, CONDITION_AZURE_CIRCUIT_OPEN);
+    remove_condition(status, CONDITION_POLICY_VIOLATION);
+    // kstatus treats these abnormal-true conditions as false only when absent
+    remove_condition(status, CONDITION_RECONCILING);
+    remove_condition(status, CONDITION_STALLED);
+}
+
+/// Marks the CR not Ready (and Degraded, if permanent) after a failed reconcile and records the error;
+/// `renewing` tells whether the failure left a token due for renewal in place
+pub fn mark_failed(
+    status: &mut SasGeneratorStatus,
+    error: &ReconcileError,
+    renewing: bool,
+    now: OffsetDateTime,
+) {
+    status.last_error = Some(error.to_string());
+    status.last_failure_time = Some(format_rfc3339(now));
+    status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+    set_condition(
+        status,
+        CONDITION_READY,
+        false,
+        error.reason(),
+        error.to_string(),
+        now,
+    );
+    if renewing {
+        set_condition(
+            status,
+            CONDITION_RENEWING,
+            true,
+            "RenewalPending",
+            format!(
+                "Token expiring at {} could not be replaced yet",
+                status
+                    .expiry
+                    .as_ref()
+                    .map_or_else(|| "unknown".to_string(), format_time)
+            ),
+            now,
+        );
+    }
+    let flagged = match error {
+        ReconcileError::CircuitOpen { .. } => Some(CONDITION_AZURE_CIRCUIT_OPEN),
+        ReconcileError::Policy(_) => Some(CONDITION_POLICY_VIOLATION),
+        _ => None,
+    };
+    if let Some(condition) = flagged {
+        set_condition(
+            status,
+            condition,
+            true,
+            error.reason(),
+            error.to_string(),
+            now,
+        );
+    }
+    // Transient failures only show in Ready and Reconciling while they are retried
+    if error.is_permanent() {
+        set_condition(
+            status,
+            CONDITION_DEGRADED,
+            true,
+            error.reason(),
+            error.to_string(),
+            now,
+        );
+    }
+    if error.is_terminal() {
+        set_condition(
+            status,
+            CONDITION_STALLED,
+            true,
+            error.reason(),
+            error.to_string(),
+            now,
+        );
+        remove_condition(status, CONDITION_RECONCILING);
+    } else {
+        set_condition(
+            status,
+            CONDITION_RECONCILING,
+            true,
+            "Retrying",
+            error.to_string(),
+            now,
+        );
+        remove_condition(status, CONDITION_STALLED);
+    }
+}
+
+/// Removes a condition; returns true if it was present
+pub fn remove_condition(status: &mut SasGeneratorStatus, type_: &str) -> bool {
+    let before = status.conditions.len();
+    status.conditions.retain(|c| c.type_ != type_);
+    status.conditions.len() != before
+}
+
 #[instrument(skip(ctx), fields(cr_name = %sasgen.name_any()))]
 pub async fn update_crd_status(
     sasgen: &SasGenerator,
@@ -17,11 +203,14 @@ pub async fn update_crd_status(
     debug!(
         %name,
         %ns,
-        has_token = status.token.is_some(),
+        has_token_hash = status.token_hash.is_some(),
         has_expiry = status.expiry.is_some(),
         "Preparing to patch CRD status"
     );
 
+    let mut status = status;
+    status.observed_generation = sasgen.metadata.generation;
+
     let patch = Patch::Apply(&SasGenerator {
         metadata: kube::api::ObjectMeta {
             name: Some(name.clone()),
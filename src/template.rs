@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TemplateError {
+    #[error("unterminated placeholder starting at byte {0}")]
+    Unterminated(usize),
+
+    #[error("invalid placeholder '{{{{{0}}}}}', expected '{{{{ .name }}}}'")]
+    InvalidPlaceholder(String),
+
+    #[error("unknown variable '.{0}'")]
+    UnknownVariable(String),
+}
+
+/// One piece of a parsed template
+enum Part<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+/// Splits a template into literal text and `{{ .name }}` placeholders
+fn parse(template: &str) -> Result<Vec<Part<'_>>, TemplateError> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    let mut offset = 0;
+
+    while let Some(open) = rest.find("{{") {
+        parts.push(Part::Text(&rest[..open]));
+        let inner_start = open + 2;
+        let close = rest[inner_start..]
+            .find("}}")
+            .ok_or(TemplateError::Unterminated(offset + open))?;
+        let inner = &rest[inner_start..inner_start + close];
+
+        let name = inner
+            .trim()
+            .strip_prefix('.')
+            .filter(|n| {
+                !n.is_empty()
+                    && n.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            })
+            .ok_or_else(|| TemplateError::InvalidPlaceholder(inner.to_string()))?;
+        parts.push(Part::Variable(name));
+
+        let consumed = inner_start + close + 2;
+        rest = &rest[consumed..];
+        offset += consumed;
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+/// Variable names referenced by a template, in order of appearance
+pub fn variables(template: &str) -> Result<Vec<&str>, TemplateError> {
+    Ok(parse(template)?
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Variable(name) => Some(name),
+            Part::Text(_) => None,
+        })
+        .collect())
+}
+
+/// Renders `{{ .name }}` placeholders from `vars`; unknown variables are an error so typos
+/// surface instead of silently rendering empty values
+pub fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(template.len());
+    for part in parse(template)? {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Variable(name) => out.push_str(
+                vars.get(name)
+                    .ok_or_else(|| TemplateError::UnknownVariable(name.to_string()))?,
+            ),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("account".to_string(), "acct".to_string()),
+            ("sas_token".to_string(), "sv=2022&sig=x".to_string()),
+        ])
+    }
+
+    #[test]
+    fn renders_placeholders_with_and_without_spaces() {
+        assert_eq!(
+            render(
+                "AZURE_ACCOUNT={{ .account }}\nTOKEN={{.sas_token}}\n",
+                &vars()
+            )
+            .unwrap(),
+            "AZURE_ACCOUNT=acct\nTOKEN=sv=2022&sig=x\n"
+        );
+    }
+
+    #[test]
+    fn leaves_text_without_placeholders_untouched() {
+        assert_eq!(render("plain } text {", &vars()).unwrap(), "plain } text {");
+        assert_eq!(render("", &vars()).unwrap(), "");
+    }
+
+    #[test]
+    fn lists_variables_in_order() {
+        assert_eq!(
+            variables("{{ .b }}-{{ .a }}-{{ .b }}").unwrap(),
+            vec!["b", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_variables() {
+        assert_eq!(
+            render("{{ .acount }}", &vars()),
+            Err(TemplateError::UnknownVariable("acount".into()))
+        );
+    }
+
+    #[test]
+    fn reports_malformed_placeholders() {
+        assert_eq!(
+            render("ok {{ .account }} {{ .sas_token", &vars()),
+            Err(TemplateError::Unterminated(18))
+        );
+        assert_eq!(
+            render("{{ account }}", &vars()),
+            Err(TemplateError::InvalidPlaceholder(" account ".into()))
+        );
+        assert_eq!(
+            render("{{ . }}", &vars()),
+            Err(TemplateError::InvalidPlaceholder(" . ".into()))
+        );
+        assert_eq!(
+            render("{{ .a b }}", &vars()),
+            Err(TemplateError::InvalidPlaceholder(" .a b ".into()))
+        );
+    }
+
+    #[test]
+    fn error_messages_show_the_placeholder() {
+        assert_eq!(
+            TemplateError::InvalidPlaceholder("account".into()).to_string(),
+            "invalid placeholder '{{account}}', expected '{{ .name }}'"
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::DateTime;
+use sha2::{Digest, Sha256};
 use time::OffsetDateTime;
 use tracing::warn;
 
@@ -8,3 +11,35 @@ pub fn format_rfc3339(dt: OffsetDateTime) -> String {
             String::new()
         })
 }
+
+pub fn parse_rfc3339(value: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(value, &time::format_description::well_known::Rfc3339)
+        .map_err(|e| warn!(?value, ?e, "Failed to parse RFC3339 timestamp"))
+        .ok()
+}
+
+/// Converts to the `metav1.Time` used for typed (`format: date-time`) status timestamps
+pub fn to_time(dt: OffsetDateTime) -> Time {
+    Time(DateTime::from_timestamp(dt.unix_timestamp(), dt.nanosecond()).unwrap_or_default())
+}
+
+/// Converts a `metav1.Time` back for date arithmetic
+pub fn from_time(time: &Time) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(time.0.timestamp())
+        .and_then(|dt| dt.replace_nanosecond(time.0.timestamp_subsec_nanos()))
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+/// RFC3339 rendering of a typed status timestamp, for annotations and messages
+pub fn format_time(time: &Time) -> String {
+    format_rfc3339(from_time(time))
+}
+
+/// Short, non-reversible fingerprint of a secret value, safe to publish in metadata
+pub fn token_hash(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
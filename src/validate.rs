@@ -0,0 +1,1102 @@
+use crate::crd::{
+    BlobScope, ClusterRef, DeletionPolicy, OutputFormat, SasGenerator, SecretKeys, SecretOutput,
+    SecretTemplate,
+};
+use crate::sas::parse_permissions;
+use crate::template;
+use crate::versioned::VERSION_HASH_LEN;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{debug, instrument};
+
+/// User delegation keys (and therefore user delegation SAS tokens) are capped at 7 days by Azure
+pub const MAX_USER_DELEGATION_TTL_HOURS: i64 = 7 * 24;
+
+/// Upper bound for backdating the token start time; anything larger hints at a broken node clock
+pub const MAX_START_SKEW_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SpecError {
+    #[error("invalid {field} '{value}': {reason}")]
+    InvalidName {
+        field: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+
+    #[error("conflicting fields {first} and {second}: {reason}")]
+    ConflictingFields {
+        first: &'static str,
+        second: &'static str,
+        reason: String,
+    },
+
+    #[error("unsupported combination: {0}")]
+    Unsupported(String),
+}
+
+impl SpecError {
+    /// Short CamelCase reason used for conditions and events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SpecError::InvalidName { .. } => "InvalidName",
+            SpecError::ConflictingFields { .. } => "ConflictingFields",
+            SpecError::Unsupported(_) => "UnsupportedCombination",
+        }
+    }
+}
+
+/// Storage account names as a CRD schema pattern, so the API server rejects them up front
+pub const ACCOUNT_NAME_PATTERN: &str = "^[a-z0-9]{3,24}$";
+/// Container names as a CRD schema pattern; the length is checked by `minLength`/`maxLength`
+pub const CONTAINER_NAME_PATTERN: &str = r"^(\$root|[a-z0-9]+(-[a-z0-9]+)*)$";
+
+/// Storage account names: 3-24 characters, lowercase letters and digits only
+fn validate_account_name(name: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field: "storageAccount",
+        value: name.to_string(),
+        reason,
+    };
+
+    if !(3..=24).contains(&name.len()) {
+        return Err(invalid("must be between 3 and 24 characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    {
+        return Err(invalid("must contain only lowercase letters and digits"));
+    }
+    Ok(())
+}
+
+/// Container names: 3-63 characters, lowercase letters, digits and single hyphens,
+/// starting and ending with a letter or digit
+fn validate_container_name(name: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field: "containerName",
+        value: name.to_string(),
+        reason,
+    };
+
+    if name == "$root" {
+        return Ok(());
+    }
+    if !(3..=63).contains(&name.len()) {
+        return Err(invalid("must be between 3 and 63 characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(invalid(
+            "must contain only lowercase letters, digits and hyphens",
+        ));
+    }
+    if name.starts_with('-') || name.ends_with('-') {
+        return Err(invalid("must start and end with a letter or digit"));
+    }
+    if name.contains("--") {
+        return Err(invalid("must not contain consecutive hyphens"));
+    }
+    Ok(())
+}
+
+/// Kubernetes Secret names must be valid DNS subdomains (RFC 1123)
+fn validate_secret_name(name: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field: "secretName",
+        value: name.to_string(),
+        reason,
+    };
+
+    if name.is_empty() || name.len() > 253 {
+        return Err(invalid("must be between 1 and 253 characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+    {
+        return Err(invalid(
+            "must contain only lowercase letters, digits, '-' and '.'",
+        ));
+    }
+    let alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if !name.starts_with(alnum) || !name.ends_with(alnum) {
+        return Err(invalid("must start and end with a letter or digit"));
+    }
+    Ok(())
+}
+
+/// The renamed Secret data keys must be valid and distinct
+fn validate_secret_keys(keys: &SecretKeys) -> Result<(), SpecError> {
+    let names = [
+        ("secretKeys.sasToken", keys.sas_token()),
+        ("secretKeys.account", keys.account()),
+        ("secretKeys.container", keys.container()),
+        ("secretKeys.containers", keys.containers()),
+        ("secretKeys.url", keys.url()),
+        ("secretKeys.connectionString", keys.connection_string()),
+    ];
+    for (i, (field, key)) in names.iter().enumerate() {
+        validate_data_key(field, key)?;
+        if names[..i].iter().any(|(_, other)| other == key) {
+            return Err(SpecError::InvalidName {
+                field,
+                value: key.to_string(),
+                reason: "used for more than one value",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Secret data keys: 1-253 characters of letters, digits, '-', '_' and '.'
+fn validate_data_key(field: &'static str, key: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field,
+        value: key.to_string(),
+        reason,
+    };
+    if key.is_empty() || key.len() > 253 {
+        return Err(invalid("must be between 1 and 253 characters"));
+    }
+    if !key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(invalid(
+            "must contain only letters, digits, '-', '_' and '.'",
+        ));
+    }
+    Ok(())
+}
+
+/// Custom Secret types: `Opaque` or a qualified name outside the built-in `kubernetes.io/`
+/// types, which the API server validates against their own required keys
+fn validate_secret_type(secret_type: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field: "secretType",
+        value: secret_type.to_string(),
+        reason,
+    };
+    if secret_type == "Opaque" {
+        return Ok(());
+    }
+    if secret_type.is_empty() || secret_type.len() > 253 {
+        return Err(invalid("must be between 1 and 253 characters"));
+    }
+    if secret_type.starts_with("kubernetes.io/") {
+        return Err(invalid(
+            "built-in kubernetes.io/ types require their own data keys",
+        ));
+    }
+    if !secret_type
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+    {
+        return Err(invalid(
+            "must contain only letters, digits, '-', '_', '.' and '/'",
+        ));
+    }
+    Ok(())
+}
+
+/// Template variables that do not depend on the container names
+const TEMPLATE_VARIABLES: &[&str] = &[
+    "account",
+    "endpoint",
+    "expiry",
+    "containers",
+    "token",
+    "container",
+    "url",
+    "connection_string",
+];
+
+/// Template keys must be valid data keys and every placeholder must name a known variable
+fn validate_template(templates: &SecretTemplate) -> Result<(), SpecError> {
+    for (key, source) in &templates.data {
+        validate_data_key("template.data", key)?;
+        let variables = template::variables(source)
+            .map_err(|e| SpecError::Unsupported(format!("template.data.{key}: {e}")))?;
+        for name in variables {
+            let per_container = ["token_", "url_", "connection_string_"]
+                .iter()
+                .any(|prefix| name.strip_prefix(prefix).is_some_and(|c| !c.is_empty()));
+            if !per_container && !TEMPLATE_VARIABLES.contains(&name) {
+                return Err(SpecError::Unsupported(format!(
+                    "template.data.{key}: unknown variable '.{name}' (expected one of {} or \
+                     token_/url_/connection_string_<container>)",
+                    TEMPLATE_VARIABLES.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keys under `sas.azure.com/` are written by the operator and cannot be set by users
+fn validate_metadata_key(field: &'static str, key: &str) -> Result<(), SpecError> {
+    if key.is_empty() || key.starts_with("sas.azure.com/") {
+        return Err(SpecError::InvalidName {
+            field,
+            value: key.to_string(),
+            reason: "must be non-empty and outside the reserved sas.azure.com/ prefix",
+        });
+    }
+    Ok(())
+}
+
+/// Outputs need a name, a valid Secret name and permissions, and a layout fitting one Secret
+/// holding every container
+fn validate_output(output: &SecretOutput, single_container: bool) -> Result<(), SpecError> {
+    if output.name.is_empty() {
+        return Err(SpecError::InvalidName {
+            field: "outputs.name",
+            value: String::new(),
+            reason: "must not be empty",
+        });
+    }
+    validate_secret_name(&output.secret_name)?;
+    if let Some(permissions) = &output.permissions {
+        if permissions.is_empty() || parse_permissions(permissions).is_err() {
+            return Err(SpecError::InvalidName {
+                field: "outputs.permissions",
+                value: permissions.clone(),
+                reason: "must be non-empty and use only the SAS permission letters racwdxyltmeop",
+            });
+        }
+    }
+    let format = output.output_format.unwrap_or_default();
+    if let Some(keys) = &output.secret_keys {
+        validate_secret_keys(keys)?;
+        if format != OutputFormat::Default {
+            return Err(SpecError::ConflictingFields {
+                first: "outputs.secretKeys",
+                second: "outputs.outputFormat",
+                reason: "output presets use fixed key names".into(),
+            });
+        }
+    }
+    if format.single_container() && !single_container {
+        return Err(SpecError::Unsupported(format!(
+            "output '{}' uses outputFormat {}, which needs containerName",
+            output.name,
+            format!("{format:?}").to_lowercase()
+        )));
+    }
+    Ok(())
+}
+
+/// Cluster refs need a name for status and a kubeconfig Secret to connect with
+fn validate_cluster_ref(cluster: &ClusterRef) -> Result<(), SpecError> {
+    if cluster.name.is_empty() {
+        return Err(SpecError::InvalidName {
+            field: "clusterRefs.name",
+            value: String::new(),
+            reason: "must not be empty",
+        });
+    }
+    if cluster.kubeconfig_secret_ref.name.is_empty() {
+        return Err(SpecError::InvalidName {
+            field: "clusterRefs.kubeconfigSecretRef.name",
+            value: String::new(),
+            reason: "must not be empty",
+        });
+    }
+    Ok(())
+}
+
+/// Namespace names must be valid DNS labels (RFC 1123)
+fn validate_namespace_name(field: &'static str, name: &str) -> Result<(), SpecError> {
+    let invalid = |reason| SpecError::InvalidName {
+        field,
+        value: name.to_string(),
+        reason,
+    };
+
+    if name.is_empty() || name.len() > 63 {
+        return Err(invalid("must be between 1 and 63 characters"));
+    }
+    let alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if !name.chars().all(|c| alnum(c) || c == '-') {
+        return Err(invalid(
+            "must contain only lowercase letters, digits and '-'",
+        ));
+    }
+    if !name.starts_with(alnum) || !name.ends_with(alnum) {
+        return Err(invalid("must start and end with a letter or digit"));
+    }
+    Ok(())
+}
+
+/// `blobEndpoint` must be an absolute http(s) URL; `endpointSuffix` a bare DNS suffix
+fn validate_endpoint(suffix: Option<&str>, endpoint: Option<&str>) -> Result<(), SpecError> {
+    if suffix.is_some() && endpoint.is_some() {
+        return Err(SpecError::ConflictingFields {
+            first: "endpointSuffix",
+            second: "blobEndpoint",
+            reason: "blobEndpoint already names the full host".into(),
+        });
+    }
+    if let Some(suffix) = suffix {
+        if suffix.is_empty() || suffix.contains(['/', ':']) || suffix.starts_with('.') {
+            return Err(SpecError::InvalidName {
+                field: "endpointSuffix",
+                value: suffix.to_string(),
+                reason: "must be a DNS suffix such as core.windows.net",
+            });
+        }
+    }
+    if let Some(endpoint) = endpoint {
+        let valid = url::Url::parse(endpoint)
+            .is_ok_and(|u| matches!(u.scheme(), "https" | "http") && u.host().is_some());
+        if !valid {
+            return Err(SpecError::InvalidName {
+                field: "blobEndpoint",
+                value: endpoint.to_string(),
+                reason: "must be an absolute http(s) URL",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A blob scope names one blob in one container, pinned to exactly one snapshot or version
+fn validate_blob_scope(scope: &BlobScope, single_container: bool) -> Result<(), SpecError> {
+    if !single_container {
+        return Err(SpecError::Unsupported(
+            "blobScope requires containerName".into(),
+        ));
+    }
+    if scope.blob_name.is_empty() || scope.blob_name.len() > 1024 {
+        return Err(SpecError::InvalidName {
+            field: "blobScope.blobName",
+            value: scope.blob_name.clone(),
+            reason: "must be between 1 and 1024 characters",
+        });
+    }
+    let (field, value) = match (&scope.snapshot, &scope.version_id) {
+        (Some(snapshot), None) => ("blobScope.snapshot", snapshot),
+        (None, Some(version)) => ("blobScope.versionId", version),
+        (Some(_), Some(_)) => {
+            return Err(SpecError::ConflictingFields {
+                first: "blobScope.snapshot",
+                second: "blobScope.versionId",
+                reason: "a token can be scoped to a snapshot or a version, not both".into(),
+            })
+        }
+        (None, None) => {
+            return Err(SpecError::Unsupported(
+                "blobScope needs a snapshot or a versionId".into(),
+            ))
+        }
+    };
+    if OffsetDateTime::parse(value, &Rfc3339).is_err() {
+        return Err(SpecError::InvalidName {
+            field,
+            value: value.clone(),
+            reason: "must be an RFC 3339 timestamp as returned by Azure",
+        });
+    }
+    Ok(())
+}
+
+/// Account-key signing bypasses Azure AD, so identity options and user delegation only
+/// SAS fields have nothing to apply to
+fn validate_account_key_mode(sasgen: &SasGenerator) -> Result<(), SpecError> {
+    let spec = &sasgen.spec;
+    if spec.account_key_secret_ref.is_some() && spec.connection_string_secret_ref.is_some() {
+        return Err(SpecError::ConflictingFields {
+            first: "accountKeySecretRef",
+            second: "connectionStringSecretRef",
+            reason: "a CR signs with a single account key".into(),
+        });
+    }
+    let mode = if spec.account_key_secret_ref.is_some() {
+        "accountKeySecretRef"
+    } else {
+        "connectionStringSecretRef"
+    };
+    let conflicts = [
+        ("azureIdentity", spec.azure_identity.is_some()),
+        ("tenantId", spec.tenant_id.is_some()),
+        (
+            "credentialsSecretRef",
+            spec.credentials_secret_ref.is_some(),
+        ),
+        ("correlationId", spec.correlation_id.is_some()),
+        ("authorizedObjectId", spec.authorized_object_id.is_some()),
+        (
+            "unauthorizedObjectId",
+            spec.unauthorized_object_id.is_some(),
+        ),
+        (
+            "stampContainerMetadata",
+            spec.stamp_container_metadata == Some(true),
+        ),
+    ];
+    if let Some((field, _)) = conflicts.iter().find(|(_, set)| *set) {
+        return Err(SpecError::ConflictingFields {
+            first: mode,
+            second: field,
+            reason: "only available with Azure AD (user delegation) signing".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Validates the spec before any external call is made.
+/// `ttl_hours` and `renewal_hours` are the effective values after applying operator defaults.
+#[instrument(skip(sasgen), fields(cr_name = %kube::ResourceExt::name_any(sasgen)))]
+pub fn validate_spec(
+    sasgen: &SasGenerator,
+    ttl_hours: i64,
+    renewal_hours: i64,
+) -> Result<(), SpecError> {
+    validate_account_name(&sasgen.spec.storage_account)?;
+
+    let spec = &sasgen.spec;
+    let sources = [
+        ("containerName", spec.container_name.is_some()),
+        ("containers", spec.containers.is_some()),
+        ("containerSelector", spec.container_selector.is_some()),
+    ];
+    let set: Vec<&'static str> = sources
+        .iter()
+        .filter(|(_, present)| *present)
+        .map(|(field, _)| *field)
+        .collect();
+    match set.as_slice() {
+        [] => {
+            return Err(SpecError::Unsupported(
+                "one of containerName, containers or containerSelector is required".into(),
+            ))
+        }
+        [first, second, ..] => {
+            return Err(SpecError::ConflictingFields {
+                first,
+                second,
+                reason: "set only one of them".into(),
+            })
+        }
+        _ => {}
+    }
+    if spec.containers.as_ref().is_some_and(|list| list.is_empty()) {
+        return Err(SpecError::Unsupported(
+            "containers must list at least one container".into(),
+        ));
+    }
+    if let Some(pattern) = spec
+        .container_selector
+        .as_ref()
+        .and_then(|s| s.regex.as_ref())
+    {
+        if let Err(e) = regex::Regex::new(pattern) {
+            return Err(SpecError::Unsupported(format!(
+                "containerSelector.regex does not compile: {e}"
+            )));
+        }
+    }
+
+    let containers = sasgen.container_names();
+    for (i, container) in containers.iter().enumerate() {
+        validate_container_name(container)?;
+        if containers[..i].contains(container) {
+            return Err(SpecError::InvalidName {
+                field: "containers",
+                value: container.clone(),
+                reason: "listed more than once",
+            });
+        }
+    }
+    if let Some(namespace) = &spec.target_namespace {
+        validate_namespace_name("targetNamespace", namespace)?;
+    }
+    if let Some(namespaces) = &spec.target_namespaces {
+        if spec.target_namespace.is_some() {
+            return Err(SpecError::ConflictingFields {
+                first: "targetNamespace",
+                second: "targetNamespaces",
+                reason: "set only one of them".into(),
+            });
+        }
+        if namespaces.is_empty() {
+            return Err(SpecError::Unsupported(
+                "targetNamespaces must list at least one namespace".into(),
+            ));
+        }
+        for (i, namespace) in namespaces.iter().enumerate() {
+            validate_namespace_name("targetNamespaces", namespace)?;
+            if namespaces[..i].contains(namespace) {
+                return Err(SpecError::InvalidName {
+                    field: "targetNamespaces",
+                    value: namespace.clone(),
+                    reason: "listed more than once",
+                });
+            }
+        }
+    }
+    if let Some(selector) = sasgen.namespace_selector() {
+        if let Err(e) = selector {
+            return Err(SpecError::Unsupported(format!(
+                "targetNamespaceSelector is invalid: {e}"
+            )));
+        }
+        if spec.target_namespace.is_some() || spec.target_namespaces.is_some() {
+            return Err(SpecError::ConflictingFields {
+                first: "targetNamespaceSelector",
+                second: "targetNamespace(s)",
+                reason: "set only one of them".into(),
+            });
+        }
+    }
+    for (i, cluster) in spec.cluster_refs.iter().flatten().enumerate() {
+        validate_cluster_ref(cluster)?;
+        let clusters = spec.cluster_refs.as_deref().unwrap_or_default();
+        if clusters[..i].iter().any(|c| c.name == cluster.name) {
+            return Err(SpecError::InvalidName {
+                field: "clusterRefs.name",
+                value: cluster.name.clone(),
+                reason: "listed more than once",
+            });
+        }
+    }
+    for target in sasgen.secret_targets(&containers, &sasgen.target_namespaces()) {
+        validate_secret_name(&target.name)?;
+        if spec.immutable_secrets.unwrap_or(false) && target.name.len() > 252 - VERSION_HASH_LEN {
+            return Err(SpecError::InvalidName {
+                field: "secretName",
+                value: target.name,
+                reason: "leaves no room for the version suffix of immutableSecrets",
+            });
+        }
+    }
+    let main_targets = sasgen.secret_targets(&containers, &sasgen.target_namespaces());
+    let outputs = spec.outputs.as_deref().unwrap_or_default();
+    for (i, output) in outputs.iter().enumerate() {
+        validate_output(output, spec.container_name.is_some())?;
+        if outputs[..i].iter().any(|o| o.name == output.name) {
+            return Err(SpecError::InvalidName {
+                field: "outputs.name",
+                value: output.name.clone(),
+                reason: "listed more than once",
+            });
+        }
+        let taken = main_targets.iter().any(|t| t.name == output.secret_name)
+            || outputs[..i]
+                .iter()
+                .any(|o| o.secret_name == output.secret_name);
+        if taken {
+            return Err(SpecError::InvalidName {
+                field: "outputs.secretName",
+                value: output.secret_name.clone(),
+                reason: "is already written by this CR",
+            });
+        }
+    }
+    if !outputs.is_empty() && spec.import_secret_ref.is_some() {
+        return Err(SpecError::ConflictingFields {
+            first: "outputs",
+            second: "importSecretRef",
+            reason: "an imported token cannot serve outputs with other permissions".into(),
+        });
+    }
+    if let Some(keys) = &spec.secret_keys {
+        validate_secret_keys(keys)?;
+        if spec
+            .output_format
+            .is_some_and(|f| f != OutputFormat::Default)
+        {
+            return Err(SpecError::ConflictingFields {
+                first: "secretKeys",
+                second: "outputFormat",
+                reason: "output presets use fixed key names".into(),
+            });
+        }
+    }
+    for key in spec.additional_data.iter().flat_map(|d| d.keys()) {
+        validate_data_key("additionalData", key)?;
+    }
+    for source in spec.additional_data_from.iter().flatten() {
+        if source.secret_ref.is_some() == source.config_map_ref.is_some() {
+            return Err(SpecError::Unsupported(
+                "each additionalDataFrom entry needs exactly one of secretRef and configMapRef"
+                    .into(),
+            ));
+        }
+    }
+    if let Some(templates) = &spec.template {
+        validate_template(templates)?;
+    }
+    if !sasgen.owner_reference() && spec.deletion_policy.is_none() {
+        return Err(SpecError::Unsupported(
+            "ownerReference: false requires an explicit deletionPolicy".into(),
+        ));
+    }
+    if sasgen.owner_reference() && sasgen.deletion_policy() == DeletionPolicy::Retain {
+        return Err(SpecError::ConflictingFields {
+            first: "deletionPolicy",
+            second: "ownerReference",
+            reason:
+                "Retain needs ownerReference: false, or the garbage collector deletes the Secrets"
+                    .into(),
+        });
+    }
+    for key in spec.secret_labels.iter().flat_map(|l| l.keys()) {
+        validate_metadata_key("secretLabels", key)?;
+    }
+    for key in spec.secret_annotations.iter().flat_map(|a| a.keys()) {
+        validate_metadata_key("secretAnnotations", key)?;
+    }
+    if let Some(secret_type) = &spec.secret_type {
+        validate_secret_type(secret_type)?;
+    }
+    if sasgen.blue_green() {
+        if spec
+            .output_format
+            .is_some_and(|f| f != OutputFormat::Default)
+        {
+            return Err(SpecError::ConflictingFields {
+                first: "rotationStrategy",
+                second: "outputFormat",
+                reason: "previous tokens are only published in the default layout".into(),
+            });
+        }
+        if spec.immutable_secrets.unwrap_or(false) {
+            return Err(SpecError::ConflictingFields {
+                first: "rotationStrategy",
+                second: "immutableSecrets",
+                reason: "immutable Secrets already keep the previous version".into(),
+            });
+        }
+    }
+    if let Some(overlap) = spec
+        .rotation_strategy
+        .as_ref()
+        .and_then(|r| r.overlap_hours)
+    {
+        // The previous token expires at most one renewal window after the rotation
+        if !(1..=renewal_hours).contains(&overlap) {
+            return Err(SpecError::Unsupported(format!(
+                "rotationStrategy.overlapHours must be between 1 and the renewal window \
+                 ({renewal_hours}h), got {overlap}"
+            )));
+        }
+    }
+    let format = spec.output_format.unwrap_or_default();
+    if format.single_container()
+        && spec.container_name.is_none()
+        && !spec.secret_per_container.unwrap_or(false)
+    {
+        return Err(SpecError::Unsupported(format!(
+            "outputFormat {} needs one container per Secret: use containerName or \
+             secretPerContainer",
+            format!("{format:?}").to_lowercase()
+        )));
+    }
+
+    for (field, value) in [
+        ("correlationId", &spec.correlation_id),
+        ("tenantId", &spec.tenant_id),
+        ("authorizedObjectId", &spec.authorized_object_id),
+        ("unauthorizedObjectId", &spec.unauthorized_object_id),
+        (
+            "azureIdentity.clientId",
+            &spec
+                .azure_identity
+                .as_ref()
+                .and_then(|i| i.client_id.clone()),
+        ),
+    ] {
+        if let Some(id) = value {
+            if uuid::Uuid::parse_str(id).is_err() {
+                return Err(SpecError::InvalidName {
+                    field,
+                    value: id.clone(),
+                    reason: "must be a GUID",
+                });
+            }
+        }
+    }
+    validate_endpoint(
+        spec.endpoint_suffix.as_deref(),
+        spec.blob_endpoint.as_deref(),
+    )?;
+    if let Some(scope) = &spec.blob_scope {
+        validate_blob_scope(scope, spec.container_name.is_some())?;
+        let formats = spec.output_format.into_iter().chain(
+            spec.outputs
+                .iter()
+                .flatten()
+                .filter_map(|o| o.output_format),
+        );
+        if let Some(format) = formats.into_iter().find(|f| f.container_access()) {
+            return Err(SpecError::ConflictingFields {
+                first: "blobScope",
+                second: "outputFormat",
+                reason: format!(
+                    "{} needs container access, which a single-blob token does not grant",
+                    format!("{format:?}").to_lowercase()
+                ),
+            });
+        }
+    }
+    if let Some(identity) = &spec.azure_identity {
+        if let Some(service_account) = &identity.service_account_name {
+            if identity.client_id.is_none() {
+                return Err(SpecError::Unsupported(
+                    "azureIdentity.serviceAccountName requires azureIdentity.clientId".into(),
+                ));
+            }
+            validate_secret_name(service_account).map_err(|_| SpecError::InvalidName {
+                field: "azureIdentity.serviceAccountName",
+                value: service_account.clone(),
+                reason: "must be a valid DNS subdomain",
+            })?;
+        }
+    }
+    if spec.azure_identity.is_some() && spec.credentials_secret_ref.is_some() {
+        return Err(SpecError::ConflictingFields {
+            first: "azureIdentity",
+            second: "credentialsSecretRef",
+            reason: "a CR authenticates with a single identity".into(),
+        });
+    }
+    if sasgen.signs_with_account_key() {
+        validate_account_key_mode(sasgen)?;
+    }
+    if spec.authorized_object_id.is_some() && spec.unauthorized_object_id.is_some() {
+        return Err(SpecError::ConflictingFields {
+            first: "authorizedObjectId",
+            second: "unauthorizedObjectId",
+            reason: "a token can bind only one agent object ID".into(),
+        });
+    }
+
+    if ttl_hours <= 0 {
+        return Err(SpecError::Unsupported(format!(
+            "sasTtlHours must be positive, got {ttl_hours}"
+        )));
+    }
+    if renewal_hours < 0 {
+        return Err(SpecError::Unsupported(format!(
+            "sasRenewalHours must not be negative, got {renewal_hours}"
+        )));
+    }
+    if renewal_hours >= ttl_hours {
+        return Err(SpecError::ConflictingFields {
+            first: "sasRenewalHours",
+            second: "sasTtlHours",
+            reason: format!(
+                "renewal window ({renewal_hours}h) must be shorter than the TTL ({ttl_hours}h)"
+            ),
+        });
+    }
+    if let Some(skew) = spec.start_skew_seconds {
+        if !(0..=MAX_START_SKEW_SECONDS).contains(&skew) {
+            return Err(SpecError::Unsupported(format!(
+                "startSkewSeconds must be between 0 and {MAX_START_SKEW_SECONDS}, got {skew}"
+            )));
+        }
+    }
+    if let Some(interval) = spec.reconcile_interval_seconds {
+        // Checking less often than tokens live would let them expire between reconciles
+        if interval == 0 || interval >= ttl_hours as u64 * 3600 {
+            return Err(SpecError::Unsupported(format!(
+                "reconcileIntervalSeconds must be between 1 and the TTL ({ttl_hours}h), got {interval}"
+            )));
+        }
+    }
+    if ttl_hours > MAX_USER_DELEGATION_TTL_HOURS && !sasgen.signs_with_account_key() {
+        return Err(SpecError::Unsupported(format!(
+            "sasTtlHours {ttl_hours} exceeds the {MAX_USER_DELEGATION_TTL_HOURS}h limit of user delegation SAS"
+        )));
+    }
+
+    debug!("Spec validated successfully");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn sasgen(extra: Value) -> SasGenerator {
+        let mut spec = json!({
+            "storageAccount": "backupacct",
+            "containerName": "data",
+            "secretName": "backup-sas",
+        });
+        for (key, value) in extra.as_object().unwrap() {
+            if value.is_null() {
+                spec.as_object_mut().unwrap().remove(key);
+            } else {
+                spec[key] = value.clone();
+            }
+        }
+        serde_json::from_value(json!({
+            "apiVersion": "sas.azure.com/v1alpha1",
+            "kind": "SasGenerator",
+            "metadata": { "name": "backup", "namespace": "apps" },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    fn check(extra: Value) -> Result<(), SpecError> {
+        validate_spec(&sasgen(extra), 48, 24)
+    }
+
+    fn reason(extra: Value) -> &'static str {
+        check(extra).unwrap_err().reason()
+    }
+
+    #[test]
+    fn accepts_minimal_spec() {
+        assert_eq!(check(json!({})), Ok(()));
+        assert_eq!(
+            check(json!({ "containerName": null, "containers": ["data", "logs"] })),
+            Ok(())
+        );
+        assert_eq!(check(json!({ "containerName": "$root" })), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for account in ["ab", "Backup", "backup-acct", "a".repeat(25).as_str()] {
+            assert!(
+                matches!(
+                    check(json!({ "storageAccount": account })),
+                    Err(SpecError::InvalidName {
+                        field: "storageAccount",
+                        ..
+                    })
+                ),
+                "{account}"
+            );
+        }
+        for container in ["ab", "-data", "data-", "da--ta", "Data", "da_ta"] {
+            assert!(
+                matches!(
+                    check(json!({ "containerName": container })),
+                    Err(SpecError::InvalidName {
+                        field: "containerName",
+                        ..
+                    })
+                ),
+                "{container}"
+            );
+        }
+        assert_eq!(reason(json!({ "secretName": "Backup" })), "InvalidName");
+        assert_eq!(
+            check(json!({ "containerName": null, "containers": ["data", "data"] })),
+            Err(SpecError::InvalidName {
+                field: "containers",
+                value: "data".into(),
+                reason: "listed more than once",
+            })
+        );
+    }
+
+    #[test]
+    fn name_rules_match_schema_patterns() {
+        let account = regex::Regex::new(ACCOUNT_NAME_PATTERN).unwrap();
+        let container = regex::Regex::new(CONTAINER_NAME_PATTERN).unwrap();
+        for name in ["abc", "backup01", "ab", "Backup", "backup-acct"] {
+            assert_eq!(
+                account.is_match(name),
+                validate_account_name(name).is_ok(),
+                "{name}"
+            );
+        }
+        for name in [
+            "data",
+            "$root",
+            "my-data-1",
+            "-data",
+            "data-",
+            "da--ta",
+            "Data",
+        ] {
+            assert_eq!(
+                container.is_match(name),
+                validate_container_name(name).is_ok(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires_exactly_one_container_source() {
+        assert_eq!(
+            reason(json!({ "containerName": null })),
+            "UnsupportedCombination"
+        );
+        assert_eq!(
+            check(json!({ "containers": ["logs"] })),
+            Err(SpecError::ConflictingFields {
+                first: "containerName",
+                second: "containers",
+                reason: "set only one of them".into(),
+            })
+        );
+        assert_eq!(
+            reason(json!({ "containerName": null, "containers": [] })),
+            "UnsupportedCombination"
+        );
+    }
+
+    #[test]
+    fn checks_ttl_renewal_and_skew() {
+        let spec = sasgen(json!({}));
+        assert!(validate_spec(&spec, 0, 0).is_err());
+        assert!(validate_spec(&spec, 48, -1).is_err());
+        assert!(matches!(
+            validate_spec(&spec, 24, 24),
+            Err(SpecError::ConflictingFields {
+                first: "sasRenewalHours",
+                ..
+            })
+        ));
+        assert!(validate_spec(&spec, MAX_USER_DELEGATION_TTL_HOURS + 1, 24).is_err());
+        assert!(validate_spec(&spec, MAX_USER_DELEGATION_TTL_HOURS, 24).is_ok());
+
+        assert!(check(json!({ "startSkewSeconds": -1 })).is_err());
+        assert!(check(json!({ "startSkewSeconds": MAX_START_SKEW_SECONDS + 1 })).is_err());
+        assert!(check(json!({ "startSkewSeconds": 0 })).is_ok());
+        assert!(check(json!({ "reconcileIntervalSeconds": 0 })).is_err());
+        assert!(check(json!({ "reconcileIntervalSeconds": 48 * 3600 })).is_err());
+        assert!(check(json!({ "reconcileIntervalSeconds": 300 })).is_ok());
+    }
+
+    #[test]
+    fn account_key_signing_allows_long_ttl_but_not_aad_fields() {
+        let key = json!({ "accountKeySecretRef": { "name": "storage-key" } });
+        assert!(validate_spec(&sasgen(key.clone()), 24 * 30, 24).is_ok());
+        assert_eq!(
+            check(json!({
+                "accountKeySecretRef": { "name": "storage-key" },
+                "correlationId": "00000000-0000-0000-0000-000000000001",
+            })),
+            Err(SpecError::ConflictingFields {
+                first: "accountKeySecretRef",
+                second: "correlationId",
+                reason: "only available with Azure AD (user delegation) signing".into(),
+            })
+        );
+        assert_eq!(
+            reason(json!({
+                "accountKeySecretRef": { "name": "a" },
+                "connectionStringSecretRef": { "name": "b" },
+            })),
+            "ConflictingFields"
+        );
+    }
+
+    #[test]
+    fn checks_guids_and_identity() {
+        assert!(check(json!({ "tenantId": "not-a-guid" })).is_err());
+        assert!(check(json!({ "tenantId": "00000000-0000-0000-0000-000000000001" })).is_ok());
+        assert_eq!(
+            reason(json!({ "azureIdentity": { "serviceAccountName": "reader" } })),
+            "UnsupportedCombination"
+        );
+        assert!(check(json!({
+            "azureIdentity": {
+                "clientId": "00000000-0000-0000-0000-000000000001",
+                "serviceAccountName": "reader",
+            },
+        }))
+        .is_ok());
+        assert_eq!(
+            reason(json!({
+                "azureIdentity": { "clientId": "00000000-0000-0000-0000-000000000001" },
+                "credentialsSecretRef": { "name": "sp" },
+            })),
+            "ConflictingFields"
+        );
+    }
+
+    #[test]
+    fn checks_outputs() {
+        let output = |name: &str, secret: &str, permissions: &str| json!({ "name": name, "secretName": secret, "permissions": permissions });
+        assert!(check(json!({ "outputs": [output("ro", "backup-ro", "rl")] })).is_ok());
+        assert!(check(json!({ "outputs": [output("ro", "backup-ro", "rq")] })).is_err());
+        assert!(check(json!({ "outputs": [output("ro", "backup-ro", "")] })).is_err());
+        assert_eq!(
+            check(json!({ "outputs": [output("ro", "backup-sas", "rl")] })),
+            Err(SpecError::InvalidName {
+                field: "outputs.secretName",
+                value: "backup-sas".into(),
+                reason: "is already written by this CR",
+            })
+        );
+        assert!(check(json!({
+            "outputs": [output("ro", "backup-ro", "rl"), output("ro", "backup-ro2", "r")],
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn checks_templates() {
+        assert!(check(json!({ "template": { "data": { "env": "A={{ .account }}" } } })).is_ok());
+        assert!(check(json!({ "template": { "data": { "env": "A={{ .acount }}" } } })).is_err());
+        assert!(check(json!({ "template": { "data": { "env": "A={{ account" } } })).is_err());
+    }
+
+    #[test]
+    fn checks_rotation_and_ownership() {
+        assert!(
+            check(json!({ "rotationStrategy": { "type": "BlueGreen", "overlapHours": 2 } }))
+                .is_ok()
+        );
+        assert!(
+            check(json!({ "rotationStrategy": { "type": "BlueGreen", "overlapHours": 25 } }))
+                .is_err()
+        );
+        assert!(check(json!({
+            "rotationStrategy": { "type": "BlueGreen" },
+            "immutableSecrets": true,
+        }))
+        .is_err());
+        assert!(check(json!({ "ownerReference": false })).is_err());
+        assert!(check(json!({ "ownerReference": false, "deletionPolicy": "Retain" })).is_ok());
+        assert!(check(json!({ "deletionPolicy": "Retain" })).is_err());
+    }
+
+    #[test]
+    fn single_container_formats_need_one_container_per_secret() {
+        assert!(check(json!({ "outputFormat": "velero" })).is_ok());
+        assert!(check(json!({
+            "containerName": null,
+            "containers": ["data", "logs"],
+            "outputFormat": "velero",
+        }))
+        .is_err());
+        assert!(check(json!({
+            "containerName": null,
+            "containers": ["data", "logs"],
+            "outputFormat": "velero",
+            "secretPerContainer": true,
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn blob_scope_rejects_container_formats() {
+        let scope =
+            json!({ "blobName": "db/dump.bak", "snapshot": "2024-03-09T01:42:34.9360000Z" });
+        assert!(check(json!({ "blobScope": scope })).is_ok());
+        assert!(check(json!({ "blobScope": scope, "outputFormat": "azcopy" })).is_ok());
+        assert!(check(json!({ "blobScope": scope, "outputFormat": "rclone" })).is_err());
+        assert!(check(json!({
+            "blobScope": scope,
+            "outputs": [{ "name": "restic", "secretName": "restic-sas", "outputFormat": "restic" }],
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::cleanup::{owned_by, secret_owner};
+use crate::crd::{ContextData, SasGenerator, SecretTarget};
+use crate::reconcile::ReconcileError;
+use crate::secret::ensure_secret;
+use crate::utils::token_hash;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{ListParams, ObjectMeta};
+use kube::{Api, ResourceExt};
+use std::collections::BTreeMap;
+use tracing::{debug, info, instrument, warn};
+
+/// Label linking a versioned Secret to the pointer Secret it belongs to
+pub const VERSIONED_FROM_LABEL: &str = "sas.azure.com/versioned-from";
+
+/// Key of the pointer Secret naming the current versioned Secret
+pub const POINTER_KEY: &str = "secretName";
+
+/// Length of the content hash appended to versioned Secret names
+pub const VERSION_HASH_LEN: usize = 10;
+
+/// Versioned Secrets kept per pointer: the current one and its predecessor, which pods
+/// started before the rotation may still mount
+const KEPT_VERSIONS: usize = 2;
+
+/// `<base>-<hash>` of the Secret type and content, so identical Secrets share a name
+fn versioned_name(base: &str, secret_type: &str, data: &BTreeMap<String, String>) -> String {
+    let content = format!(
+        "{secret_type}\n{}",
+        serde_json::to_string(data).unwrap_or_default()
+    );
+    let hash = token_hash(&content);
+    format!("{base}-{}", &hash[..VERSION_HASH_LEN])
+}
+
+/// Writes `data` into an immutable `<base>-<hash>` Secret, points the `target` Secret at it
+/// and prunes versions older than the previous one. Returns the pointer's resourceVersion.
+#[instrument(skip_all, fields(cr_name = %sasgen.name_any(), secret = %target.name))]
+pub async fn ensure_versioned_secret(
+    sasgen: &SasGenerator,
+    ctx: &ContextData,
+    target: &SecretTarget,
+    data: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+) -> Result<Option<String>, ReconcileError> {
+    let api: Api<Secret> = Api::namespaced(ctx.client.clone(), &target.namespace);
+    let name = versioned_name(&target.name, sasgen.secret_type(), &data);
+
+    if let Some(existing) = api.get_opt(&name).await? {
+        if !owned_by(sasgen, &existing) {
+            return Err(ReconcileError::Policy(format!(
+                "Secret {}/{name} exists and was not written by this SasGenerator",
+                target.namespace
+            )));
+        }
+        debug!(%name, "Versioned Secret already exists");
+    } else {
+        let mut version_annotations = annotations.clone();
+        let owner_references = secret_owner(sasgen, &target.namespace, &mut version_annotations);
+        let mut version_labels = labels.clone();
+        version_labels.insert(VERSIONED_FROM_LABEL.into(), target.name.clone());
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(target.namespace.clone()),
+                labels: Some(version_labels),
+                annotations: Some(version_annotations),
+                owner_references,
+                ..Default::default()
+            },
+            string_data: Some(data),
+            type_: Some(sasgen.secret_type().to_string()),
+            immutable: Some(true),
+            ..Default::default()
+        };
+        api.create(&Default::default(), &secret).await?;
+        info!(%name, "Created immutable versioned Secret");
+    }
+
+    let pointer = BTreeMap::from([(POINTER_KEY.to_string(), name.clone())]);
+    let written = ensure_secret(sasgen, ctx, target, pointer, labels, annotations).await?;
+
+    // Pruning is housekeeping; a failure must not fail the rollout
+    if let Err(e) = prune_versions(sasgen, &api, &target.name, &name).await {
+        warn!(error = %e, "Failed to prune old versioned Secrets");
+    }
+    Ok(written)
+}
+
+/// Deletes this CR's versions of `base` except `current` and the newest other one
+async fn prune_versions(
+    sasgen: &SasGenerator,
+    api: &Api<Secret>,
+    base: &str,
+    current: &str,
+) -> Result<(), kube::Error> {
+    let mut versions = api
+        .list(&ListParams::default().labels(&format!("{VERSIONED_FROM_LABEL}={base}")))
+        .await?
+        .items;
+    versions.retain(|s| owned_by(sasgen, s));
+    versions.sort_by_key(|s| std::cmp::Reverse(s.creation_timestamp()));
+
+    let stale = versions
+        .iter()
+        .map(|s| s.name_any())
+        .filter(|name| name != current)
+        .skip(KEPT_VERSIONS - 1);
+    for name in stale {
+        info!(%name, "Deleting superseded versioned Secret");
+        api.delete(&name, &Default::default()).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Detects a controller stream that stopped yielding, e.g. after an API server hiccup left
+/// the watch hanging. Beats come from CR watch events, relists included, and from reconcile
+/// results. Only CRs whose last reconcile requeued are expected to come back, within the
+/// requeue ceiling that `Config::requeue_ceiling_seconds` keeps below the window; CRs left
+/// to other instances, broken specs and failing CRs backing off may stay silent.
+#[derive(Debug)]
+pub struct Watchdog {
+    /// Silence tolerated before liveness fails; `None` disables the watchdog
+    window: Option<Duration>,
+    last_event: Mutex<Instant>,
+    /// `namespace/name` of the CRs due for another reconcile
+    scheduled: Mutex<HashSet<String>>,
+}
+
+impl Watchdog {
+    pub fn new(window_seconds: u64) -> Self {
+        Self {
+            window: (window_seconds > 0).then(|| Duration::from_secs(window_seconds)),
+            last_event: Mutex::new(Instant::now()),
+            scheduled: Mutex::default(),
+        }
+    }
+
+    /// Records that the watch or the controller stream yielded
+    pub fn beat(&self) {
+        *self.last_event.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    /// Records the outcome of a reconcile: whether the CR was requeued
+    pub fn reconciled(&self, key: String, requeued: bool) {
+        self.beat();
+        let mut scheduled = self.scheduled.lock().unwrap_or_else(|e| e.into_inner());
+        if requeued {
+            scheduled.insert(key);
+        } else {
+            scheduled.remove(&key);
+        }
+    }
+
+    /// Time since the last event once it exceeds the window. With no CR requeued nothing
+    /// has to happen, so silence is expected and never reported.
+    pub fn stalled(&self) -> Option<Duration> {
+        let silence = self
+            .last_event
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed();
+        let expecting = !self
+            .scheduled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_empty();
+        (expecting && self.window.is_some_and(|window| silence > window)).then_some(silence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn silent(window_seconds: u64) -> Watchdog {
+        let watchdog = Watchdog::new(window_seconds);
+        *watchdog.last_event.lock().unwrap() = Instant::now() - Duration::from_secs(120);
+        watchdog
+    }
+
+    #[test]
+    fn silence_is_a_stall_only_while_crs_are_requeued() {
+        let watchdog = silent(60);
+        assert!(watchdog.stalled().is_none());
+
+        watchdog
+            .scheduled
+            .lock()
+            .unwrap()
+            .insert("apps/logs".into());
+        assert!(watchdog.stalled().is_some());
+
+        watchdog.reconciled("apps/logs".into(), false);
+        assert!(watchdog.stalled().is_none());
+    }
+
+    #[test]
+    fn reconciles_and_watch_events_reset_the_silence() {
+        let watchdog = silent(60);
+        watchdog.reconciled("apps/logs".into(), true);
+        assert!(watchdog.stalled().is_none());
+
+        *watchdog.last_event.lock().unwrap() = Instant::now() - Duration::from_secs(120);
+        assert!(watchdog.stalled().is_some());
+        watchdog.beat();
+        assert!(watchdog.stalled().is_none());
+    }
+
+    #[test]
+    fn zero_window_disables_the_watchdog() {
+        let watchdog = silent(0);
+        watchdog
+            .scheduled
+            .lock()
+            .unwrap()
+            .insert("apps/logs".into());
+        assert!(watchdog.stalled().is_none());
+    }
+}
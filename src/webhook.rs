@@ -0,0 +1,360 @@
+use crate::admin::{read_until, write_response, Response};
+use crate::cleanup::owner_key;
+use crate::config::Config;
+use crate::conversion;
+use crate::crd::SasGenerator;
+use crate::validate::validate_spec;
+use crate::webhookcert;
+use kube::api::{Api, ListParams};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use kube::core::conversion::{ConversionRequest, ConversionResponse, ConversionReview};
+use kube::core::Status;
+use kube::Client;
+use native_tls::Identity;
+use openssl::pkey::PKey;
+use serde_json::{json, Value};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_native_tls::TlsAcceptor;
+use tracing::{debug, info, instrument, warn};
+
+/// AdmissionReviews larger than this are rejected; one for a SasGenerator is far smaller
+const MAX_REVIEW_BYTES: usize = 1024 * 1024;
+
+/// Time the API server gets to send its review
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a self-signed certificate is checked for rotation
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+struct WebhookState {
+    client: Client,
+    config: Config,
+}
+
+/// Serves the admission webhooks over HTTPS, run with `--webhook` next to the controller:
+/// `/validate` rejects invalid SasGenerators before they are stored, `/mutate` fills in
+/// defaults when they are created and `/convert` translates between API versions.
+/// The serving certificate is read from `tls.crt` and `tls.key` in `WEBHOOK_CERT_DIR`; with
+/// `WEBHOOK_SELF_SIGNED_CERT` the server issues and rotates it itself.
+pub async fn serve(config: Config, client: Client) -> Result<(), Box<dyn std::error::Error>> {
+    if config.webhook_self_signed_cert {
+        webhookcert::ensure(&client, &config).await?;
+    }
+    let acceptor = Arc::new(RwLock::new(tls_acceptor(&config.webhook_cert_dir)?));
+    if config.webhook_self_signed_cert {
+        tokio::spawn(rotate_certificate(
+            client.clone(),
+            config.clone(),
+            acceptor.clone(),
+        ));
+    }
+    let listener = TcpListener::bind(&config.webhook_address).await?;
+    info!(address = %config.webhook_address, "Webhook server listening");
+    let state = Arc::new(WebhookState { client, config });
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &acceptor, &state).await {
+                debug!(%peer, %e, "Webhook request failed");
+            }
+        });
+    }
+}
+
+/// Re-checks the self-signed certificate and swaps in a rotated one for new connections
+async fn rotate_certificate(client: Client, config: Config, acceptor: Arc<RwLock<TlsAcceptor>>) {
+    loop {
+        tokio::time::sleep(CERT_CHECK_INTERVAL).await;
+        let reloaded = match webhookcert::ensure(&client, &config).await {
+            Ok(true) => tls_acceptor(&config.webhook_cert_dir),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        match reloaded {
+            Ok(reloaded) => {
+                *acceptor.write().unwrap_or_else(|e| e.into_inner()) = reloaded;
+                info!("Webhook server now serves the rotated certificate");
+            }
+            Err(e) => warn!(%e, "Failed to rotate the webhook serving certificate"),
+        }
+    }
+}
+
+fn tls_acceptor(dir: &str) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let dir = Path::new(dir);
+    let cert = std::fs::read(dir.join("tls.crt"))?;
+    let key = PKey::private_key_from_pem(&std::fs::read(dir.join("tls.key"))?)?;
+    // native-tls only takes PKCS#8 keys, while cert-manager issues PKCS#1 RSA keys by default
+    let identity = Identity::from_pkcs8(&cert, &key.private_key_to_pem_pkcs8()?)?;
+    Ok(TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?))
+}
+
+#[instrument(skip_all)]
+async fn handle(stream: TcpStream, acceptor: &TlsAcceptor, state: &WebhookState) -> io::Result<()> {
+    let mut stream = acceptor.accept(stream).await.map_err(io::Error::other)?;
+    let (head, body) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request not received in time"))??;
+
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("POST"), Some(path)) => route(path, &body, state).await,
+        _ => Response::text("405 Method Not Allowed", "only POST is supported\n"),
+    };
+    debug!(status = response.status, "Webhook request served");
+    write_response(&mut stream, response).await
+}
+
+/// Reads the request line and headers, then a body of `Content-Length` bytes
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(String, Vec<u8>)> {
+    let buf = read_until(stream, MAX_REVIEW_BYTES, |buf| {
+        header_end(buf).is_some_and(|end| buf.len() >= end + content_length(&buf[..end]))
+    })
+    .await?;
+    let end = header_end(&buf)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "incomplete request"))?;
+    Ok((
+        String::from_utf8_lossy(&buf[..end]).into_owned(),
+        buf[end..].to_vec(),
+    ))
+}
+
+fn header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+fn content_length(head: &[u8]) -> usize {
+    String::from_utf8_lossy(head)
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().ok())?
+        })
+        .unwrap_or(0)
+}
+
+async fn route(path: &str, body: &[u8], state: &WebhookState) -> Response {
+    let path = path.split('?').next().unwrap_or_default();
+    if path == "/convert" {
+        return convert(body);
+    }
+    let review: AdmissionReview<SasGenerator> = match serde_json::from_slice(body) {
+        Ok(review) => review,
+        Err(e) => {
+            return Response::text("400 Bad Request", format!("invalid AdmissionReview: {e}\n"))
+        }
+    };
+    let request: AdmissionRequest<SasGenerator> = match review.try_into() {
+        Ok(request) => request,
+        Err(e) => return Response::text("400 Bad Request", format!("{e}\n")),
+    };
+    let response = match path {
+        "/validate" => validate(&request, state).await,
+        "/mutate" => mutate(&request, &state.config),
+        _ => return Response::text("404 Not Found", "not found\n"),
+    };
+    Response {
+        status: "200 OK",
+        content_type: "application/json",
+        body: serde_json::to_string(&response.into_review()).unwrap_or_default(),
+    }
+}
+
+/// Converts SasGenerators between v1alpha1 and v1beta1; the whole review fails if any object
+/// cannot be converted, as the API server requires
+fn convert(body: &[u8]) -> Response {
+    let request = match serde_json::from_slice::<ConversionReview>(body)
+        .map_err(|e| e.to_string())
+        .and_then(|review| ConversionRequest::from_review(review).map_err(|e| e.to_string()))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            return Response::text(
+                "400 Bad Request",
+                format!("invalid ConversionReview: {e}\n"),
+            )
+        }
+    };
+    let desired = request.desired_api_version.clone();
+    let converted: Result<Vec<_>, _> = request
+        .objects
+        .iter()
+        .cloned()
+        .map(|object| conversion::convert(object, &desired))
+        .collect();
+    let response = ConversionResponse::for_request(request);
+    let response = match converted {
+        Ok(objects) => response.success(objects),
+        Err(e) => {
+            warn!(%e, %desired, "SasGenerator conversion failed");
+            response.failure(Status::failure(&e, "ConversionFailed"))
+        }
+    };
+    Response {
+        status: "200 OK",
+        content_type: "application/json",
+        body: serde_json::to_string(&response.into_review()).unwrap_or_default(),
+    }
+}
+
+/// Admits the SasGenerator unless its spec is invalid or it would write a Secret that another
+/// SasGenerator already writes
+#[instrument(skip_all, fields(name = %request.name, namespace = ?request.namespace, operation = ?request.operation))]
+async fn validate(
+    request: &AdmissionRequest<SasGenerator>,
+    state: &WebhookState,
+) -> AdmissionResponse {
+    let response = AdmissionResponse::from(request);
+    let Some(mut sasgen) = request.object.clone() else {
+        return response;
+    };
+    // Objects being created may not carry their namespace yet
+    if sasgen.metadata.namespace.is_none() {
+        sasgen.metadata.namespace = request.namespace.clone();
+    }
+
+    let config = &state.config;
+    let ttl_hours = sasgen
+        .spec
+        .sas_ttl_hours
+        .unwrap_or(config.default_ttl_hours());
+    let renewal_hours = sasgen
+        .spec
+        .sas_renewal_hours
+        .unwrap_or(config.default_renewal_hours());
+    if let Err(e) = validate_spec(&sasgen, ttl_hours, renewal_hours) {
+        info!(%e, "Rejecting invalid SasGenerator");
+        return response.deny(e.to_string());
+    }
+
+    match conflicting_secret(&sasgen, &state.client).await {
+        Ok(None) => response,
+        Ok(Some(conflict)) => {
+            info!(%conflict, "Rejecting SasGenerator with a conflicting Secret");
+            response.deny(conflict)
+        }
+        Err(e) => {
+            warn!(%e, "Could not compare Secret names with other SasGenerators; admitting");
+            response
+        }
+    }
+}
+
+/// Writes the operator defaults for `secretName`, `sasTtlHours` and `sasRenewalHours` into
+/// new SasGenerators, so the stored spec is explicit and GitOps diffs stay stable when the
+/// operator defaults change
+#[instrument(skip_all, fields(name = %request.name, namespace = ?request.namespace))]
+fn mutate(request: &AdmissionRequest<SasGenerator>, config: &Config) -> AdmissionResponse {
+    let response = AdmissionResponse::from(request);
+    let Some(sasgen) = request
+        .object
+        .as_ref()
+        .filter(|_| request.operation == Operation::Create)
+    else {
+        return response;
+    };
+
+    let spec = &sasgen.spec;
+    let defaults = [
+        (
+            "secretName",
+            spec.secret_name
+                .is_none()
+                .then(|| sasgen.default_secret_name().map(Value::from))
+                .flatten(),
+        ),
+        (
+            "sasTtlHours",
+            spec.sas_ttl_hours
+                .is_none()
+                .then(|| config.default_ttl_hours().into()),
+        ),
+        (
+            "sasRenewalHours",
+            spec.sas_renewal_hours
+                .is_none()
+                .then(|| config.default_renewal_hours().into()),
+        ),
+    ];
+    let operations: Vec<Value> = defaults
+        .into_iter()
+        .filter_map(|(field, value)| {
+            Some(json!({ "op": "add", "path": format!("/spec/{field}"), "value": value? }))
+        })
+        .collect();
+    if operations.is_empty() {
+        return response;
+    }
+    debug!(?operations, "Defaulting SasGenerator");
+    let patch = match serde_json::from_value::<json_patch::Patch>(Value::Array(operations)) {
+        Ok(patch) => patch,
+        Err(e) => return AdmissionResponse::invalid(e),
+    };
+    match response.with_patch(patch) {
+        Ok(response) => response,
+        Err(e) => AdmissionResponse::invalid(e),
+    }
+}
+
+/// The first Secret of `sasgen` that another SasGenerator already writes, as a denial message
+async fn conflicting_secret(
+    sasgen: &SasGenerator,
+    client: &Client,
+) -> Result<Option<String>, kube::Error> {
+    let ours = static_secrets(sasgen);
+    if ours.is_empty() {
+        return Ok(None);
+    }
+    let others = Api::<SasGenerator>::all(client.clone())
+        .list(&ListParams::default())
+        .await?;
+    for other in others
+        .items
+        .iter()
+        .filter(|other| owner_key(other) != owner_key(sasgen))
+    {
+        if let Some((namespace, name)) = static_secrets(other)
+            .into_iter()
+            .find(|secret| ours.contains(secret))
+        {
+            return Ok(Some(format!(
+                "Secret {namespace}/{name} is already written by SasGenerator {}",
+                owner_key(other)
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// `(namespace, name)` of the Secrets a CR writes, as far as its spec tells; Secrets of
+/// selected containers or namespaces are only known at reconcile time
+fn static_secrets(sasgen: &SasGenerator) -> Vec<(String, String)> {
+    let spec = &sasgen.spec;
+    if spec.container_selector.is_some() || spec.target_namespace_selector.is_some() {
+        return Vec::new();
+    }
+    let namespaces = sasgen.target_namespaces();
+    let outputs = namespaces.iter().flat_map(|namespace| {
+        spec.outputs
+            .iter()
+            .flatten()
+            .map(|output| (namespace.clone(), output.secret_name.clone()))
+    });
+    sasgen
+        .secret_targets(&sasgen.container_names(), &namespaces)
+        .into_iter()
+        .map(|target| (target.namespace, target.name))
+        .chain(outputs)
+        .collect()
+}
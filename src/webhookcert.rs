@@ -0,0 +1,244 @@
+use crate::config::Config;
+use crate::conversion::{WEBHOOK_SERVICE_NAME, WEBHOOK_SERVICE_NAMESPACE};
+use k8s_openapi::api::admissionregistration::v1::{
+    MutatingWebhookConfiguration, ValidatingWebhookConfiguration,
+};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::ByteString;
+use kube::api::{Api, ObjectMeta, PostParams};
+use kube::{Client, CustomResourceExt};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::x509::extension::{
+    BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{info, instrument, warn};
+
+/// Secret shared by the webhook replicas, so they serve one certificate and agree on the
+/// CA bundle
+pub const CERT_SECRET_NAME: &str = "sas-operator-webhook-cert";
+
+const CERT_VALIDITY_DAYS: u32 = 365;
+/// Certificates are replaced once they expire within this many days
+const RENEW_BEFORE_DAYS: u32 = 30;
+
+/// Makes sure a valid self-signed serving certificate exists (`WEBHOOK_SELF_SIGNED_CERT`),
+/// writes it to `WEBHOOK_CERT_DIR` and patches its CA bundle into the webhook
+/// configurations and the conversion webhook of the CRD. The previous certificate stays in
+/// the bundle after a rotation, so replicas still serving it keep being trusted.
+/// Returns whether the certificate on disk changed.
+#[instrument(skip_all)]
+pub async fn ensure(client: &Client, config: &Config) -> Result<bool, Box<dyn std::error::Error>> {
+    let secrets = Api::<Secret>::namespaced(client.clone(), WEBHOOK_SERVICE_NAMESPACE);
+    let existing = secrets.get_opt(CERT_SECRET_NAME).await?;
+    let current = existing.as_ref().and_then(|s| {
+        Some((
+            secret_value(s, "tls.crt")?,
+            secret_value(s, "tls.key")?,
+            secret_value(s, "ca.crt")?,
+        ))
+    });
+
+    let (cert, key, bundle) = match current {
+        Some(current) if !expiring(&current.0)? => current,
+        _ => {
+            let (cert, key) = generate()?;
+            // Still-valid predecessors stay trusted until every replica has reloaded
+            let mut bundle = cert.clone();
+            if let Some((previous, _, _)) = current.filter(|c| !expired(&c.0).unwrap_or(true)) {
+                bundle.extend_from_slice(&previous);
+            }
+            let secret = Secret {
+                metadata: ObjectMeta {
+                    name: Some(CERT_SECRET_NAME.into()),
+                    resource_version: existing.and_then(|s| s.metadata.resource_version),
+                    ..Default::default()
+                },
+                type_: Some("kubernetes.io/tls".into()),
+                data: Some(BTreeMap::from([
+                    ("tls.crt".into(), ByteString(cert.clone())),
+                    ("tls.key".into(), ByteString(key.clone())),
+                    ("ca.crt".into(), ByteString(bundle.clone())),
+                ])),
+                ..Default::default()
+            };
+            // A conflict means another replica rotated first; its certificate is used next time
+            match secret.metadata.resource_version {
+                Some(_) => secrets
+                    .replace(CERT_SECRET_NAME, &PostParams::default(), &secret)
+                    .await
+                    .map(drop)?,
+                None => secrets
+                    .create(&PostParams::default(), &secret)
+                    .await
+                    .map(drop)?,
+            }
+            info!(
+                secret = CERT_SECRET_NAME,
+                "Issued a new webhook serving certificate"
+            );
+            (cert, key, bundle)
+        }
+    };
+
+    patch_ca_bundle(client, config, &ByteString(bundle)).await?;
+    write_if_changed(&config.webhook_cert_dir, &cert, &key)
+}
+
+fn secret_value(secret: &Secret, key: &str) -> Option<Vec<u8>> {
+    secret.data.as_ref()?.get(key).map(|v| v.0.clone())
+}
+
+fn expiring(cert: &[u8]) -> Result<bool, openssl::error::ErrorStack> {
+    expires_before(cert, RENEW_BEFORE_DAYS)
+}
+
+fn expired(cert: &[u8]) -> Result<bool, openssl::error::ErrorStack> {
+    expires_before(cert, 0)
+}
+
+fn expires_before(cert: &[u8], days: u32) -> Result<bool, openssl::error::ErrorStack> {
+    let threshold = Asn1Time::days_from_now(days)?;
+    Ok(X509::from_pem(cert)?.not_after().compare(&threshold)? == Ordering::Less)
+}
+
+/// Self-signed certificate for the names the API server uses to reach the webhook Service
+fn generate() -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+    let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+    let service = format!("{WEBHOOK_SERVICE_NAME}.{WEBHOOK_SERVICE_NAMESPACE}.svc");
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, &service)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    let (not_before, not_after) = (
+        Asn1Time::days_from_now(0)?,
+        Asn1Time::days_from_now(CERT_VALIDITY_DAYS)?,
+    );
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    let san = SubjectAlternativeName::new()
+        .dns(WEBHOOK_SERVICE_NAME)
+        .dns(&format!(
+            "{WEBHOOK_SERVICE_NAME}.{WEBHOOK_SERVICE_NAMESPACE}"
+        ))
+        .dns(&service)
+        .dns(&format!("{service}.cluster.local"))
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    // The certificate is its own CA in the bundle
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .digital_signature()
+            .key_encipherment()
+            .key_cert_sign()
+            .build()?,
+    )?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
+
+/// Sets the CA bundle on every webhook of `WEBHOOK_CONFIGURATION_NAME` and on the CRD's
+/// conversion webhook; objects that are missing are left for `manifests` to create
+async fn patch_ca_bundle(
+    client: &Client,
+    config: &Config,
+    bundle: &ByteString,
+) -> Result<(), kube::Error> {
+    let name = &config.webhook_configuration_name;
+
+    let api = Api::<ValidatingWebhookConfiguration>::all(client.clone());
+    if let Some(mut configuration) = api.get_opt(name).await? {
+        let mut changed = false;
+        for webhook in configuration.webhooks.iter_mut().flatten() {
+            changed |= set_bundle(&mut webhook.client_config.ca_bundle, bundle);
+        }
+        if changed {
+            api.replace(name, &PostParams::default(), &configuration)
+                .await?;
+            info!(%name, "Patched the CA bundle of the validating webhooks");
+        }
+    } else {
+        warn!(%name, "ValidatingWebhookConfiguration not found; SasGenerators are not validated");
+    }
+
+    let api = Api::<MutatingWebhookConfiguration>::all(client.clone());
+    if let Some(mut configuration) = api.get_opt(name).await? {
+        let mut changed = false;
+        for webhook in configuration.webhooks.iter_mut().flatten() {
+            changed |= set_bundle(&mut webhook.client_config.ca_bundle, bundle);
+        }
+        if changed {
+            api.replace(name, &PostParams::default(), &configuration)
+                .await?;
+            info!(%name, "Patched the CA bundle of the mutating webhooks");
+        }
+    } else {
+        warn!(%name, "MutatingWebhookConfiguration not found; SasGenerators are not defaulted");
+    }
+
+    let api = Api::<CustomResourceDefinition>::all(client.clone());
+    let crd_name = crate::crd::SasGenerator::crd_name();
+    if let Some(mut crd) = api.get_opt(crd_name).await? {
+        let client_config = crd
+            .spec
+            .conversion
+            .as_mut()
+            .and_then(|c| c.webhook.as_mut())
+            .and_then(|w| w.client_config.as_mut());
+        if let Some(client_config) = client_config {
+            if set_bundle(&mut client_config.ca_bundle, bundle) {
+                api.replace(crd_name, &PostParams::default(), &crd).await?;
+                info!(
+                    crd = crd_name,
+                    "Patched the CA bundle of the conversion webhook"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_bundle(target: &mut Option<ByteString>, bundle: &ByteString) -> bool {
+    if target.as_ref() == Some(bundle) {
+        return false;
+    }
+    *target = Some(bundle.clone());
+    true
+}
+
+fn write_if_changed(
+    dir: &str,
+    cert: &[u8],
+    key: &[u8],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let dir = Path::new(dir);
+    if std::fs::read(dir.join("tls.crt")).is_ok_and(|on_disk| on_disk == cert) {
+        return Ok(false);
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("tls.key"), key)?;
+    std::fs::write(dir.join("tls.crt"), cert)?;
+    Ok(true)
+}